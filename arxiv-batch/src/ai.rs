@@ -1,190 +1,1155 @@
 use crate::common::{Paper, Summary};
-use crate::utils::s;
+use crate::cost::{Budget, PriceTable, TokenUsage};
+use crate::faithfulness::FaithfulnessChecker;
+use crate::llm::{CancelToken, ChatRequest, ChatResponse, LlmError, LlmProvider};
+use crate::prompt::{OutputLanguage, PromptTemplate};
+use crate::reference::ReferenceRanker;
+use crate::schema::SummarySchema;
+use crate::section::SectionRole;
+use crate::serializer::{serialize_notes, serialize_related, PaperFormat};
+use crate::summary_cache::{content_hash, hash, CachedSummary, SummaryCache};
+use crate::tokens::{
+    context_window, count_message_tokens, count_tokens, truncate_tokens, window_prompt_budget,
+};
 use anyhow::Result;
+use dotenvy::dotenv;
 use openai_tools::json_schema::JsonSchema;
-use openai_tools::{Message, OpenAI, ResponseFormat};
-use std::include_str;
-use std::thread::sleep;
+use openai_tools::{Message, ResponseFormat};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Tokens reserved for the JSON schema and the formatting of the request.
+const MESSAGE_OVERHEAD: usize = 1_024;
+
+/// Upper bound of the wait between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A model of the fallback chain with its context window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelSpec {
+    pub model_id: String,
+    pub context_window: usize,
+}
+
+impl ModelSpec {
+    /// "gpt-4o-mini", or "my-model=32000" to set the context window of the model.
+    pub fn parse(text: &str) -> Result<ModelSpec> {
+        let (model_id, window) = match text.split_once('=') {
+            Some((model_id, window)) => (model_id.trim(), Some(window.trim())),
+            None => (text.trim(), None),
+        };
+        if model_id.is_empty() {
+            return Err(anyhow::anyhow!("Empty model ID: {}", text));
+        }
+        let context_window = match window {
+            Some(window) => window
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid context window: {}", text))?,
+            None => context_window(model_id),
+        };
+        return Ok(ModelSpec {
+            model_id: model_id.to_string(),
+            context_window,
+        });
+    }
+
+    /// Models in the order they are tried: "gpt-4o-mini,gpt-4o,my-model=32000"
+    pub fn parse_list(text: &str) -> Result<Vec<ModelSpec>> {
+        let models = text
+            .split(',')
+            .filter(|x| !x.trim().is_empty())
+            .map(ModelSpec::parse)
+            .collect::<Result<Vec<ModelSpec>>>()?;
+        if models.is_empty() {
+            return Err(anyhow::anyhow!("No model ID is given"));
+        }
+        return Ok(models);
+    }
+
+    pub fn prompt_budget(&self) -> usize {
+        return window_prompt_budget(self.context_window);
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct AI {
-    model_id: String,
+    /// The model used first, followed by the fallbacks for summaries
+    models: Vec<ModelSpec>,
+    section_roles: Vec<SectionRole>,
+    paper_format: PaperFormat,
+    language: OutputLanguage,
+    schema: SummarySchema,
+    /// Selects the references included in the prompt
+    references: ReferenceRanker,
+    provider: Arc<dyn LlmProvider>,
+    prompt: PromptTemplate,
+    /// Repair requests sent for a response that does not match the schema
+    repair_attempts: usize,
+    /// Accept a summary with missing fields when the repairs fail
+    allow_partial: bool,
+    /// Timeout of a request to the LLM
+    timeout: Duration,
+    cancel: CancelToken,
+    prices: PriceTable,
+    run_usage: Arc<Mutex<TokenUsage>>,
+    summary_cache: Option<SummaryCache>,
+    force_resummarize: bool,
+    /// Checks the summaries against the papers
+    faithfulness: Option<FaithfulnessChecker>,
 }
 
 impl AI {
-    pub fn new(model_id: &str) -> AI {
-        AI {
-            model_id: String::from(model_id),
-        }
+    /// `model_id` is a model or a comma-separated fallback chain (see `ModelSpec::parse_list`).
+    /// Fails when the model IDs, the LLM provider, the summary schema or the prompt template
+    /// are not valid.
+    pub fn new(model_id: &str) -> Result<AI> {
+        dotenv().ok();
+        // sections to be summarized: "introduction,method,experiments,results,conclusion"
+        let section_roles = match std::env::var("SUMMARY_SECTIONS") {
+            Ok(roles) if !roles.trim().is_empty() => SectionRole::parse_list(&roles),
+            _ => SectionRole::all(),
+        };
+        let language = OutputLanguage::from_env();
+        let repair_attempts = std::env::var("SUMMARY_REPAIR_ATTEMPTS")
+            .ok()
+            .and_then(|x| x.trim().parse().ok())
+            .unwrap_or(2);
+        let allow_partial = std::env::var("ALLOW_PARTIAL_SUMMARY")
+            .map(|x| matches!(x.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+            .unwrap_or(false);
+        let timeout = std::env::var("LLM_TIMEOUT")
+            .ok()
+            .and_then(|x| x.trim().parse().ok())
+            .unwrap_or(300);
+        let models = ModelSpec::parse_list(model_id)
+            .map_err(|e| anyhow::anyhow!("Invalid model ID: {}", e))?;
+        let schema = SummarySchema::from_env()
+            .map_err(|e| anyhow::anyhow!("Failed to load the summary schema: {}", e))?;
+        let provider = crate::llm::from_env()
+            .map_err(|e| anyhow::anyhow!("Failed to configure the LLM provider: {}", e))?;
+        let prompt = PromptTemplate::from_env(language)
+            .map_err(|e| anyhow::anyhow!("Failed to load the prompt template: {}", e))?;
+        return Ok(AI {
+            models,
+            section_roles,
+            paper_format: PaperFormat::from_env(),
+            language,
+            schema,
+            references: ReferenceRanker::new(),
+            provider,
+            prompt,
+            repair_attempts,
+            allow_partial,
+            timeout: Duration::from_secs(timeout),
+            cancel: CancelToken::new(),
+            prices: PriceTable::new(),
+            run_usage: Arc::new(Mutex::new(TokenUsage::default())),
+            summary_cache: None,
+            force_resummarize: false,
+            faithfulness: None,
+        });
+    }
+
+    pub fn section_roles(&mut self, section_roles: Vec<SectionRole>) -> &mut Self {
+        self.section_roles = section_roles;
+        return self;
+    }
+
+    pub fn paper_format(&mut self, paper_format: PaperFormat) -> &mut Self {
+        self.paper_format = paper_format;
+        return self;
+    }
+
+    /// Set the output language and load its prompt template.
+    pub fn language(&mut self, language: OutputLanguage) -> Result<&mut Self> {
+        self.prompt = PromptTemplate::from_env(language)?;
+        self.language = language;
+        return Ok(self);
+    }
+
+    pub fn schema(&mut self, schema: SummarySchema) -> &mut Self {
+        self.schema = schema;
+        return self;
+    }
+
+    pub fn reference_ranker(&mut self, references: ReferenceRanker) -> &mut Self {
+        self.references = references;
+        return self;
+    }
+
+    pub fn repair_attempts(&mut self, repair_attempts: usize) -> &mut Self {
+        self.repair_attempts = repair_attempts;
+        return self;
     }
 
-    async fn get_instruction(&self, paper: &Paper) -> Result<(String, String, String)> {
-        // instruction
-        let instruction = s(include_str!("instructions/instruction_1.txt"));
+    pub fn allow_partial(&mut self, allow_partial: bool) -> &mut Self {
+        self.allow_partial = allow_partial;
+        return self;
+    }
+
+    pub fn summary_cache(&mut self, summary_cache: SummaryCache) -> &mut Self {
+        self.summary_cache = Some(summary_cache);
+        return self;
+    }
+
+    /// Summarize the papers again even if their summaries are cached.
+    pub fn force_resummarize(&mut self, force_resummarize: bool) -> &mut Self {
+        self.force_resummarize = force_resummarize;
+        return self;
+    }
+
+    pub fn faithfulness_checker(&mut self, checker: FaithfulnessChecker) -> &mut Self {
+        self.faithfulness = Some(checker);
+        return self;
+    }
+
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        return self;
+    }
+
+    /// Cancel the requests in flight and the retries when `cancel` is cancelled.
+    pub fn cancel_token(&mut self, cancel: CancelToken) -> &mut Self {
+        self.cancel = cancel;
+        return self;
+    }
+
+    pub fn model_id(&self) -> &str {
+        return &self.model().model_id;
+    }
 
+    /// Tokens available for the prompt of the model.
+    pub fn prompt_budget(&self) -> usize {
+        return self.model().prompt_budget();
+    }
+
+    fn model(&self) -> &ModelSpec {
+        return &self.models[0];
+    }
+
+    /// This AI with the fallback chain starting from the model at `index`.
+    fn with_model(&self, index: usize) -> AI {
+        let mut ai = self.clone();
+        ai.models = self.models[index..].to_vec();
+        return ai;
+    }
+
+    pub fn prompt_template(&self) -> &PromptTemplate {
+        return &self.prompt;
+    }
+
+    pub fn prompt(&mut self, prompt: PromptTemplate) -> &mut Self {
+        self.prompt = prompt;
+        return self;
+    }
+
+    pub fn provider(&mut self, provider: Arc<dyn LlmProvider>) -> &mut Self {
+        self.provider = provider;
+        return self;
+    }
+
+    /// Variables of the prompt template for the sections of `roles`.
+    fn get_variables(&self, paper: &Paper, roles: &[SectionRole]) -> (String, String) {
         assert!(
-            paper.original_text_map.len() > 0,
+            !paper.document.is_empty(),
             "Failed to get instruction: Original text is empty."
         );
+        let paper_xml = paper.serialize_by_roles(roles, self.paper_format);
+        return (paper_xml, self.get_references(paper));
+    }
 
-        // paper
-        let paper_text = paper.original_text2xml();
-        let mut paper_xml = s("############ 論文 ############\n");
-        paper_xml.push_str(&paper_text);
-
-        // reference instruction
-        let references = paper.references2xml();
-        let mut reference_xml = s("############ 参考文献 ############\n");
-        reference_xml.push_str(&references);
-
-        return Ok((instruction, paper_xml, reference_xml));
-    }
-
-    async fn get_messages(&self, paper: &Paper) -> Result<Vec<Message>> {
-        let (instruction, paper_xml, _) = self.get_instruction(paper).await?;
-        let messages = vec![
-            Message::new("system", "あなたは優秀な研究アシスタントです．"),
-            Message::new(
-                "user",
-                &format!(
-                    "これからこの論文の要約の準備をしてください: {}",
-                    paper.title
-                ),
-            ),
-            Message::new(
-                "user",
-                &format!("要約の際は以下の指示に従ってください: \n\n{}", instruction),
-            ),
-            // Message::new(
-            //     "user",
-            //     &format!(
-            //         "以下は，論文の参考文献のリストです．要約のための参考にしてください．必要であれば論文の主張を補うために参照してください．\n\n{}",
-            //         reference_xml
-            //     ),
-            // ),
-            Message::new(
-                "user",
-                &format!("以下は，論文の内容です．\n\n{}", paper_xml),
-            ),
-            Message::new("user", "要約してください:"),
-        ];
-        return Ok(messages);
+    /// The most relevant references of the paper within the token budget of the references.
+    fn get_references(&self, paper: &Paper) -> String {
+        let references = self
+            .references
+            .select(paper, &self.model().model_id, self.paper_format);
+        return serialize_related(&references, "references", "reference", self.paper_format);
     }
 
-    fn get_json_schema(&self) -> JsonSchema {
-        let mut json_schema = JsonSchema::new("summary");
-        json_schema.add_property(
-            "is_survey",
-            "boolean",
-            Option::from(s("この論文がサーベイ論文かどうかをtrue/falseで判定する．")),
-        );
-        json_schema.add_property(
-            "overview",
-            "string",
-            Option::from(s("この論文の概要を3文程度で記述する．")),
-        );
-        json_schema.add_property(
-            "research_question",
-            "string",
-            Option::from(s("この論文のリサーチクエスチョンを説明する．この論文の背景や既存研究との関連も含めて記述する．4文程度で詳細に記述する．")),
-        );
-        json_schema.add_property(
-            "task_category",
-            "string",
-            Option::from(s("この論文のタスク分類を記述する．例として，自然言語処理の場合は機械読解，機械翻訳，テキスト分類などが挙げられる．")),
-        );
-        json_schema.add_property(
-            "domain_as_words",
-            "string",
-            Option::from(s(
-                "この論文の実験が対象にしているドメインを単語で出力する．",
-            )),
-        );
-        json_schema.add_property(
-            "task_as_words",
-            "string",
-            Option::from(s("この論文のタスク分類を単語で出力する．")),
+    fn get_messages(&self, paper: &Paper, roles: &[SectionRole]) -> Vec<Message> {
+        let (paper_xml, references_xml) = self.get_variables(paper, roles);
+        return self.prompt.messages(
+            &self.prompt.summary,
+            &[
+                ("title", &paper.title),
+                ("abstract", &paper.abstract_text),
+                ("paper_xml", &paper_xml),
+                ("references_xml", &references_xml),
+            ],
         );
-        json_schema.add_property(
-            "comparison_with_related_works",
-            "string",
-            Option::from(s("関連研究と比較した場合のこの論文の新規性について説明する．可能な限り既存研究を参照しながら記述すること．4文程度で詳細に記述する．")),
-        );
-        json_schema.add_property(
-            "proposed_method",
-            "string",
-            Option::from(s(
-                "この論文で使用されている手法の詳細について，一つずつ順を追って説明する．4文程度で詳細に記述する．",
-            )),
-        );
-        json_schema.add_property(
-            "datasets",
-            "string",
-            Option::from(s(
-                "この論文で使用されているデータセットをリストアップする．",
-            )),
-        );
-        json_schema.add_property(
-            "experiments",
-            "string",
-            Option::from(s(
-                "実験の設定と結果について詳細に説明する．4文程度で詳細に記述する．",
-            )),
-        );
-        json_schema.add_property(
-            "analysis",
-            "string",
-            Option::from(s(
-                "実験結果の分析について記述する．4文程度で詳細に記述する．",
-            )),
+    }
+
+    fn get_json_schema(&self) -> JsonSchema {
+        return self.schema.json_schema(self.language);
+    }
+
+    /// Messages for a part of a long paper: the key points of the sections at `indices`.
+    fn get_section_messages(
+        &self,
+        paper: &Paper,
+        indices: &[usize],
+        budget: usize,
+    ) -> Vec<Message> {
+        let overhead = count_message_tokens(
+            &self.model().model_id,
+            &self
+                .prompt
+                .messages(&self.prompt.section, &[("title", &paper.title)]),
+        ) + MESSAGE_OVERHEAD;
+        let paper_xml = truncate_tokens(
+            &self.model().model_id,
+            &paper.serialize_by_sections(indices, self.paper_format),
+            budget.saturating_sub(overhead),
         );
-        json_schema.add_property(
-            "contributions",
-            "string",
-            Option::from(s("この論文のコントリビューションをリスト形式で記述する．")),
+        return self.prompt.messages(
+            &self.prompt.section,
+            &[("title", &paper.title), ("paper_xml", &paper_xml)],
         );
-        json_schema.add_property(
-            "future_works",
-            "string",
-            Option::from(s(
-                "未解決の課題および将来の研究の方向性について記述．3文程度で詳細に記述する．",
-            )),
+    }
+
+    /// Messages to merge the key points of each part into the summary.
+    fn get_merge_messages(&self, paper: &Paper, notes: &[String]) -> Vec<Message> {
+        let notes_xml = serialize_notes(paper, notes, self.paper_format);
+        return self.prompt.messages(
+            &self.prompt.merge,
+            &[
+                ("title", &paper.title),
+                ("abstract", &paper.abstract_text),
+                ("notes_xml", &notes_xml),
+                ("references_xml", &self.get_references(paper)),
+            ],
         );
-        return json_schema;
     }
 
+    /// Group the sections of `roles` into chunks of at most `budget` tokens.
+    /// A section larger than the budget becomes a chunk of its own.
+    fn split_sections(
+        &self,
+        paper: &Paper,
+        roles: &[SectionRole],
+        budget: usize,
+    ) -> Vec<Vec<usize>> {
+        let mut chunks: Vec<Vec<usize>> = Vec::new();
+        let mut chunk: Vec<usize> = Vec::new();
+        let mut chunk_tokens = 0;
+        for section in paper.document.filter_by_roles(roles) {
+            let tokens = count_tokens(
+                &self.model().model_id,
+                &paper.serialize_by_sections(&[section.index], self.paper_format),
+            );
+            if !chunk.is_empty() && chunk_tokens + tokens > budget {
+                chunks.push(chunk);
+                chunk = Vec::new();
+                chunk_tokens = 0;
+            }
+            chunk.push(section.index);
+            chunk_tokens += tokens;
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        return chunks;
+    }
+
+    fn fits(&self, messages: &[Message]) -> bool {
+        let tokens = count_message_tokens(&self.model().model_id, messages) + MESSAGE_OVERHEAD;
+        return tokens <= self.prompt_budget();
+    }
+
+    /// Summarize the paper.
+    /// If the paper does not fit in the context window of the model, the appendices and
+    /// references are left out first. If it still does not fit, each part of the paper is
+    /// summarized separately and the results are merged into the summary.
+    ///
+    /// The tokens and the cost of the requests are recorded in `paper.usage`
+    /// and added to the usage of the run.
+    ///
+    /// When the model fails to summarize the paper (the prompt is too long for it, it refuses,
+    /// or the retries and repairs run out), the next model of the fallback chain is tried.
+    /// The model that produced the summary is recorded in `paper.summary.model_id`, and the
+    /// claims not supported by the paper in `paper.summary.unsupported_claims`.
+    ///
+    /// With a summary cache, a cached summary of the same content and prompt by one of the
+    /// models is reused unless `force_resummarize` is set, and new complete summaries are cached.
     pub async fn summarize(&self, paper: &mut Paper) -> Result<()> {
-        let mut messages = self.get_messages(paper).await?;
+        paper.usage = TokenUsage::default();
+        if let Some(summary) = self.cached_summary(paper) {
+            paper.summary = summary;
+            return Ok(());
+        }
+
+        let mut result = Err(anyhow::anyhow!("No model to summarize with"));
+        for index in 0..self.models.len() {
+            let ai = self.with_model(index);
+            result = ai.summarize_paper(paper).await;
+            match result.as_ref() {
+                Ok(_) => {
+                    ai.check_faithfulness(paper).await;
+                    ai.cache_summary(paper);
+                    break;
+                }
+                Err(_) if self.cancel.is_cancelled() => break,
+                Err(e) => {
+                    if let Some(next) = self.models.get(index + 1) {
+                        eprintln!(
+                            "WARNING: {} failed to summarize {}: {}; falling back to {}",
+                            ai.model().model_id,
+                            paper.title,
+                            e,
+                            next.model_id
+                        );
+                    }
+                }
+            }
+        }
+        self.run_usage.lock().unwrap().merge(&paper.usage);
+        return result;
+    }
+
+    /// A cached summary of the paper by one of the models, unless `force_resummarize` is set.
+    fn cached_summary(&self, paper: &Paper) -> Option<Summary> {
+        let cache = self.summary_cache.as_ref()?;
+        if self.force_resummarize {
+            return None;
+        }
+        for index in 0..self.models.len() {
+            match cache.get(&self.with_model(index).cache_key(paper)) {
+                Ok(Some(entry)) => return Some(entry.summary),
+                Ok(None) => {}
+                Err(e) => eprintln!("WARNING: Failed to read the summary cache: {}", e),
+            }
+        }
+        return None;
+    }
+
+    /// Whether `summarize` reuses a cached summary of the paper without a request.
+    pub fn has_cached_summary(&self, paper: &Paper) -> bool {
+        return self.cached_summary(paper).is_some();
+    }
+
+    /// The request of the summary to be sent in a batch (see `crate::batch`) by the first model,
+    /// or `None` when the paper has a cached summary or needs more than one request.
+    pub fn batch_request(&self, paper: &Paper) -> Option<ChatRequest> {
+        if self.cached_summary(paper).is_some() {
+            return None;
+        }
+        let messages = self.single_messages(paper)?;
+        let mut request = ChatRequest::new(&self.model().model_id, messages);
+        request
+            .temperature(1.0)
+            .response_format(ResponseFormat::new("json_schema", self.get_json_schema()));
+        return Some(request);
+    }
+
+    /// Take the response of a batch request as the summary of the paper, charged at the batch
+    /// price. When the request failed or the response does not match the schema, the paper is
+    /// summarized by `summarize` instead, with the repairs and the fallback models, unless
+    /// the run has used up `budget`.
+    pub async fn apply_batch_response(
+        &self,
+        paper: &mut Paper,
+        response: Result<ChatResponse>,
+        budget: &Budget,
+    ) -> Result<()> {
+        paper.usage = TokenUsage::default();
+        let summary = match response {
+            Ok(response) => {
+                let model = if response.model.is_empty() {
+                    self.model().model_id.clone()
+                } else {
+                    response.model.clone()
+                };
+                paper.usage.add_batch(&model, &response.usage, &self.prices);
+                self.parse_summary(&response.content)
+            }
+            Err(e) => Err(e),
+        };
+
+        match summary {
+            Ok(mut summary) => {
+                summary.template_version = self.prompt.version.clone();
+                summary.model_id = self.model().model_id.clone();
+                paper.summary = summary;
+                self.check_faithfulness(paper).await;
+                self.cache_summary(paper);
+                self.run_usage.lock().unwrap().merge(&paper.usage);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!(
+                    "WARNING: The batch failed to summarize {}: {}; summarizing it directly",
+                    paper.title, e
+                );
+                let batch_usage = paper.usage.clone();
+                self.run_usage.lock().unwrap().merge(&batch_usage);
+                if let Some(reason) = budget.exceeded(&self.run_usage()) {
+                    return Err(anyhow::anyhow!(
+                        "The budget is used up ({}): {} is not summarized again",
+                        reason,
+                        paper.title
+                    ));
+                }
+                let result = self.summarize(paper).await;
+                paper.usage.merge(&batch_usage);
+                return result;
+            }
+        }
+    }
+
+    /// Check the summary against the paper and record the unsupported claims.
+    /// If the checker is set to resummarize, a summary with unsupported claims is generated
+    /// once more and the one with fewer unsupported claims is kept.
+    async fn check_faithfulness(&self, paper: &mut Paper) {
+        let checker = match self.faithfulness.as_ref() {
+            Some(checker) => checker,
+            None => return,
+        };
+        let report = checker.check(paper, &self.schema);
+        paper.summary.unsupported_claims = report.claims();
+        if report.passed() || !checker.resummarize {
+            return;
+        }
+
+        eprintln!(
+            "WARNING: Unsupported claims in the summary of {}: {}; summarizing it again",
+            paper.title,
+            report.reason()
+        );
+        let summary = paper.summary.clone();
+        match self.summarize_paper(paper).await {
+            Ok(_) => {
+                let retry = checker.check(paper, &self.schema);
+                if retry.unsupported.len() < report.unsupported.len() {
+                    paper.summary.unsupported_claims = retry.claims();
+                } else {
+                    paper.summary = summary;
+                }
+            }
+            Err(e) => {
+                eprintln!("WARNING: Failed to summarize the paper again: {}", e);
+                paper.summary = summary;
+            }
+        }
+    }
+
+    /// Cache a complete summary of the paper by the first model.
+    fn cache_summary(&self, paper: &Paper) {
+        if let Some(cache) = self.summary_cache.as_ref() {
+            if paper.summary.missing_fields.is_empty() {
+                let key = self.cache_key(paper);
+                let entry = CachedSummary::from_paper(&key, &self.model().model_id, paper);
+                if let Err(e) = cache.put(&entry) {
+                    eprintln!("WARNING: Failed to write the summary cache: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Key of the summary cache: the model, the prompt, the schema and the content of the paper.
+    pub fn cache_key(&self, paper: &Paper) -> String {
+        let fields = self
+            .schema
+            .fields
+            .iter()
+            .map(|field| format!("{}:{}", field.name, field.type_name))
+            .collect::<Vec<String>>()
+            .join(",");
+        return hash(&[
+            &self.model().model_id,
+            &self.prompt.version,
+            self.language.as_str(),
+            &fields,
+            &content_hash(paper),
+        ]);
+    }
+
+    /// Tokens and cost of all the papers summarized so far.
+    pub fn run_usage(&self) -> TokenUsage {
+        return self.run_usage.lock().unwrap().clone();
+    }
+
+    /// Prices used to account the cost of the requests.
+    pub fn prices(&self) -> &PriceTable {
+        return &self.prices;
+    }
+
+    /// Add the usage of requests sent by another `AI`, e.g. the ratings of the triage,
+    /// to the usage of the run.
+    pub fn add_usage(&self, usage: &TokenUsage) {
+        self.run_usage.lock().unwrap().merge(usage);
+    }
+
+    /// The section roles without the appendices and the references.
+    fn main_roles(&self) -> Vec<SectionRole> {
+        return self
+            .section_roles
+            .iter()
+            .filter(|role| !matches!(role, SectionRole::Appendix | SectionRole::References))
+            .cloned()
+            .collect();
+    }
+
+    /// The messages to summarize the paper in one request: the whole paper, or the paper
+    /// without the appendices and the references. `None` if neither fits.
+    fn single_messages(&self, paper: &Paper) -> Option<Vec<Message>> {
+        let messages = self.get_messages(paper, &self.section_roles);
+        if self.fits(&messages) {
+            return Some(messages);
+        }
+        let messages = self.get_messages(paper, &self.main_roles());
+        if self.fits(&messages) {
+            return Some(messages);
+        }
+        return None;
+    }
+
+    async fn summarize_paper(&self, paper: &mut Paper) -> Result<()> {
+        if let Some(messages) = self.single_messages(paper) {
+            return self.request_summary(paper, messages).await;
+        }
+
+        // map: the key points of each part
+        let budget = self.prompt_budget();
+        let mut notes = Vec::new();
+        let roles = self.main_roles();
+        for indices in self.split_sections(paper, &roles, budget / 2) {
+            let messages = self.get_section_messages(paper, &indices, budget);
+            notes.push(self.request_text(messages, &mut paper.usage).await?);
+        }
+
+        // reduce: the summary from the key points
+        let messages = self.get_merge_messages(paper, &notes);
+        return self.request_summary(paper, messages).await;
+    }
+
+    /// Send a request with the timeout and record its usage.
+    /// Fails right away when the token is cancelled.
+    async fn chat(&self, request: &ChatRequest, usage: &mut TokenUsage) -> Result<ChatResponse> {
+        let mut response = self.send(request).await?;
+        // some local servers do not report the usage
+        if response.usage.total_tokens == 0 {
+            response.usage.prompt_tokens =
+                count_message_tokens(&request.model, &request.messages) as u64;
+            response.usage.completion_tokens =
+                count_tokens(&request.model, &response.content) as u64;
+            response.usage.total_tokens =
+                response.usage.prompt_tokens + response.usage.completion_tokens;
+        }
+        let model = if response.model.is_empty() {
+            &request.model
+        } else {
+            &response.model
+        };
+        usage.add(model, &response.usage, &self.prices);
+        return Ok(response);
+    }
+
+    async fn send(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        if self.cancel.is_cancelled() {
+            return Err(anyhow::anyhow!("Cancelled"));
+        }
+        tokio::select! {
+            result = tokio::time::timeout(self.timeout, self.provider.chat(request)) => {
+                return match result {
+                    Ok(response) => response,
+                    Err(_) => Err(anyhow::anyhow!(
+                        "The request timed out after {}s",
+                        self.timeout.as_secs()
+                    )),
+                };
+            }
+            _ = self.cancel.cancelled() => return Err(anyhow::anyhow!("Cancelled")),
+        }
+    }
+
+    /// Wait before the next retry: 1s, 2s, 4s, ... up to `MAX_BACKOFF`.
+    /// Fails when the token is cancelled while waiting.
+    async fn backoff(&self, attempt: u32) -> Result<()> {
+        let wait = Duration::from_secs(1u64 << attempt.min(16)).min(MAX_BACKOFF);
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => return Ok(()),
+            _ = self.cancel.cancelled() => return Err(anyhow::anyhow!("Cancelled")),
+        }
+    }
+
+    /// Request a JSON response following `json_schema` outside of a summary, e.g. to rate a paper.
+    /// The usage is added to the usage of the run.
+    pub async fn request_json(
+        &self,
+        messages: Vec<Message>,
+        json_schema: JsonSchema,
+    ) -> Result<serde_json::Value> {
+        let mut usage = TokenUsage::default();
+        let content = self.request(messages, Some(json_schema), &mut usage).await;
+        self.run_usage.lock().unwrap().merge(&usage);
+        let content = content?;
+        return serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Failed to parse the response as JSON: {}", e));
+    }
+
+    /// Request a text response outside of a summary, e.g. to answer a question about a paper.
+    /// The usage is added to the usage of the run.
+    pub async fn complete(&self, messages: Vec<Message>) -> Result<String> {
+        let mut usage = TokenUsage::default();
+        let content = self.request_text(messages, &mut usage).await;
+        self.run_usage.lock().unwrap().merge(&usage);
+        return content;
+    }
+
+    async fn request_text(&self, messages: Vec<Message>, usage: &mut TokenUsage) -> Result<String> {
+        return self.request(messages, None, usage).await;
+    }
+
+    async fn request(
+        &self,
+        messages: Vec<Message>,
+        json_schema: Option<JsonSchema>,
+        usage: &mut TokenUsage,
+    ) -> Result<String> {
+        let mut retry_count = 5u8;
+        let mut attempt = 0;
+        while retry_count > 0 {
+            let mut request = ChatRequest::new(&self.model().model_id, messages.clone());
+            request.temperature(1.0);
+            if let Some(json_schema) = json_schema.as_ref() {
+                request.response_format(ResponseFormat::new("json_schema", json_schema.clone()));
+            }
+
+            match self.chat(&request, usage).await {
+                Ok(response) => return Ok(response.content),
+                Err(e) => {
+                    if self.cancel.is_cancelled() || e.is::<LlmError>() {
+                        return Err(e);
+                    }
+                    eprintln!("Failed to chat: {} (retry: {})", e, retry_count);
+                    retry_count -= 1;
+                    self.backoff(attempt).await?;
+                    attempt += 1;
+                }
+            }
+        }
+        return Err(anyhow::anyhow!("Failed to get a response from the LLM."));
+    }
+
+    /// Request the summary and check the response against the schema.
+    /// An invalid response is sent back to the model with the problems found, up to
+    /// `repair_attempts` times. If it is still invalid, the valid fields are kept when
+    /// partial summaries are allowed, and the others are recorded as missing.
+    async fn request_summary(&self, paper: &mut Paper, mut messages: Vec<Message>) -> Result<()> {
         let json_schema = self.get_json_schema();
 
         let mut retry_count = 5u8;
+        let mut attempt = 0;
+        let mut repair_count = 0;
         while retry_count > 0 {
-            let mut openai = OpenAI::new();
-            openai
-                .model_id(&self.model_id)
-                .messages(messages.clone())
+            let mut request = ChatRequest::new(&self.model().model_id, messages.clone());
+            request
                 .temperature(1.0)
                 .response_format(ResponseFormat::new("json_schema", json_schema.clone()));
 
-            let response = match openai.chat() {
+            let response = match self.chat(&request, &mut paper.usage).await {
                 Ok(response) => response,
                 Err(e) => {
-                    eprintln!("Failed to chat: {} (retry: {})", e.to_string(), retry_count);
+                    if self.cancel.is_cancelled() || e.is::<LlmError>() {
+                        return Err(e);
+                    }
+                    eprintln!("Failed to chat: {} (retry: {})", e, retry_count);
                     retry_count -= 1;
-                    sleep(std::time::Duration::from_secs(1));
+                    self.backoff(attempt).await?;
+                    attempt += 1;
 
-                    messages.push(Message::new("system", "JSON形式で出力してください．"));
-                    messages.push(Message::new("user", "要約してください．"));
+                    messages.extend(self.prompt.retry_messages());
                     continue;
                 }
             };
-            let summary = response.choices[0].message.content.clone();
-            let sumamry = serde_json::from_str::<Summary>(summary.as_str())?;
-
-            paper.summary = sumamry;
+            let content = response.content;
+            let error = match self.parse_summary(&content) {
+                Ok(mut summary) => {
+                    summary.template_version = self.prompt.version.clone();
+                    summary.model_id = self.model().model_id.clone();
+                    paper.summary = summary;
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
 
-            return Ok(());
+            if repair_count >= self.repair_attempts {
+                if self.allow_partial {
+                    if let Some(mut summary) = self.partial_summary(&content) {
+                        eprintln!(
+                            "WARNING: Accepted a partial summary of {}: missing {}",
+                            paper.title,
+                            summary.missing_fields.join(", ")
+                        );
+                        summary.template_version = self.prompt.version.clone();
+                        summary.model_id = self.model().model_id.clone();
+                        paper.summary = summary;
+                        return Ok(());
+                    }
+                }
+                return Err(anyhow::anyhow!(
+                    "Invalid summary after {} repair attempts: {}",
+                    repair_count,
+                    error
+                ));
+            }
+            repair_count += 1;
+            eprintln!(
+                "WARNING: Invalid summary, asking for a repair ({}/{}): {}",
+                repair_count, self.repair_attempts, error
+            );
+            messages.push(Message::new("assistant", &content));
+            messages.push(self.prompt.repair_message(&error.to_string()));
         }
         return Err(anyhow::anyhow!("Failed to summarize."));
     }
+
+    /// Parse a response that matches the schema.
+    fn parse_summary(&self, content: &str) -> Result<Summary> {
+        let value = serde_json::from_str::<serde_json::Value>(content)
+            .map_err(|e| anyhow::anyhow!("invalid JSON ({})", e))?;
+        let errors = self.schema.validate(&value, self.language);
+        if !errors.is_empty() {
+            return Err(anyhow::anyhow!(
+                "{}",
+                errors
+                    .iter()
+                    .map(|error| error.to_string())
+                    .collect::<Vec<String>>()
+                    .join("; ")
+            ));
+        }
+        return Summary::from_json(content);
+    }
+
+    /// Keep the valid fields of a response that is a JSON object.
+    fn partial_summary(&self, content: &str) -> Option<Summary> {
+        let mut value = serde_json::from_str::<serde_json::Value>(content).ok()?;
+        let errors = self.schema.validate(&value, self.language);
+        let object = value.as_object_mut()?;
+        let mut missing_fields: Vec<String> = Vec::new();
+        for error in errors {
+            object.remove(&error.name);
+            let name = error.name.trim_end_matches("_en").to_string();
+            if !missing_fields.contains(&name) {
+                missing_fields.push(name);
+            }
+        }
+        let mut summary = Summary::from_json(&value.to_string()).ok()?;
+        summary.missing_fields = missing_fields;
+        return Some(summary);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::document::{Document, DocumentSection};
+    use crate::llm::OpenAICompatible;
+    use crate::utils::s;
+
+    #[test]
+    fn test_split_sections() {
+        let mut paper = Paper::default();
+        paper.document = Document {
+            sections: ["1 Introduction", "2 Method", "3 Experiments", "A Proofs"]
+                .iter()
+                .enumerate()
+                .map(|(index, title)| DocumentSection::new(index, title, vec!["word ".repeat(100)]))
+                .collect(),
+            ..Default::default()
+        };
+        let ai = AI::new("gpt-4o-mini").unwrap();
+        let section_tokens = count_tokens(
+            "gpt-4o-mini",
+            &paper.serialize_by_sections(&[0], PaperFormat::Xml),
+        );
+
+        let roles = vec![
+            SectionRole::Introduction,
+            SectionRole::Method,
+            SectionRole::Experiments,
+        ];
+        let chunks = ai.split_sections(&paper, &roles, section_tokens * 2);
+        assert_eq!(chunks, vec![vec![0, 1], vec![2]]);
+
+        let chunks = ai.split_sections(&paper, &roles, 1);
+        assert_eq!(chunks, vec![vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn test_bilingual_summary() {
+        let mut ai = AI::new("gpt-4o-mini").unwrap();
+        ai.language(OutputLanguage::Bilingual).unwrap();
+        let schema = serde_json::to_value(ai.get_json_schema()).unwrap();
+        let properties = &schema["schema"]["properties"];
+        assert!(properties["overview"].is_object());
+        assert!(properties["overview_en"].is_object());
+        assert!(properties["is_survey_en"].is_null());
+
+        let content = serde_json::json!({
+            "is_survey": true,
+            "overview": "概要",
+            "overview_en": "Overview",
+            "limitations": "なし",
+            "limitations_en": "None"
+        });
+        let summary = Summary::from_json(&content.to_string()).unwrap();
+        assert_eq!(summary.overview, "概要");
+        let english = summary.english.clone().unwrap();
+        assert_eq!(english.overview, "Overview");
+        assert!(english.is_survey);
+        assert_eq!(summary.text("limitations"), "なし");
+        assert_eq!(english.text("limitations"), "None");
+
+        let summary = Summary::from_json(&serde_json::to_string(&english).unwrap()).unwrap();
+        assert!(summary.english.is_none());
+    }
+
+    pub fn summary_json() -> serde_json::Value {
+        return serde_json::json!({
+            "is_survey": false,
+            "overview": "A tiny transformer.",
+            "research_question": "",
+            "task_category": "",
+            "task_as_words": "machine translation",
+            "comparison_with_related_works": "",
+            "proposed_method": "",
+            "datasets": "WMT14",
+            "domain_as_words": "news",
+            "experiments": "27.3 BLEU",
+            "analysis": "",
+            "contributions": "",
+            "future_works": "",
+            "dataset_list": [],
+            "metrics": [],
+            "results": []
+        });
+    }
+
+    #[tokio::test]
+    async fn test_summarize_with_stub_server() {
+        let summary = summary_json();
+        let body = serde_json::json!({
+            "model": "local-model",
+            "choices": [{"message": {"role": "assistant", "content": summary.to_string()}}],
+            "usage": {"prompt_tokens": 100, "completion_tokens": 20, "total_tokens": 120}
+        });
+        let (url, mut requests) = crate::llm::tests::stub_server(&body.to_string()).await;
+
+        let mut paper = Paper::default();
+        paper.title = "A Tiny Transformer".to_string();
+        paper.document = Document {
+            sections: vec![DocumentSection::new(
+                0,
+                "1 Introduction",
+                vec!["We present a tiny transformer.".to_string()],
+            )],
+            ..Default::default()
+        };
+        paper.references = vec![Paper::reference(
+            "",
+            "Attention Is All You Need",
+            "",
+            Vec::new(),
+            crate::utils::default_datetime(),
+        )];
+        let mut ai = AI::new("local-model").unwrap();
+        ai.provider(Arc::new(OpenAICompatible::new(&url, None)));
+        ai.summarize(&mut paper).await.unwrap();
+        assert_eq!(paper.summary.overview, "A tiny transformer.");
+        assert_eq!(paper.summary.datasets, "WMT14");
+        assert_eq!(paper.summary.template_version, "summary-ja-4");
+        assert_eq!(paper.summary.model_id, "local-model");
+        assert_eq!(paper.usage.prompt_tokens, 100);
+        assert_eq!(paper.usage.completion_tokens, 20);
+        assert_eq!(paper.usage.models, vec!["local-model"]);
+        assert_eq!(ai.run_usage().total_tokens(), 120);
+
+        let raw = requests.recv().await.unwrap();
+        assert!(raw.contains("We present a tiny transformer."));
+        assert!(raw.contains("Attention Is All You Need"));
+        assert!(raw.contains(r#""response_format":{"type":"json_schema""#));
+
+        // the second run uses the cached summary
+        let dir = std::env::temp_dir().join("arxiv-batch-test-ai-summary-cache");
+        let _ = std::fs::remove_dir_all(&dir);
+        ai.summary_cache(SummaryCache::from_dir(&dir));
+        assert!(!ai.has_cached_summary(&paper));
+        ai.summarize(&mut paper).await.unwrap();
+        assert!(requests.recv().await.is_some());
+        assert!(ai.has_cached_summary(&paper));
+        paper.summary = Summary::default();
+        ai.summarize(&mut paper).await.unwrap();
+        assert_eq!(paper.summary.datasets, "WMT14");
+        assert_eq!(paper.usage.requests, 0);
+        assert!(requests.try_recv().is_err());
+
+        ai.force_resummarize(true);
+        assert!(!ai.has_cached_summary(&paper));
+        ai.summarize(&mut paper).await.unwrap();
+        assert_eq!(paper.usage.requests, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Rejects the prompts for "small-model" as too long and answers the others with a summary.
+    #[derive(Debug, Default)]
+    struct FallbackProvider {
+        models: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl LlmProvider for FallbackProvider {
+        fn name(&self) -> &str {
+            return "fallback";
+        }
+
+        async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+            self.models.lock().unwrap().push(request.model.clone());
+            if request.model == "small-model" {
+                return Err(
+                    LlmError::ContextLength(s("maximum context length is 8192 tokens")).into(),
+                );
+            }
+            return Ok(ChatResponse {
+                content: summary_json().to_string(),
+                model: request.model.clone(),
+                usage: crate::llm::Usage {
+                    prompt_tokens: 10,
+                    completion_tokens: 5,
+                    total_tokens: 15,
+                },
+            });
+        }
+    }
+
+    #[test]
+    fn test_model_spec() {
+        let models = ModelSpec::parse_list("gpt-4o-mini, my-model=32000").unwrap();
+        assert_eq!(models[0].model_id, "gpt-4o-mini");
+        assert_eq!(models[0].context_window, 128_000);
+        assert_eq!(models[1].model_id, "my-model");
+        assert_eq!(models[1].context_window, 32_000);
+        assert_eq!(models[1].prompt_budget(), 24_000);
+        assert!(ModelSpec::parse_list("").is_err());
+        assert!(ModelSpec::parse_list("my-model=large").is_err());
+        assert!(AI::new("my-model=large").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_model_fallback() {
+        let provider = Arc::new(FallbackProvider::default());
+        let mut ai = AI::new("small-model=8192,large-model").unwrap();
+        ai.provider(provider.clone());
+        let mut paper = Paper::default();
+        paper.title = "A Tiny Transformer".to_string();
+        paper.document = Document {
+            sections: vec![DocumentSection::new(
+                0,
+                "1 Introduction",
+                vec!["We present a tiny transformer.".to_string()],
+            )],
+            ..Default::default()
+        };
+        ai.summarize(&mut paper).await.unwrap();
+
+        // the context length error is not retried
+        assert_eq!(
+            *provider.models.lock().unwrap(),
+            vec!["small-model", "large-model"]
+        );
+        assert_eq!(paper.summary.model_id, "large-model");
+        assert_eq!(paper.summary.datasets, "WMT14");
+        assert_eq!(paper.usage.models, vec!["large-model"]);
+        assert_eq!(ai.model_id(), "small-model");
+    }
+
+    #[tokio::test]
+    async fn test_repair_and_partial_summary() {
+        // the datasets field is missing in every response
+        let summary = serde_json::json!({
+            "is_survey": false,
+            "overview": "A tiny transformer.",
+            "research_question": "",
+            "task_category": "",
+            "task_as_words": "machine translation",
+            "comparison_with_related_works": "",
+            "proposed_method": "",
+            "domain_as_words": "news",
+            "experiments": "27.3 BLEU",
+            "analysis": "",
+            "contributions": "",
+            "future_works": "",
+            "dataset_list": [],
+            "metrics": [],
+            "results": []
+        });
+        let body = serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": summary.to_string()}}]
+        });
+        let (url, mut requests) = crate::llm::tests::stub_server(&body.to_string()).await;
+
+        let mut paper = Paper::default();
+        paper.document = Document {
+            sections: vec![DocumentSection::new(
+                0,
+                "1 Introduction",
+                vec!["We present a tiny transformer.".to_string()],
+            )],
+            ..Default::default()
+        };
+        let mut ai = AI::new("local-model").unwrap();
+        ai.provider(Arc::new(OpenAICompatible::new(&url, None)))
+            .language(OutputLanguage::Japanese)
+            .unwrap()
+            .repair_attempts(1)
+            .allow_partial(false);
+        let error = ai.summarize(&mut paper).await.unwrap_err();
+        assert!(error.to_string().contains("datasets: missing"));
+        let _ = requests.recv().await.unwrap();
+        let repair = requests.recv().await.unwrap();
+        assert!(repair.contains("datasets: missing"));
+
+        ai.allow_partial(true);
+        ai.summarize(&mut paper).await.unwrap();
+        assert_eq!(paper.summary.overview, "A tiny transformer.");
+        assert_eq!(paper.summary.missing_fields, vec!["datasets"]);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_and_cancel() {
+        // a server that accepts connections and never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let mut ai = AI::new("local-model").unwrap();
+        ai.provider(Arc::new(OpenAICompatible::new(&url, None)))
+            .timeout(Duration::from_millis(200));
+        let request = ChatRequest::new("local-model", vec![Message::new("user", "Hi")]);
+        let error = ai.send(&request).await.unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+
+        let cancel = CancelToken::new();
+        ai.cancel_token(cancel.clone())
+            .timeout(Duration::from_secs(60));
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            canceller.cancel();
+        });
+        let time = std::time::Instant::now();
+        let error = ai.send(&request).await.unwrap_err();
+        assert_eq!(error.to_string(), "Cancelled");
+        assert!(time.elapsed() < Duration::from_secs(5));
+        assert!(ai.backoff(10).await.is_err());
+        let mut usage = TokenUsage::default();
+        assert!(ai.request_text(vec![], &mut usage).await.is_err());
+        assert_eq!(usage.requests, 0);
+    }
 }
@@ -0,0 +1,663 @@
+//! This module summarizes many papers at once with the OpenAI Batch API, at half the price of
+//! the chat completions API in exchange for results within the completion window (24 hours).
+//! The summary requests are uploaded as a JSONL file, a batch job is created and polled until
+//! it finishes, and the lines of its output file are matched back to the papers by custom ID.
+use crate::ai::AI;
+use crate::common::Paper;
+use crate::cost::Budget;
+use crate::llm::{parse_response, CancelToken, ChatRequest, ChatResponse, Usage, OPENAI_BASE_URL};
+use crate::summary_cache::hash;
+use crate::tokens::count_message_tokens;
+use anyhow::Result;
+use dotenvy::dotenv;
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub const BATCH_ENDPOINT: &str = "/v1/chat/completions";
+pub const COMPLETION_WINDOW: &str = "24h";
+
+/// Completion tokens assumed for a summary when estimating the cost of a batch.
+const ESTIMATED_COMPLETION_TOKENS: u64 = 2_000;
+
+/// Failed checks of the status of a batch in a row before giving up on it.
+const MAX_POLL_RETRIES: u32 = 5;
+
+/// A line of the input file: a chat completions request with the ID to match its result.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchRequest {
+    pub custom_id: String,
+    pub method: String,
+    pub url: String,
+    pub body: ChatRequest,
+}
+
+impl BatchRequest {
+    pub fn new(custom_id: &str, body: ChatRequest) -> BatchRequest {
+        BatchRequest {
+            custom_id: custom_id.to_string(),
+            method: String::from("POST"),
+            url: String::from(BATCH_ENDPOINT),
+            body,
+        }
+    }
+}
+
+/// The input file of a batch: a request per line.
+pub fn to_jsonl(requests: &[BatchRequest]) -> Result<String> {
+    let mut jsonl = String::new();
+    for request in requests.iter() {
+        jsonl.push_str(&serde_json::to_string(request)?);
+        jsonl.push('\n');
+    }
+    return Ok(jsonl);
+}
+
+/// Results of the output or the error file of a batch by custom ID.
+pub fn parse_output(jsonl: &str) -> FxHashMap<String, Result<ChatResponse>> {
+    #[derive(Deserialize)]
+    struct LineResponse {
+        #[serde(default)]
+        status_code: u16,
+        #[serde(default)]
+        body: serde_json::Value,
+    }
+    #[derive(Deserialize)]
+    struct Line {
+        custom_id: String,
+        #[serde(default)]
+        response: Option<LineResponse>,
+        #[serde(default)]
+        error: Option<serde_json::Value>,
+    }
+
+    let mut results = FxHashMap::default();
+    for line in jsonl.lines().filter(|line| !line.trim().is_empty()) {
+        let line = match serde_json::from_str::<Line>(line) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("WARNING: Failed to parse a line of the batch output: {}", e);
+                continue;
+            }
+        };
+        let result = match (line.response, line.error) {
+            (Some(response), _) if response.status_code == 200 => {
+                parse_response(&response.body.to_string())
+            }
+            (Some(response), _) => Err(anyhow::anyhow!(
+                "LLM server returned {}: {}",
+                response.status_code,
+                response.body
+            )),
+            (None, Some(error)) => Err(anyhow::anyhow!("The request failed: {}", error)),
+            (None, None) => Err(anyhow::anyhow!("The request has no response")),
+        };
+        results.insert(line.custom_id, result);
+    }
+    return results;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct RequestCounts {
+    #[serde(default)]
+    pub total: u64,
+    #[serde(default)]
+    pub completed: u64,
+    #[serde(default)]
+    pub failed: u64,
+}
+
+/// A batch job as returned by `/batches`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct BatchJob {
+    pub id: String,
+    /// "validating", "in_progress", "finalizing", "completed", "failed", "expired", ...
+    pub status: String,
+    #[serde(default)]
+    pub output_file_id: Option<String>,
+    #[serde(default)]
+    pub error_file_id: Option<String>,
+    #[serde(default)]
+    pub request_counts: Option<RequestCounts>,
+}
+
+impl BatchJob {
+    pub fn is_finished(&self) -> bool {
+        return matches!(
+            self.status.as_str(),
+            "completed" | "failed" | "expired" | "cancelled"
+        );
+    }
+}
+
+/// A server with the OpenAI Files and Batch APIs: `{base_url}/files` and `{base_url}/batches`.
+#[derive(Debug, Clone)]
+pub struct BatchClient {
+    pub base_url: String,
+    pub api_key: Option<String>,
+    /// Wait between the checks of the status of a batch
+    pub poll_interval: Duration,
+    cancel: CancelToken,
+}
+
+impl BatchClient {
+    /// `LLM_BASE_URL` and `LLM_API_KEY` (or `OPENAI_API_KEY`) like the LLM, and
+    /// `BATCH_POLL_INTERVAL` in seconds (60).
+    /// Fails when `LLM_PROVIDER` is not an OpenAI-compatible server, e.g. Azure OpenAI.
+    pub fn new() -> Result<BatchClient> {
+        dotenv().ok();
+        let env = |key: &str| std::env::var(key).ok().filter(|x| !x.trim().is_empty());
+        let provider = env("LLM_PROVIDER").unwrap_or(String::from("openai"));
+        if !matches!(
+            provider.to_lowercase().as_str(),
+            "openai" | "openai-compatible" | "vllm" | "llama.cpp" | "ollama"
+        ) {
+            return Err(anyhow::anyhow!(
+                "The Batch API needs an OpenAI-compatible LLM_PROVIDER, not {}",
+                provider
+            ));
+        }
+        let base_url = env("LLM_BASE_URL").unwrap_or(String::from(OPENAI_BASE_URL));
+        let api_key = env("LLM_API_KEY").or(env("OPENAI_API_KEY"));
+        let poll_interval = env("BATCH_POLL_INTERVAL")
+            .and_then(|x| x.trim().parse().ok())
+            .unwrap_or(60);
+        let mut client = BatchClient::from_url(&base_url, api_key);
+        client.poll_interval(Duration::from_secs(poll_interval));
+        return Ok(client);
+    }
+
+    pub fn from_url(base_url: &str, api_key: Option<String>) -> BatchClient {
+        BatchClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            poll_interval: Duration::from_secs(60),
+            cancel: CancelToken::new(),
+        }
+    }
+
+    pub fn poll_interval(&mut self, poll_interval: Duration) -> &mut Self {
+        self.poll_interval = poll_interval;
+        return self;
+    }
+
+    /// The batch is cancelled on the server too when the token is cancelled while waiting.
+    pub fn cancel_token(&mut self, cancel: CancelToken) -> &mut Self {
+        self.cancel = cancel;
+        return self;
+    }
+
+    fn authorize(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        return match self.api_key.as_ref() {
+            Some(api_key) => builder.bearer_auth(api_key),
+            None => builder,
+        };
+    }
+
+    async fn send(&self, builder: reqwest::RequestBuilder) -> Result<String> {
+        let response = self.authorize(builder).send().await?;
+        let status = response.status();
+        let content = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow::anyhow!(
+                "Batch server returned {}: {}",
+                status,
+                content
+            ));
+        }
+        return Ok(content);
+    }
+
+    async fn send_job(&self, builder: reqwest::RequestBuilder) -> Result<BatchJob> {
+        let content = self.send(builder).await?;
+        return serde_json::from_str::<BatchJob>(&content)
+            .map_err(|e| anyhow::anyhow!("Failed to parse JSON: {} CONTENT: {}", e, content));
+    }
+
+    /// Upload the input file of a batch and return its ID.
+    pub async fn upload(&self, jsonl: &str) -> Result<String> {
+        #[derive(Deserialize)]
+        struct File {
+            id: String,
+        }
+
+        let boundary = format!("arxiv-batch-{}", hash(&[jsonl]));
+        let body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"purpose\"\r\n\r\nbatch\r\n\
+             --{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"batch.jsonl\"\r\n\
+             Content-Type: application/jsonl\r\n\r\n{jsonl}\r\n--{boundary}--\r\n"
+        );
+        let builder = reqwest::Client::new()
+            .post(format!("{}/files", self.base_url))
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .body(body);
+        let content = self.send(builder).await?;
+        let file = serde_json::from_str::<File>(&content)
+            .map_err(|e| anyhow::anyhow!("Failed to parse JSON: {} CONTENT: {}", e, content))?;
+        return Ok(file.id);
+    }
+
+    pub async fn create(&self, input_file_id: &str) -> Result<BatchJob> {
+        let body = serde_json::json!({
+            "input_file_id": input_file_id,
+            "endpoint": BATCH_ENDPOINT,
+            "completion_window": COMPLETION_WINDOW,
+        });
+        let builder = reqwest::Client::new()
+            .post(format!("{}/batches", self.base_url))
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+        return self.send_job(builder).await;
+    }
+
+    pub async fn get(&self, batch_id: &str) -> Result<BatchJob> {
+        let builder = reqwest::Client::new().get(format!("{}/batches/{}", self.base_url, batch_id));
+        return self.send_job(builder).await;
+    }
+
+    pub async fn cancel(&self, batch_id: &str) -> Result<BatchJob> {
+        let builder =
+            reqwest::Client::new().post(format!("{}/batches/{}/cancel", self.base_url, batch_id));
+        return self.send_job(builder).await;
+    }
+
+    pub async fn file_content(&self, file_id: &str) -> Result<String> {
+        let builder =
+            reqwest::Client::new().get(format!("{}/files/{}/content", self.base_url, file_id));
+        return self.send(builder).await;
+    }
+
+    /// Poll the batch until it finishes. A failed check is retried up to `MAX_POLL_RETRIES`
+    /// times with a growing wait; after that, and when the token is cancelled, the batch is
+    /// cancelled so that it is not billed for results nobody collects, and this fails.
+    pub async fn wait(&self, batch_id: &str) -> Result<BatchJob> {
+        let mut failures = 0;
+        loop {
+            let mut wait = self.poll_interval;
+            match self.get(batch_id).await {
+                Ok(job) => {
+                    failures = 0;
+                    let counts = job.request_counts.clone().unwrap_or_default();
+                    println!(
+                        "Batch {}: {} ({}/{} completed, {} failed)",
+                        job.id, job.status, counts.completed, counts.total, counts.failed
+                    );
+                    if job.is_finished() {
+                        return Ok(job);
+                    }
+                }
+                Err(e) => {
+                    failures += 1;
+                    if failures > MAX_POLL_RETRIES {
+                        self.cancel_batch(batch_id).await;
+                        return Err(e);
+                    }
+                    eprintln!(
+                        "WARNING: Failed to check the batch {}: {} (retry: {})",
+                        batch_id, e, failures
+                    );
+                    wait = self.poll_interval * (1 << failures);
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = self.cancel.cancelled() => {
+                    self.cancel_batch(batch_id).await;
+                    return Err(anyhow::anyhow!("Cancelled"));
+                }
+            }
+        }
+    }
+
+    async fn cancel_batch(&self, batch_id: &str) {
+        match self.cancel(batch_id).await {
+            Ok(_) => eprintln!("WARNING: Cancelled the batch {}", batch_id),
+            Err(e) => eprintln!(
+                "WARNING: Failed to cancel the batch {}, it may still be running: {}",
+                batch_id, e
+            ),
+        }
+    }
+
+    /// Upload the requests and create a batch of them.
+    pub async fn submit(&self, requests: &[BatchRequest]) -> Result<BatchJob> {
+        let input_file_id = self.upload(&to_jsonl(requests)?).await?;
+        let job = self.create(&input_file_id).await?;
+        println!(
+            "Submitted a batch of {} requests: {}",
+            requests.len(),
+            job.id
+        );
+        return Ok(job);
+    }
+
+    /// Wait for the batch and return the results of its requests by custom ID.
+    /// The requests missing in the output of a finished batch are left out.
+    pub async fn collect(&self, batch_id: &str) -> Result<FxHashMap<String, Result<ChatResponse>>> {
+        let job = self.wait(batch_id).await?;
+        let mut results = FxHashMap::default();
+        if let Some(file_id) = job.error_file_id.as_ref() {
+            results.extend(parse_output(&self.file_content(file_id).await?));
+        }
+        match job.output_file_id.as_ref() {
+            Some(file_id) => results.extend(parse_output(&self.file_content(file_id).await?)),
+            None if results.is_empty() => {
+                return Err(anyhow::anyhow!(
+                    "The batch {} ended with no output: {}",
+                    job.id,
+                    job.status
+                ));
+            }
+            None => {}
+        }
+        return Ok(results);
+    }
+
+    /// Summarize the papers at `indices` in one batch and return the result of each paper
+    /// by index. The papers with a cached summary or too long for one request are left out,
+    /// and so are the papers beyond the estimated cost the budget allows; they are summarized
+    /// by `AI::summarize`. This fails only when the batch cannot be submitted: once it is,
+    /// the papers of a batch that does not finish fail rather than being paid for twice.
+    pub async fn summarize(
+        &self,
+        ai: &AI,
+        papers: &mut [Paper],
+        indices: &[usize],
+        budget: &Budget,
+    ) -> Result<FxHashMap<usize, Result<()>>> {
+        let mut requests = Vec::new();
+        let mut estimate = ai.run_usage();
+        for index in indices.iter() {
+            let request = match ai.batch_request(&papers[*index]) {
+                Some(request) => request,
+                None => continue,
+            };
+            let usage = Usage {
+                prompt_tokens: count_message_tokens(&request.model, &request.messages) as u64,
+                completion_tokens: ESTIMATED_COMPLETION_TOKENS,
+                total_tokens: 0,
+            };
+            estimate.add_batch(&request.model, &usage, ai.prices());
+            if let Some(reason) = budget.exceeded(&estimate) {
+                println!(
+                    "The budget allows {} papers in the batch (estimated {})",
+                    requests.len(),
+                    reason
+                );
+                break;
+            }
+            requests.push((
+                *index,
+                BatchRequest::new(&format!("paper-{}", index), request),
+            ));
+        }
+        let mut summaries = FxHashMap::default();
+        if requests.is_empty() {
+            return Ok(summaries);
+        }
+
+        let job = self
+            .submit(
+                &requests
+                    .iter()
+                    .map(|(_, request)| request.clone())
+                    .collect::<Vec<BatchRequest>>(),
+            )
+            .await?;
+        let mut results = match self.collect(&job.id).await {
+            Ok(results) => results,
+            Err(e) => {
+                eprintln!("WARNING: The batch {} did not finish: {}", job.id, e);
+                for (index, _) in requests.iter() {
+                    summaries.insert(
+                        *index,
+                        Err(anyhow::anyhow!(
+                            "The batch {} did not finish: {}",
+                            job.id,
+                            e
+                        )),
+                    );
+                }
+                return Ok(summaries);
+            }
+        };
+        for (index, request) in requests.iter() {
+            let response = results
+                .remove(&request.custom_id)
+                .unwrap_or(Err(anyhow::anyhow!("No result in the batch output")));
+            let result = ai
+                .apply_batch_response(&mut papers[*index], response, budget)
+                .await;
+            summaries.insert(*index, result);
+        }
+        return Ok(summaries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::tests::summary_json;
+    use crate::common::tests::PaperBuilder;
+    use crate::llm::tests::{chat_response, read_request};
+    use crate::llm::OpenAICompatible;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    /// A stand-in of the Files, Batch and chat completions endpoints. The first
+    /// `failed_checks` checks of the batch fail, and the next one finds it in progress;
+    /// its output answers "paper-0" with a summary and "paper-1" with an error, and the
+    /// chat completions endpoint answers with a summary.
+    async fn batch_server(failed_checks: usize) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(async move {
+            let mut checks = 0;
+            loop {
+                let (mut socket, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(_) => return,
+                };
+                let request = read_request(&mut socket).await;
+                let line = request.lines().next().unwrap_or_default().to_string();
+                log.lock().unwrap().push(request.clone());
+                let mut summary = chat_response(&summary_json().to_string());
+                summary["model"] = serde_json::json!("gpt-4o-mini");
+                let mut status = "200 OK";
+                let body = if line.starts_with("POST /v1/files ") {
+                    serde_json::json!({"id": "file-in", "purpose": "batch"}).to_string()
+                } else if line.starts_with("POST /v1/batches ") {
+                    serde_json::json!({"id": "batch-1", "status": "validating"}).to_string()
+                } else if line.starts_with("GET /v1/batches/batch-1 ") {
+                    checks += 1;
+                    if checks <= failed_checks {
+                        status = "503 Service Unavailable";
+                        serde_json::json!({"error": {"message": "overloaded"}}).to_string()
+                    } else if checks == failed_checks + 1 {
+                        serde_json::json!({"id": "batch-1", "status": "in_progress"}).to_string()
+                    } else {
+                        serde_json::json!({
+                            "id": "batch-1",
+                            "status": "completed",
+                            "output_file_id": "file-out",
+                            "request_counts": {"total": 2, "completed": 1, "failed": 1}
+                        })
+                        .to_string()
+                    }
+                } else if line.starts_with("POST /v1/batches/batch-1/cancel ") {
+                    serde_json::json!({"id": "batch-1", "status": "cancelling"}).to_string()
+                } else if line.starts_with("GET /v1/files/file-out/content ") {
+                    format!(
+                        "{}\n{}\n",
+                        serde_json::json!({
+                            "custom_id": "paper-0",
+                            "response": {"status_code": 200, "body": summary}
+                        }),
+                        serde_json::json!({
+                            "custom_id": "paper-1",
+                            "response": {"status_code": 500, "body": {"error": {"message": "server error"}}}
+                        })
+                    )
+                } else {
+                    summary.to_string()
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+        return (url, requests);
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let output = format!(
+            "{}\n\n{}\n{}\n",
+            serde_json::json!({
+                "custom_id": "a",
+                "response": {"status_code": 200, "body": chat_response("Hello!")}
+            }),
+            serde_json::json!({
+                "custom_id": "b",
+                "response": null,
+                "error": {"code": "batch_expired", "message": "expired"}
+            }),
+            serde_json::json!({
+                "custom_id": "c",
+                "response": {"status_code": 400, "body": {"error": {"message": "bad request"}}}
+            })
+        );
+        let results = parse_output(&output);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results["a"].as_ref().unwrap().content, "Hello!");
+        assert!(results["b"]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("batch_expired"));
+        assert!(results["c"]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("400"));
+    }
+
+    #[tokio::test]
+    async fn test_summarize_in_batch() {
+        let (url, requests) = batch_server(1).await;
+        let mut client = BatchClient::from_url(&url, Some(String::from("sk-test")));
+        client.poll_interval(Duration::from_millis(10));
+        let mut ai = AI::new("gpt-4o-mini").unwrap();
+        ai.provider(Arc::new(OpenAICompatible::new(&url, None)));
+
+        let mut papers = vec![
+            PaperBuilder::new("A Tiny Transformer")
+                .section("1 Introduction", "We present A Tiny Transformer.")
+                .build(),
+            PaperBuilder::new("A Huge Transformer")
+                .section("1 Introduction", "We present A Huge Transformer.")
+                .build(),
+            PaperBuilder::new("Skipped")
+                .section("1 Introduction", "We present Skipped.")
+                .build(),
+        ];
+        let results = client
+            .summarize(&ai, &mut papers, &[0, 1], &Budget::default())
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results[&0].is_ok());
+        assert!(results[&1].is_ok());
+        assert_eq!(papers[0].summary.datasets, "WMT14");
+        assert_eq!(papers[0].summary.model_id, "gpt-4o-mini");
+        // half of $0.15/M input and $0.6/M output
+        assert_eq!(papers[0].usage.requests, 1);
+        assert!((papers[0].usage.cost - 0.0000135).abs() < 1e-12);
+        // the failed request is sent again to the chat completions endpoint
+        assert_eq!(papers[1].summary.datasets, "WMT14");
+        assert_eq!(papers[1].usage.requests, 1);
+        assert!(papers[2].summary.overview.is_empty());
+        assert_eq!(ai.run_usage().requests, 2);
+
+        let requests = requests.lock().unwrap();
+        let lines = requests
+            .iter()
+            .map(|request| request.lines().next().unwrap().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            lines,
+            vec![
+                "POST /v1/files HTTP/1.1",
+                "POST /v1/batches HTTP/1.1",
+                "GET /v1/batches/batch-1 HTTP/1.1",
+                "GET /v1/batches/batch-1 HTTP/1.1",
+                "GET /v1/batches/batch-1 HTTP/1.1",
+                "GET /v1/files/file-out/content HTTP/1.1",
+                "POST /v1/chat/completions HTTP/1.1",
+            ]
+        );
+        assert!(requests[0]
+            .to_lowercase()
+            .contains("authorization: bearer sk-test"));
+        assert!(requests[0].contains("name=\"purpose\"\r\n\r\nbatch\r\n"));
+        assert!(requests[0].contains(r#"{"custom_id":"paper-0","method":"POST","url":"/v1/chat/completions","body":{"model":"gpt-4o-mini""#));
+        assert!(requests[0].contains("We present A Huge Transformer."));
+        assert!(!requests[0].contains("Skipped"));
+        assert!(requests[1].contains(r#""input_file_id":"file-in""#));
+        assert!(requests[1].contains(r#""completion_window":"24h""#));
+    }
+
+    #[tokio::test]
+    async fn test_batch_budget_and_failed_checks() {
+        let (url, requests) = batch_server(MAX_POLL_RETRIES as usize + 1).await;
+        let mut client = BatchClient::from_url(&url, None);
+        client.poll_interval(Duration::from_millis(1));
+        let mut ai = AI::new("gpt-4o-mini").unwrap();
+        ai.provider(Arc::new(OpenAICompatible::new(&url, None)));
+
+        // a summary is estimated at about $0.0006 for the completion alone
+        let budget = Budget {
+            max_cost: Some(0.001),
+            max_tokens: None,
+        };
+        let mut papers = vec![
+            PaperBuilder::new("A Tiny Transformer")
+                .section("1 Introduction", "We present A Tiny Transformer.")
+                .build(),
+            PaperBuilder::new("A Huge Transformer")
+                .section("1 Introduction", "We present A Huge Transformer.")
+                .build(),
+        ];
+        let results = client
+            .summarize(&ai, &mut papers, &[0, 1], &budget)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[&0]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("The batch batch-1 did not finish"));
+        assert_eq!(ai.run_usage().requests, 0);
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].contains("A Tiny Transformer"));
+        assert!(!requests[0].contains("A Huge Transformer"));
+        let last = requests.last().unwrap();
+        assert!(last.starts_with("POST /v1/batches/batch-1/cancel HTTP/1.1"));
+        assert!(!requests
+            .iter()
+            .any(|request| request.starts_with("POST /v1/chat/completions")));
+    }
+}
@@ -56,6 +56,9 @@ pub struct Cache {
     pub path: PathBuf,
     pub papers: Vec<PaperCache>,
     pub failed_papers: Vec<PaperCache>,
+    /// Papers whose extracted text did not pass the quality gate
+    #[serde(default = "Vec::new")]
+    pub needs_review: Vec<PaperCache>,
     pub authors: Vec<AuthorCache>,
     pub author_map: FxHashMap<String, String>,
 }
@@ -69,6 +72,7 @@ impl Cache {
             path,
             papers: Vec::new(),
             failed_papers: Vec::new(),
+            needs_review: Vec::new(),
             authors: Vec::new(),
             author_map: FxHashMap::default(),
         }
@@ -232,6 +236,12 @@ impl Cache {
         self.papers.push(paper);
     }
 
+    /// Record a paper that needs review, replacing the previous record of the same paper.
+    pub fn add_needs_review(&mut self, paper: PaperCache) {
+        self.needs_review.retain(|x| x.title != paper.title);
+        self.needs_review.push(paper);
+    }
+
     pub fn add_author(&mut self, author: AuthorCache) {
         self.authors.push(author.clone());
         self.author_map
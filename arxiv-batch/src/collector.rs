@@ -29,6 +29,11 @@ impl Collector {
         }
     }
 
+    /// Wait time in seconds between the requests to the APIs.
+    pub fn wait_time(&self) -> u64 {
+        return self.wait_time;
+    }
+
     fn build_default_arxiv(target_date: Option<DateTime<Utc>>) -> ar::ArXiv {
         let category_conditions = ar::QueryParams::or(vec![
             ar::QueryParams::subject_category(ar::Category::CsAi),
@@ -180,6 +185,7 @@ impl Collector {
                 ss::structs::PaperField::Title,
                 ss::structs::PaperField::Abstract,
                 ss::structs::PaperField::PublicationDate,
+                ss::structs::PaperField::CitationCount,
             ]),
         ]);
 
@@ -279,7 +285,7 @@ impl Collector {
             .unwrap()
             .iter()
             .map(|r| {
-                Paper::reference(
+                let mut reference = Paper::reference(
                     r.paper_id.clone().unwrap_or_default().as_str(),
                     r.title
                         .clone()
@@ -298,7 +304,9 @@ impl Collector {
                     } else {
                         default_datetime()
                     },
-                )
+                );
+                reference.citation_count = r.citation_count.unwrap_or_default();
+                reference
             })
             .collect();
 
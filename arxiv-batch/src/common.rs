@@ -1,10 +1,15 @@
-use crate::utils::s;
+use crate::cost::TokenUsage;
+use crate::document::Document;
+use crate::latex::{is_timeout, EprintDownloader};
+use crate::section::SectionRole;
+use crate::serializer::{serialize_paper, serialize_related, PaperFormat, PaperView};
+use crate::utils::arxiv_id_from_url;
 use anyhow::Result;
-use chrono::{DateTime, Datelike, Utc};
+use chrono::{DateTime, Utc};
 use fxhash::FxHashMap;
 use keywords::rsc::{extract_keywords, load_keywords, Keyword, Language};
 use rsrpp::parser::parse;
-use rsrpp::parser::structs::{ParserConfig, Section};
+use rsrpp::parser::structs::ParserConfig;
 use serde::{Deserialize, Serialize};
 
 pub enum StatusCode {
@@ -58,7 +63,36 @@ impl Author {
     }
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatasetInfo {
+    pub name: String,
+    pub task: String,
+    pub url: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metric {
+    pub name: String,
+    pub description: String,
+}
+
+/// A key result: the score of the proposed method on a dataset with a metric.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyResult {
+    pub dataset: String,
+    pub metric: String,
+    pub value: String,
+    /// The best baseline compared and its score
+    pub baseline: String,
+}
+
+/// Summary of a paper. The built-in fields are typed, and the fields added
+/// in the summary schema (`SUMMARY_SCHEMA`) are kept in `extra`.
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Summary {
     pub is_survey: bool,
     pub overview: String,
@@ -73,43 +107,160 @@ pub struct Summary {
     pub analysis: String,
     pub contributions: String,
     pub future_works: String,
+    pub dataset_list: Vec<DatasetInfo>,
+    pub metrics: Vec<Metric>,
+    pub results: Vec<KeyResult>,
+    /// Version of the prompt template that produced the summary
+    pub template_version: String,
+    /// Model that produced the summary
+    pub model_id: String,
+    /// Fields missing from a partial summary
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing_fields: Vec<String>,
+    /// Numbers and names in the summary that were not found in the paper
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unsupported_claims: Vec<String>,
+    /// English version of the summary in bilingual mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub english: Option<Box<Summary>>,
+    /// Fields defined only in the summary schema
+    #[serde(flatten)]
+    pub extra: FxHashMap<String, serde_json::Value>,
 }
 
 impl Summary {
-    pub fn task_as_vec(&self) -> Vec<String> {
-        if self.task_as_words.contains(",") {
-            return self
-                .task_as_words
-                .split(",")
-                .map(|s| s.to_string())
-                .collect();
-        } else if self.task_as_words.contains("、") {
-            return self
-                .task_as_words
-                .split("、")
-                .map(|s| s.to_string())
-                .collect();
-        } else {
-            return vec![self.task_as_words.clone()];
+    /// Parse the response of the LLM.
+    /// The fields ending with "_en" of a bilingual response make up the English version.
+    pub fn from_json(content: &str) -> Result<Summary> {
+        let value = serde_json::from_str::<serde_json::Value>(content)?;
+        let object = value.as_object().ok_or(anyhow::anyhow!(
+            "The summary is not a JSON object: {}",
+            content
+        ))?;
+        if !object.keys().any(|key| key.ends_with("_en")) {
+            return Ok(serde_json::from_value::<Summary>(value)?);
         }
+
+        let mut japanese = serde_json::Map::new();
+        let mut english = serde_json::Map::new();
+        for (key, value) in object {
+            match key.strip_suffix("_en") {
+                Some(name) => {
+                    english.insert(name.to_string(), value.clone());
+                }
+                None => {
+                    japanese.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        // fields written only once (e.g. is_survey) are shared by both languages
+        for (key, value) in japanese.iter() {
+            if !english.contains_key(key) {
+                english.insert(key.clone(), value.clone());
+            }
+        }
+        let mut summary = serde_json::from_value::<Summary>(serde_json::Value::Object(japanese))?;
+        let english = serde_json::from_value::<Summary>(serde_json::Value::Object(english))?;
+        summary.english = Some(Box::new(english));
+        return Ok(summary);
     }
 
-    pub fn domain_as_vec(&self) -> Vec<String> {
-        if self.domain_as_words.contains(",") {
-            return self
-                .domain_as_words
-                .split(",")
-                .map(|s| s.to_string())
-                .collect();
-        } else if self.domain_as_words.contains("、") {
-            return self
-                .domain_as_words
-                .split("、")
-                .map(|s| s.to_string())
-                .collect();
-        } else {
-            return vec![self.domain_as_words.clone()];
+    /// Value of the field `name`, either a built-in field or one in `extra`.
+    pub fn value(&self, name: &str) -> Option<serde_json::Value> {
+        if let Some(value) = self.extra.get(name) {
+            return Some(value.clone());
+        }
+        let value = serde_json::to_value(self).ok()?;
+        return value.get(name).cloned();
+    }
+
+    /// Value of the field `name` as text ("" if it is missing).
+    pub fn text(&self, name: &str) -> String {
+        return match self.value(name) {
+            Some(serde_json::Value::String(text)) => text,
+            Some(serde_json::Value::Null) | None => String::new(),
+            Some(serde_json::Value::Array(values)) => values
+                .iter()
+                .map(|value| match value {
+                    serde_json::Value::String(text) => text.clone(),
+                    serde_json::Value::Object(object) => object
+                        .values()
+                        .filter_map(|value| value.as_str())
+                        .filter(|value| !value.is_empty())
+                        .collect::<Vec<&str>>()
+                        .join(" / "),
+                    value => value.to_string(),
+                })
+                .collect::<Vec<String>>()
+                .join(", "),
+            Some(value) => value.to_string(),
+        };
+    }
+
+    /// Value of the field `name` split into words at "," or "、".
+    /// The words of an array are its elements, or the "name" of each object.
+    pub fn words(&self, name: &str) -> Vec<String> {
+        if let Some(serde_json::Value::Array(values)) = self.value(name) {
+            let mut words: Vec<String> = Vec::new();
+            for value in values.iter() {
+                let word = match value {
+                    serde_json::Value::Object(object) => {
+                        object.get("name").and_then(|x| x.as_str())
+                    }
+                    value => value.as_str(),
+                };
+                // Notion does not accept commas in the options
+                let word = word
+                    .unwrap_or_default()
+                    .replace(',', " ")
+                    .trim()
+                    .to_string();
+                if !word.is_empty() && !words.contains(&word) {
+                    words.push(word);
+                }
+            }
+            return words;
         }
+        return split_words(&self.text(name));
+    }
+
+    /// Rows of the field `name` of type array: the values of `columns` in each element.
+    pub fn rows(&self, name: &str, columns: &[&str]) -> Vec<Vec<String>> {
+        let values = match self.value(name) {
+            Some(serde_json::Value::Array(values)) => values,
+            _ => return Vec::new(),
+        };
+        return values
+            .iter()
+            .map(|value| {
+                columns
+                    .iter()
+                    .map(|column| match value.get(column) {
+                        Some(serde_json::Value::String(text)) => text.clone(),
+                        Some(serde_json::Value::Null) | None => String::new(),
+                        Some(value) => value.to_string(),
+                    })
+                    .collect()
+            })
+            .collect();
+    }
+
+    pub fn task_as_vec(&self) -> Vec<String> {
+        return split_words(&self.task_as_words);
+    }
+
+    pub fn domain_as_vec(&self) -> Vec<String> {
+        return split_words(&self.domain_as_words);
+    }
+}
+
+fn split_words(text: &str) -> Vec<String> {
+    if text.contains(",") {
+        return text.split(",").map(|s| s.to_string()).collect();
+    } else if text.contains("、") {
+        return text.split("、").map(|s| s.to_string()).collect();
+    } else {
+        return vec![text.to_string()];
     }
 }
 
@@ -135,9 +286,10 @@ pub struct Paper {
     pub reference_count: u32,
     pub citations: Vec<Paper>,
     pub references: Vec<Paper>,
-    pub original_text_map: FxHashMap<String, Section>,
-    pub original_text: Vec<Section>,
+    pub document: Document,
     pub summary: Summary,
+    /// Tokens and cost of the summarization
+    pub usage: TokenUsage,
 }
 
 impl Paper {
@@ -164,8 +316,7 @@ impl Paper {
         target_text.push_str("\n\n");
         target_text.push_str(&self.abstract_text);
 
-        if self.original_text_map.contains_key("Introduction") {
-            let section = self.original_text_map.get("Introduction").unwrap();
+        for section in self.document.find_by_role(SectionRole::Introduction) {
             let paragraphs = section.contents.join("\n");
             target_text.push_str("\n\n");
             target_text.push_str(&paragraphs);
@@ -182,11 +333,44 @@ impl Paper {
         return Ok(self);
     }
 
+    /// Get the original text of the paper.
+    /// For arXiv papers the LaTeX source is downloaded with `eprints` unless `TEXT_SOURCE` is "pdf",
+    /// and the PDF is parsed as the fallback when no source is available or the download times out.
     pub async fn get_original_text(
         &mut self,
         pdf: Option<String>,
+        eprints: &EprintDownloader,
         verbose: bool,
     ) -> Result<&mut Self> {
+        let text_source = std::env::var("TEXT_SOURCE").unwrap_or(String::from("latex"));
+        if pdf.is_none() && !self.arxiv_id.is_empty() && text_source != "pdf" {
+            let arxiv_id = arxiv_id_from_url(&self.arxiv_id);
+            let document = match eprints.download(&arxiv_id).await {
+                Ok(source) => source.to_document(),
+                Err(e) => Err(e),
+            };
+            match document {
+                Ok(document) => {
+                    self.document = document;
+                    return Ok(self);
+                }
+                Err(e) if is_timeout(&e) => {
+                    eprintln!(
+                        "WARNING: Timed out downloading the LaTeX source, falling back to PDF: {}",
+                        arxiv_id
+                    );
+                }
+                Err(e) => {
+                    if verbose {
+                        println!(
+                            "Failed to get LaTeX source, falling back to PDF: {}: {}",
+                            arxiv_id, e
+                        );
+                    }
+                }
+            }
+        }
+
         let pdf = match pdf {
             Some(pdf) => pdf,
             None => {
@@ -200,80 +384,128 @@ impl Paper {
 
         let mut parser_config = ParserConfig::new();
         let pages = parse(&pdf, &mut parser_config, verbose).await?;
-        let sections = Section::from_pages(&pages);
-
-        self.original_text = sections.clone();
-        self.original_text_map = FxHashMap::default();
-        for section in sections {
-            self.original_text_map
-                .insert(section.title.clone(), section);
-        }
+        self.document = Document::from_pages(&pages);
         return Ok(self);
     }
 
     pub fn original_text2xml(&self) -> String {
-        let mut sections = self.original_text_map.values().collect::<Vec<&Section>>();
-        sections.sort_by(|a, b| a.index.cmp(&b.index));
-
-        let mut xml = s("<paper>");
-        // baseic information
-        xml.push_str("<metadata>");
-        xml.push_str(format!("<title>{}</title>", self.title).as_str());
-        xml.push_str("<authors>");
-        for author in self.authors.clone().iter() {
-            xml.push_str(format!("<author>{}</author>", author.name).as_str());
-        }
-        xml.push_str("</authors>");
-        xml.push_str("</metadata>");
-
-        // contents
-        xml.push_str("<contents>");
-        for section in sections {
-            xml.push_str("<section>");
-            xml.push_str(format!("<title>{}</title>", section.title).as_str());
-            for paragraph in section.contents.iter() {
-                xml.push_str(format!("<paragraph>{}</paragraph>", paragraph).as_str());
-            }
-            xml.push_str("</section>");
-        }
-        xml.push_str("</contents>");
-        xml.push_str("</paper");
-        return xml;
+        return self.serialize_by_roles(&SectionRole::all(), PaperFormat::Xml);
+    }
+
+    /// Serialize the original text as XML, keeping only the sections classified as one of `roles`.
+    pub fn original_text2xml_by_roles(&self, roles: &[SectionRole]) -> String {
+        return self.serialize_by_roles(roles, PaperFormat::Xml);
+    }
+
+    /// Serialize the original text, keeping only the sections classified as one of `roles`.
+    pub fn serialize_by_roles(&self, roles: &[SectionRole], format: PaperFormat) -> String {
+        let view = PaperView {
+            paper: self,
+            sections: self.document.filter_by_roles(roles),
+            figures: self.document.figures_by_roles(roles),
+            tables: self.document.tables_by_roles(roles),
+        };
+        return serialize_paper(&view, format);
+    }
+
+    /// Serialize the original text, keeping only the sections at `indices`
+    /// and the figures and tables that appear in them.
+    pub fn serialize_by_sections(&self, indices: &[usize], format: PaperFormat) -> String {
+        let view = PaperView {
+            paper: self,
+            sections: self
+                .document
+                .iter()
+                .filter(|section| indices.contains(&section.index))
+                .collect(),
+            figures: self
+                .document
+                .figures
+                .iter()
+                .filter(|figure| indices.contains(&figure.section))
+                .collect(),
+            tables: self
+                .document
+                .tables
+                .iter()
+                .filter(|table| indices.contains(&table.section))
+                .collect(),
+        };
+        return serialize_paper(&view, format);
     }
 
     pub fn references2xml(&self) -> String {
-        let mut xml = s("<references>");
-        for reference in &self.references {
-            xml.push_str("<reference>");
-            xml.push_str(format!("<title>{}<title>", reference.title).as_str());
-            xml.push_str("<authors>");
-            for author in reference.authors.clone().iter() {
-                xml.push_str(format!("<author>{}</author>", author.name).as_str());
-            }
-            xml.push_str("</authors>");
-            xml.push_str(format!("<year>{}</year>", reference.publication_date.year()).as_str());
-            xml.push_str(format!("<abstract>{}</abstract>", reference.abstract_text).as_str());
-            xml.push_str("</reference>");
-        }
-        xml.push_str("</references>");
-        return xml;
+        return self.serialize_references(PaperFormat::Xml);
     }
 
     pub fn citations2xml(&self) -> String {
-        let mut xml = s("<citations>");
-        for citation in &self.citations {
-            xml.push_str("<citation>");
-            xml.push_str(format!("<title>{}<title>", citation.title).as_str());
-            xml.push_str("<authors>");
-            for author in citation.authors.clone().iter() {
-                xml.push_str(format!("<author>{}</author>", author.name).as_str());
-            }
-            xml.push_str("</authors>");
-            xml.push_str(format!("<year>{}</year>", citation.publication_date.year()).as_str());
-            xml.push_str(format!("<abstract>{}</abstract>", citation.abstract_text).as_str());
-            xml.push_str("</citation>");
+        return self.serialize_citations(PaperFormat::Xml);
+    }
+
+    pub fn serialize_references(&self, format: PaperFormat) -> String {
+        return serialize_related(&self.references, "references", "reference", format);
+    }
+
+    pub fn serialize_citations(&self, format: PaperFormat) -> String {
+        return serialize_related(&self.citations, "citations", "citation", format);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::document::{DocumentSection, Table};
+
+    /// Builds a `Paper` with only the fields a test cares about.
+    pub struct PaperBuilder {
+        paper: Paper,
+    }
+
+    impl PaperBuilder {
+        pub fn new(title: &str) -> PaperBuilder {
+            let mut paper = Paper::default();
+            paper.title = title.to_string();
+            return PaperBuilder { paper };
+        }
+
+        pub fn arxiv_id(mut self, arxiv_id: &str) -> PaperBuilder {
+            self.paper.arxiv_id = arxiv_id.to_string();
+            return self;
+        }
+
+        pub fn page_id(mut self, page_id: &str) -> PaperBuilder {
+            self.paper.page_id = page_id.to_string();
+            return self;
+        }
+
+        pub fn abstract_text(mut self, abstract_text: &str) -> PaperBuilder {
+            self.paper.abstract_text = abstract_text.to_string();
+            return self;
+        }
+
+        /// Appends a section with a single paragraph.
+        pub fn section(mut self, title: &str, content: &str) -> PaperBuilder {
+            let index = self.paper.document.sections.len();
+            self.paper.document.sections.push(DocumentSection::new(
+                index,
+                title,
+                vec![content.to_string()],
+            ));
+            return self;
+        }
+
+        pub fn table(mut self, table: Table) -> PaperBuilder {
+            self.paper.document.tables.push(table);
+            return self;
+        }
+
+        pub fn summary(mut self, summary: Summary) -> PaperBuilder {
+            self.paper.summary = summary;
+            return self;
+        }
+
+        pub fn build(self) -> Paper {
+            return self.paper;
         }
-        xml.push_str("</citations>");
-        return xml;
     }
 }
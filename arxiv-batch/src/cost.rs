@@ -0,0 +1,256 @@
+//! This module accounts the tokens used by the LLM and their cost.
+//! Prices are in USD per million tokens, matched by the longest prefix of the model ID.
+//! `LLM_PRICES` adds or overrides prices:
+//! "gpt-4o-mini=0.15/0.6,my-model=1/2" (input/output).
+use crate::llm::Usage;
+use dotenvy::dotenv;
+use serde::{Deserialize, Serialize};
+
+/// Built-in prices (input, output) in USD per million tokens (the longest matching prefix wins).
+const PRICES: [(&str, f64, f64); 11] = [
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("o1-mini", 1.10, 4.40),
+    ("o1", 15.00, 60.00),
+    ("o3-mini", 1.10, 4.40),
+    ("o3", 2.00, 8.00),
+];
+
+/// Share of the price charged for the requests of the Batch API.
+pub const BATCH_DISCOUNT: f64 = 0.5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Price {
+    pub model_prefix: String,
+    pub input: f64,
+    pub output: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct PriceTable {
+    prices: Vec<Price>,
+}
+
+impl PriceTable {
+    pub fn new() -> PriceTable {
+        dotenv().ok();
+        let mut table = PriceTable {
+            prices: PRICES
+                .iter()
+                .map(|(model_prefix, input, output)| Price {
+                    model_prefix: model_prefix.to_string(),
+                    input: *input,
+                    output: *output,
+                })
+                .collect(),
+        };
+        if let Ok(prices) = std::env::var("LLM_PRICES") {
+            for price in prices.split(',').filter(|x| !x.trim().is_empty()) {
+                match parse_price(price) {
+                    Some(price) => table.set(price),
+                    None => eprintln!("WARNING: Invalid price in LLM_PRICES: {}", price),
+                }
+            }
+        }
+        return table;
+    }
+
+    /// Add a price, replacing the price with the same prefix.
+    pub fn set(&mut self, price: Price) {
+        self.prices
+            .retain(|other| other.model_prefix != price.model_prefix);
+        self.prices.push(price);
+    }
+
+    /// The price with the longest prefix of `model_id`:
+    /// "gpt-4o-mini-2024-07-18" is priced as "gpt-4o-mini", not as "gpt-4o".
+    pub fn get(&self, model_id: &str) -> Option<&Price> {
+        return self
+            .prices
+            .iter()
+            .filter(|price| model_id.starts_with(&price.model_prefix))
+            .max_by_key(|price| price.model_prefix.len());
+    }
+
+    /// Cost of `usage` in USD (0 for unknown models).
+    pub fn cost(&self, model_id: &str, usage: &Usage) -> f64 {
+        return match self.get(model_id) {
+            Some(price) => {
+                (usage.prompt_tokens as f64 * price.input
+                    + usage.completion_tokens as f64 * price.output)
+                    / 1_000_000.0
+            }
+            None => 0.0,
+        };
+    }
+}
+
+/// "model=input/output"
+fn parse_price(text: &str) -> Option<Price> {
+    let (model_prefix, prices) = text.split_once('=')?;
+    let (input, output) = prices.split_once('/')?;
+    return Some(Price {
+        model_prefix: model_prefix.trim().to_string(),
+        input: input.trim().parse().ok()?,
+        output: output.trim().parse().ok()?,
+    });
+}
+
+/// Tokens and cost of the requests for a paper or a run.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub requests: u32,
+    /// Cost in USD
+    pub cost: f64,
+    /// Models that answered the requests
+    pub models: Vec<String>,
+}
+
+impl TokenUsage {
+    pub fn add(&mut self, model_id: &str, usage: &Usage, prices: &PriceTable) {
+        self.prompt_tokens += usage.prompt_tokens;
+        self.completion_tokens += usage.completion_tokens;
+        self.requests += 1;
+        self.cost += prices.cost(model_id, usage);
+        if !self.models.iter().any(|model| model == model_id) {
+            self.models.push(model_id.to_string());
+        }
+    }
+
+    /// A request sent through the Batch API, charged at `BATCH_DISCOUNT` of the price.
+    pub fn add_batch(&mut self, model_id: &str, usage: &Usage, prices: &PriceTable) {
+        self.add(model_id, usage, prices);
+        self.cost -= prices.cost(model_id, usage) * (1.0 - BATCH_DISCOUNT);
+    }
+
+    pub fn merge(&mut self, other: &TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.requests += other.requests;
+        self.cost += other.cost;
+        for model in other.models.iter() {
+            if !self.models.contains(model) {
+                self.models.push(model.clone());
+            }
+        }
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        return self.prompt_tokens + self.completion_tokens;
+    }
+}
+
+impl std::fmt::Display for TokenUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} tokens (prompt: {}, completion: {}) in {} requests, ${:.4} [{}]",
+            self.total_tokens(),
+            self.prompt_tokens,
+            self.completion_tokens,
+            self.requests,
+            self.cost,
+            self.models.join(", ")
+        )
+    }
+}
+
+/// Limits of a run; summarizing stops once one of them is reached.
+#[derive(Clone, Debug, Default)]
+pub struct Budget {
+    /// Maximum cost in USD
+    pub max_cost: Option<f64>,
+    pub max_tokens: Option<u64>,
+}
+
+impl Budget {
+    /// The reason why `usage` reached the budget, if it did.
+    pub fn exceeded(&self, usage: &TokenUsage) -> Option<String> {
+        if let Some(max_cost) = self.max_cost {
+            if usage.cost >= max_cost {
+                return Some(format!("cost ${:.4} >= ${:.4}", usage.cost, max_cost));
+            }
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            if usage.total_tokens() >= max_tokens {
+                return Some(format!("tokens {} >= {}", usage.total_tokens(), max_tokens));
+            }
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(prompt_tokens: u64, completion_tokens: u64) -> Usage {
+        return Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        };
+    }
+
+    #[test]
+    fn test_price_table() {
+        let mut prices = PriceTable::new();
+        prices.set(parse_price("gpt-4o-mini = 0.15 / 0.6").unwrap());
+        let cost = prices.cost("gpt-4o-mini-2024-07-18", &usage(1_000_000, 100_000));
+        assert!((cost - 0.21).abs() < 1e-9);
+        assert_eq!(prices.get("gpt-4o-2024-08-06").unwrap().input, 2.5);
+        assert_eq!(prices.cost("llama-3.1-8b", &usage(1_000, 1_000)), 0.0);
+
+        prices.set(parse_price("llama=1/2").unwrap());
+        assert!((prices.cost("llama-3.1-8b", &usage(1_000_000, 0)) - 1.0).abs() < 1e-9);
+        assert!(parse_price("llama=1").is_none());
+
+        // an override of a shorter prefix does not change the price of a longer one
+        prices.set(parse_price("gpt-4o=3/12").unwrap());
+        assert_eq!(prices.get("gpt-4o-2024-08-06").unwrap().input, 3.0);
+        assert_eq!(prices.get("gpt-4o-mini").unwrap().input, 0.15);
+        assert_eq!(prices.get("gpt-4o-mini-2024-07-18").unwrap().output, 0.6);
+    }
+
+    #[test]
+    fn test_token_usage_and_budget() {
+        let prices = PriceTable::new();
+        let mut paper = TokenUsage::default();
+        paper.add("gpt-4o", &usage(100_000, 10_000), &prices);
+        paper.add("gpt-4o", &usage(100_000, 10_000), &prices);
+        assert_eq!(paper.total_tokens(), 220_000);
+        assert_eq!(paper.requests, 2);
+        assert!((paper.cost - 0.7).abs() < 1e-9);
+
+        let mut batch = TokenUsage::default();
+        batch.add_batch("gpt-4o", &usage(100_000, 10_000), &prices);
+        assert!((batch.cost - 0.175).abs() < 1e-9);
+
+        let mut run = TokenUsage::default();
+        run.merge(&paper);
+        run.merge(&paper);
+        assert_eq!(run.models, vec!["gpt-4o"]);
+
+        let budget = Budget {
+            max_cost: Some(2.0),
+            max_tokens: None,
+        };
+        assert_eq!(budget.exceeded(&run), None);
+        run.merge(&paper);
+        assert_eq!(budget.exceeded(&run).unwrap(), "cost $2.1000 >= $2.0000");
+
+        let budget = Budget {
+            max_cost: None,
+            max_tokens: Some(100_000),
+        };
+        assert!(budget.exceeded(&paper).is_some());
+        assert!(Budget::default().exceeded(&run).is_none());
+    }
+}
@@ -0,0 +1,365 @@
+//! This module writes the digest of a day's batch: the summarized papers grouped by task,
+//! domain and keyword, and a short narrative of the trends written by the LLM.
+//! The digest is published as a Notion page (see `Reporter::add_digest`) and as a Markdown file.
+use crate::ai::AI;
+use crate::common::Paper;
+use crate::serializer::escape_xml;
+use crate::utils::notion_url;
+use anyhow::Result;
+use dotenvy::dotenv;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+/// Groups shown for each of the tasks, the domains and the keywords.
+const MAX_GROUPS: usize = 8;
+
+/// A group needs at least this many papers to be a trend.
+const MIN_GROUP_SIZE: usize = 2;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DigestPaper {
+    pub title: String,
+    /// Notion page of the paper, or its URL if it was not posted
+    pub url: String,
+    pub overview: String,
+    pub tasks: Vec<String>,
+    pub domains: Vec<String>,
+    pub keywords: Vec<String>,
+}
+
+impl DigestPaper {
+    pub fn from_paper(paper: &Paper) -> DigestPaper {
+        let url = if paper.page_id.is_empty() {
+            paper.url.clone()
+        } else {
+            notion_url(&paper.page_id)
+        };
+        DigestPaper {
+            title: paper.title.clone(),
+            url,
+            overview: paper.summary.overview.clone(),
+            tasks: paper.summary.task_as_vec(),
+            domains: paper.summary.domain_as_vec(),
+            keywords: paper
+                .keywords
+                .iter()
+                .map(|keyword| keyword.alias.clone())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DigestGroup {
+    /// "Task", "Domain" or "Keyword"
+    pub kind: String,
+    pub name: String,
+    /// Indices of the papers in `Digest.papers`
+    pub papers: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Digest {
+    /// "YYYY-MM-DD"
+    pub date: String,
+    pub papers: Vec<DigestPaper>,
+    pub groups: Vec<DigestGroup>,
+    /// Trends of the day written by the LLM; the papers are cited as [1], [2], ...
+    pub narrative: String,
+}
+
+impl Digest {
+    /// The digest of the summarized papers; the papers without a summary are left out.
+    pub fn from_papers(date: &str, papers: &[Paper]) -> Digest {
+        let papers = papers
+            .iter()
+            .filter(|paper| !paper.summary.overview.is_empty())
+            .map(DigestPaper::from_paper)
+            .collect::<Vec<DigestPaper>>();
+        let mut groups = Vec::new();
+        groups.extend(group_by("Task", &papers, |paper| &paper.tasks));
+        groups.extend(group_by("Domain", &papers, |paper| &paper.domains));
+        groups.extend(group_by("Keyword", &papers, |paper| &paper.keywords));
+        Digest {
+            date: date.to_string(),
+            papers,
+            groups,
+            narrative: String::new(),
+        }
+    }
+
+    pub fn title(&self) -> String {
+        return format!("arXiv digest {}", self.date);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.papers.is_empty();
+    }
+
+    /// The papers numbered from 1 and the groups, passed to the LLM.
+    pub fn papers_xml(&self) -> String {
+        let mut xml = String::from("<papers>");
+        for (i, paper) in self.papers.iter().enumerate() {
+            xml.push_str(
+                format!(
+                    "<paper number=\"{}\"><title>{}</title><overview>{}</overview></paper>",
+                    i + 1,
+                    escape_xml(&paper.title),
+                    escape_xml(&paper.overview)
+                )
+                .as_str(),
+            );
+        }
+        xml.push_str("</papers><groups>");
+        for group in self.groups.iter() {
+            xml.push_str(
+                format!(
+                    "<group kind=\"{}\" name=\"{}\">{}</group>",
+                    group.kind,
+                    escape_xml(&group.name),
+                    self.citations(&group.papers)
+                )
+                .as_str(),
+            );
+        }
+        xml.push_str("</groups>");
+        return xml;
+    }
+
+    /// Ask the LLM for the narrative of the trends.
+    pub async fn write_narrative(&mut self, ai: &AI) -> Result<()> {
+        let prompt = ai.prompt_template();
+        let messages = vec![
+            prompt.digest_system_message(),
+            prompt.digest_message(&self.date, &self.papers_xml()),
+        ];
+        self.narrative = ai.complete(messages).await?.trim().to_string();
+        return Ok(());
+    }
+
+    /// "[1] [3]"
+    pub fn citations(&self, papers: &[usize]) -> String {
+        return papers
+            .iter()
+            .map(|i| format!("[{}]", i + 1))
+            .collect::<Vec<String>>()
+            .join(" ");
+    }
+
+    /// Split `text` into pieces, linking the citations "[n]" to the papers.
+    pub fn link_citations<'a>(&'a self, text: &'a str) -> Vec<(&'a str, Option<&'a str>)> {
+        let citation_ptn = Regex::new(r"\[(\d+)\]").unwrap();
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        for captures in citation_ptn.captures_iter(text) {
+            let citation = captures.get(0).unwrap();
+            let paper = captures[1]
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| self.papers.get(number.wrapping_sub(1)))
+                .filter(|paper| !paper.url.is_empty());
+            if let Some(paper) = paper {
+                if start < citation.start() {
+                    pieces.push((&text[start..citation.start()], None));
+                }
+                pieces.push((citation.as_str(), Some(paper.url.as_str())));
+                start = citation.end();
+            }
+        }
+        if start < text.len() {
+            pieces.push((&text[start..], None));
+        }
+        return pieces;
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let linked = |text: &str| {
+            return self
+                .link_citations(text)
+                .iter()
+                .map(|(piece, url)| match url {
+                    Some(url) => format!("[{}]({})", piece, url),
+                    None => piece.to_string(),
+                })
+                .collect::<String>();
+        };
+
+        let mut markdown = format!("# {}\n\n", self.title());
+        if !self.narrative.is_empty() {
+            markdown.push_str("## Trends\n\n");
+            markdown.push_str(&linked(&self.narrative));
+            markdown.push_str("\n\n");
+        }
+        for kind in ["Task", "Domain", "Keyword"] {
+            let groups = self
+                .groups
+                .iter()
+                .filter(|group| group.kind == kind)
+                .collect::<Vec<&DigestGroup>>();
+            if groups.is_empty() {
+                continue;
+            }
+            markdown.push_str(&format!("## {}s\n\n", kind));
+            for group in groups {
+                markdown.push_str(&format!(
+                    "- {}: {}\n",
+                    group.name,
+                    linked(&self.citations(&group.papers))
+                ));
+            }
+            markdown.push('\n');
+        }
+        markdown.push_str("## Papers\n\n");
+        for (i, paper) in self.papers.iter().enumerate() {
+            if paper.url.is_empty() {
+                markdown.push_str(&format!("{}. {}\n", i + 1, paper.title));
+            } else {
+                markdown.push_str(&format!("{}. [{}]({})\n", i + 1, paper.title, paper.url));
+            }
+        }
+        return markdown;
+    }
+
+    /// Write the Markdown to `{dir}/{date}.md`, where `dir` is `DIGEST_DIR` or `{CACHE_DIR}/digests`.
+    pub fn save_markdown(&self) -> Result<PathBuf> {
+        dotenv().ok();
+        let dir = match std::env::var("DIGEST_DIR") {
+            Ok(dir) if !dir.trim().is_empty() => PathBuf::from(dir.trim()),
+            _ => {
+                let cache_dir = std::env::var("CACHE_DIR").unwrap_or(String::from(".cache"));
+                Path::new(&cache_dir).join("digests")
+            }
+        };
+        if !dir.exists() {
+            std::fs::create_dir_all(&dir)?;
+        }
+        let path = dir.join(format!("{}.md", self.date));
+        std::fs::write(&path, self.to_markdown())?;
+        return Ok(path);
+    }
+}
+
+/// Groups of the papers sharing a value (case-insensitive), the largest first.
+fn group_by(
+    kind: &str,
+    papers: &[DigestPaper],
+    values: impl Fn(&DigestPaper) -> &Vec<String>,
+) -> Vec<DigestGroup> {
+    let mut groups: Vec<DigestGroup> = Vec::new();
+    for (i, paper) in papers.iter().enumerate() {
+        for value in values(paper).iter() {
+            let name = value.trim();
+            if name.is_empty() {
+                continue;
+            }
+            match groups
+                .iter_mut()
+                .find(|group| group.name.to_lowercase() == name.to_lowercase())
+            {
+                Some(group) => {
+                    if !group.papers.contains(&i) {
+                        group.papers.push(i);
+                    }
+                }
+                None => groups.push(DigestGroup {
+                    kind: kind.to_string(),
+                    name: name.to_string(),
+                    papers: vec![i],
+                }),
+            }
+        }
+    }
+    groups.retain(|group| group.papers.len() >= MIN_GROUP_SIZE);
+    // stable sort: groups of the same size keep the order they appeared in
+    groups.sort_by_key(|group| std::cmp::Reverse(group.papers.len()));
+    groups.truncate(MAX_GROUPS);
+    return groups;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::PaperBuilder;
+    use crate::common::Summary;
+    use crate::utils::s;
+
+    fn papers() -> Vec<Paper> {
+        return vec![
+            PaperBuilder::new("Tiny Translator")
+                .page_id("aaaa-1111")
+                .summary(Summary {
+                    overview: s("Overview of Tiny Translator."),
+                    task_as_words: s("machine translation"),
+                    domain_as_words: s("news"),
+                    ..Default::default()
+                })
+                .build(),
+            PaperBuilder::new("Vision Model")
+                .page_id("bbbb-2222")
+                .summary(Summary {
+                    overview: s("Overview of Vision Model."),
+                    task_as_words: s("image classification"),
+                    domain_as_words: s("web images"),
+                    ..Default::default()
+                })
+                .build(),
+            PaperBuilder::new("Not Summarized").build(),
+            PaperBuilder::new("Big Translator")
+                .summary(Summary {
+                    overview: s("Overview of Big Translator."),
+                    task_as_words: s("Machine Translation, summarization"),
+                    domain_as_words: s("news"),
+                    ..Default::default()
+                })
+                .build(),
+        ];
+    }
+
+    #[test]
+    fn test_digest_groups() {
+        let digest = Digest::from_papers("2024-06-01", &papers());
+        assert_eq!(digest.papers.len(), 3);
+        assert_eq!(
+            digest.groups,
+            vec![
+                DigestGroup {
+                    kind: s("Task"),
+                    name: s("machine translation"),
+                    papers: vec![0, 2],
+                },
+                DigestGroup {
+                    kind: s("Domain"),
+                    name: s("news"),
+                    papers: vec![0, 2],
+                },
+            ]
+        );
+        assert!(digest
+            .papers_xml()
+            .contains(r#"<group kind="Task" name="machine translation">[1] [3]</group>"#));
+    }
+
+    #[tokio::test]
+    async fn test_digest_narrative_and_markdown() {
+        let (ai, mut requests) =
+            crate::llm::tests::stub_ai("Translation is back [1][3]; see also [9].").await;
+
+        let mut papers = papers();
+        papers[3].url = "https://arxiv.org/abs/2406.00001".to_string();
+        let mut digest = Digest::from_papers("2024-06-01", &papers);
+        digest.write_narrative(&ai).await.unwrap();
+        let raw = requests.recv().await.unwrap();
+        assert!(raw.contains("2024-06-01"));
+        assert!(raw.contains("Overview of Vision Model."));
+
+        let markdown = digest.to_markdown();
+        assert!(markdown.starts_with("# arXiv digest 2024-06-01\n\n## Trends\n\n"));
+        assert!(markdown.contains(
+            "Translation is back [[1]](https://www.notion.so/aaaa1111)[[3]](https://arxiv.org/abs/2406.00001); see also [9]."
+        ));
+        assert!(markdown.contains(
+            "## Tasks\n\n- machine translation: [[1]](https://www.notion.so/aaaa1111) [[3]]"
+        ));
+        assert!(markdown.contains("2. [Vision Model](https://www.notion.so/bbbb2222)\n"));
+    }
+}
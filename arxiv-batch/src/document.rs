@@ -0,0 +1,445 @@
+//! This module provides the ordered document model of a parsed paper.
+//! Every section is kept in document order, even if several sections share the same title.
+use crate::section::{bare_title, has_letter_numbering, section_level, SectionRole};
+use regex::Regex;
+use rsrpp::parser::structs::{Page, Section};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DocumentSection {
+    /// Position of the section in the document
+    pub index: usize,
+    pub title: String,
+    /// Hierarchy level inferred from the numbering: "3" -> 1, "3.1" -> 2
+    pub level: u8,
+    pub role: SectionRole,
+    /// First and last page of the section (0 if unknown)
+    pub page_start: i32,
+    pub page_end: i32,
+    pub contents: Vec<String>,
+}
+
+impl DocumentSection {
+    pub fn new(index: usize, title: &str, contents: Vec<String>) -> DocumentSection {
+        DocumentSection {
+            index,
+            title: title.to_string(),
+            level: section_level(title),
+            role: SectionRole::classify(title),
+            page_start: 0,
+            page_end: 0,
+            contents,
+        }
+    }
+
+    pub fn get_text(&self) -> String {
+        return self.contents.join("\n");
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Figure {
+    /// "Figure 1", "Figure 2", ...
+    pub label: String,
+    pub caption: String,
+    /// Index of the section the figure appears in
+    pub section: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Table {
+    /// "Table 1", "Table 2", ...
+    pub label: String,
+    pub caption: String,
+    /// Index of the section the table appears in
+    pub section: usize,
+    /// Cells of the table body (empty if the body could not be extracted)
+    pub rows: Vec<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Document {
+    pub sections: Vec<DocumentSection>,
+    #[serde(default = "Vec::new")]
+    pub figures: Vec<Figure>,
+    #[serde(default = "Vec::new")]
+    pub tables: Vec<Table>,
+}
+
+impl Document {
+    /// Build the document from the pages parsed by rsrpp.
+    /// A new section starts whenever the section of a block changes,
+    /// so repeated titles such as "Results" become separate sections.
+    /// Blocks starting with "Figure N:" or "Table N:" are moved to the figures and tables.
+    pub fn from_pages(pages: &Vec<Page>) -> Document {
+        let eos_ptn = Regex::new(r"(\.)(\W)").unwrap();
+        let ex_ws_ptn = Regex::new(r"\s+").unwrap();
+        let caption_ptn =
+            Regex::new(r"^(Figure|FIGURE|Fig\.|Table|TABLE)\s*([A-Z]?\d+)\s*[:.]\s*(.*)$").unwrap();
+
+        let mut sections: Vec<DocumentSection> = Vec::new();
+        let mut figures: Vec<Figure> = Vec::new();
+        let mut tables: Vec<Table> = Vec::new();
+        let mut last_text = String::new();
+        for page in pages {
+            let page_number = page.page_nubmer as i32;
+            for block in &page.blocks {
+                let mut text_block = block.get_text().trim().to_string();
+
+                // join the words hyphenated across blocks
+                if text_block.ends_with("-") {
+                    last_text.push_str(text_block.trim_end_matches("-"));
+                    continue;
+                }
+                if !last_text.is_empty() {
+                    last_text.push_str(&text_block);
+                    text_block = last_text.clone();
+                    last_text.clear();
+                }
+
+                text_block = eos_ptn.replace_all(&text_block, "$1 $2").to_string();
+                text_block = ex_ws_ptn.replace_all(&text_block, " ").to_string();
+
+                if let Some(caps) = caption_ptn.captures(&text_block) {
+                    let section = match sections.last() {
+                        Some(section) if section.title == block.section => section.index,
+                        _ => sections.len(),
+                    };
+                    if caps[1].to_lowercase().starts_with("fig") {
+                        figures.push(Figure {
+                            label: format!("Figure {}", &caps[2]),
+                            caption: caps[3].to_string(),
+                            section,
+                        });
+                    } else {
+                        tables.push(Table {
+                            label: format!("Table {}", &caps[2]),
+                            caption: caps[3].to_string(),
+                            section,
+                            rows: Vec::new(),
+                        });
+                    }
+                    continue;
+                }
+
+                match sections.last_mut() {
+                    Some(section) if section.title == block.section => {
+                        section.contents.push(text_block);
+                        section.page_end = page_number;
+                    }
+                    _ => {
+                        let mut section =
+                            DocumentSection::new(sections.len(), &block.section, vec![text_block]);
+                        section.page_start = page_number;
+                        section.page_end = page_number;
+                        sections.push(section);
+                    }
+                }
+            }
+        }
+        let mut document = Document {
+            sections,
+            figures,
+            tables,
+        };
+        document.resolve_roles();
+        return document;
+    }
+
+    /// Build the document from the sections of `rsrpp::parser::structs::Section::from_pages`.
+    pub fn from_sections(sections: &[Section]) -> Document {
+        let mut sections = sections.to_vec();
+        sections.sort_by_key(|section| section.index);
+        let mut document = Document {
+            sections: sections
+                .iter()
+                .enumerate()
+                .map(|(index, section)| {
+                    DocumentSection::new(index, &section.title, section.contents.clone())
+                })
+                .collect(),
+            ..Default::default()
+        };
+        document.resolve_roles();
+        return document;
+    }
+
+    /// Fill in what the titles alone do not tell.
+    /// A letter-numbered section is an appendix after the references or an appendix heading,
+    /// and a subsection before them ("A. Datasets" under "IV. EXPERIMENTS").
+    /// A section without a keyword takes the role of the nearest preceding section with a lower level.
+    pub fn resolve_roles(&mut self) {
+        let mut in_back_matter = false;
+        for index in 0..self.sections.len() {
+            let lettered = has_letter_numbering(&self.sections[index].title);
+            if lettered && !in_back_matter {
+                if let Some(parent) = self.sections[..index]
+                    .iter()
+                    .rev()
+                    .find(|section| !has_letter_numbering(&section.title))
+                {
+                    self.sections[index].level =
+                        parent.level + section_level(&self.sections[index].title);
+                }
+            }
+
+            if self.sections[index].role == SectionRole::Other {
+                let level = self.sections[index].level;
+                self.sections[index].role = if lettered && in_back_matter {
+                    SectionRole::Appendix
+                } else {
+                    self.sections[..index]
+                        .iter()
+                        .rev()
+                        .find(|section| section.level < level)
+                        .map(|section| section.role)
+                        .unwrap_or(SectionRole::Other)
+                };
+            }
+            if matches!(
+                self.sections[index].role,
+                SectionRole::References | SectionRole::Appendix
+            ) {
+                in_back_matter = true;
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        return self.sections.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.sections.is_empty();
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, DocumentSection> {
+        return self.sections.iter();
+    }
+
+    /// Sections whose title matches `title`, ignoring the case and the numbering:
+    /// "Results" matches "4 Results" and "RESULTS".
+    pub fn find_by_title(&self, title: &str) -> Vec<&DocumentSection> {
+        let title = bare_title(title);
+        return self
+            .sections
+            .iter()
+            .filter(|section| bare_title(&section.title) == title)
+            .collect();
+    }
+
+    pub fn find_by_role(&self, role: SectionRole) -> Vec<&DocumentSection> {
+        return self
+            .sections
+            .iter()
+            .filter(|section| section.role == role)
+            .collect();
+    }
+
+    pub fn filter_by_roles(&self, roles: &[SectionRole]) -> Vec<&DocumentSection> {
+        return self
+            .sections
+            .iter()
+            .filter(|section| roles.contains(&section.role))
+            .collect();
+    }
+
+    /// Concatenated text of the sections with the given role.
+    pub fn text_by_role(&self, role: SectionRole) -> String {
+        return self
+            .find_by_role(role)
+            .iter()
+            .map(|section| section.get_text())
+            .collect::<Vec<String>>()
+            .join("\n\n");
+    }
+
+    /// Figures that appear in the sections classified as one of `roles`.
+    /// Figures whose section is unknown are always included.
+    pub fn figures_by_roles(&self, roles: &[SectionRole]) -> Vec<&Figure> {
+        return self
+            .figures
+            .iter()
+            .filter(|figure| self.section_has_role(figure.section, roles))
+            .collect();
+    }
+
+    /// Tables that appear in the sections classified as one of `roles`.
+    /// Tables whose section is unknown are always included.
+    pub fn tables_by_roles(&self, roles: &[SectionRole]) -> Vec<&Table> {
+        return self
+            .tables
+            .iter()
+            .filter(|table| self.section_has_role(table.section, roles))
+            .collect();
+    }
+
+    fn section_has_role(&self, index: usize, roles: &[SectionRole]) -> bool {
+        return match self.sections.get(index) {
+            Some(section) => roles.contains(&section.role),
+            None => true,
+        };
+    }
+
+    pub fn get_text(&self) -> String {
+        return self
+            .sections
+            .iter()
+            .map(|section| section.get_text())
+            .collect::<Vec<String>>()
+            .join("\n\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsrpp::parser::structs::Block;
+
+    fn page(page_number: i8, blocks: Vec<(&str, &str)>) -> Page {
+        let mut page = Page::new(600.0, 800.0, page_number);
+        for (section, text) in blocks {
+            let mut block = Block::new(0.0, 0.0, 100.0, 10.0);
+            block.section = section.to_string();
+            block.add_line(0.0, 0.0, 100.0, 10.0);
+            block
+                .lines
+                .last_mut()
+                .unwrap()
+                .add_word(text.to_string(), 0.0, 0.0, 100.0, 10.0);
+            page.blocks.push(block);
+        }
+        return page;
+    }
+
+    #[test]
+    fn test_from_pages_keeps_duplicate_sections() {
+        let pages = vec![
+            page(
+                1,
+                vec![
+                    ("1 Introduction", "We study attention."),
+                    ("4 Results", "Results on WMT."),
+                ],
+            ),
+            page(
+                2,
+                vec![
+                    ("4 Results", "BLEU improves."),
+                    ("5 Analysis", "Heads specialize."),
+                    ("4 Results", "Results on parsing."),
+                ],
+            ),
+        ];
+        let document = Document::from_pages(&pages);
+        assert_eq!(document.len(), 4);
+
+        let results = document.find_by_title("Results");
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].contents.len(), 2);
+        assert_eq!((results[0].page_start, results[0].page_end), (1, 2));
+        assert_eq!(results[1].contents, vec!["Results on parsing."]);
+        assert_eq!(results[1].index, 3);
+
+        assert_eq!(document.find_by_role(SectionRole::Results).len(), 3);
+        assert_eq!(
+            document.text_by_role(SectionRole::Introduction),
+            "We study attention."
+        );
+    }
+
+    #[test]
+    fn test_from_pages_extracts_captions() {
+        let pages = vec![page(
+            3,
+            vec![
+                ("4 Results", "Our model improves BLEU."),
+                ("4 Results", "Table 2: BLEU scores on WMT14."),
+                ("4 Results", "Figure 3. Attention heads of layer 5."),
+                ("A Appendix", "Table A1: Hyperparameters."),
+                ("A Appendix", "Table 2 shows the results."),
+            ],
+        )];
+        let document = Document::from_pages(&pages);
+        assert_eq!(
+            document.sections[0].contents,
+            vec!["Our model improves BLEU."]
+        );
+        assert_eq!(
+            document.sections[1].contents,
+            vec!["Table 2 shows the results."]
+        );
+        assert_eq!(document.figures.len(), 1);
+        assert_eq!(document.figures[0].label, "Figure 3");
+        assert_eq!(document.figures[0].caption, "Attention heads of layer 5.");
+        assert_eq!(document.tables.len(), 2);
+        assert_eq!(document.tables[0].label, "Table 2");
+        assert_eq!(document.tables[0].caption, "BLEU scores on WMT14.");
+        assert_eq!(document.tables[1].section, 1);
+
+        let tables = document.tables_by_roles(&[SectionRole::Results]);
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].label, "Table 2");
+    }
+
+    #[test]
+    fn test_from_sections() {
+        let sections = vec![
+            Section {
+                index: 1,
+                title: "3.1 Encoder".to_string(),
+                contents: vec!["The encoder ...".to_string()],
+            },
+            Section {
+                index: 0,
+                title: "3 Model Architecture".to_string(),
+                contents: vec!["Most competitive ...".to_string()],
+            },
+        ];
+        let document = Document::from_sections(&sections);
+        assert_eq!(document.sections[0].title, "3 Model Architecture");
+        assert_eq!(document.sections[0].level, 1);
+        assert_eq!(document.sections[1].level, 2);
+        assert_eq!(document.sections[1].role, SectionRole::Method);
+    }
+
+    #[test]
+    fn test_resolve_roles() {
+        let titles = [
+            "IV. EXPERIMENTS",
+            "A. Datasets",
+            "B. Baselines",
+            "V. CONCLUSION",
+            "REFERENCES",
+            "A. Proof of Theorem 1",
+            "A.1 Lemmas",
+        ];
+        let mut document = Document {
+            sections: titles
+                .iter()
+                .enumerate()
+                .map(|(index, title)| DocumentSection::new(index, title, Vec::new()))
+                .collect(),
+            ..Default::default()
+        };
+        document.resolve_roles();
+        let roles = document
+            .iter()
+            .map(|section| section.role)
+            .collect::<Vec<SectionRole>>();
+        assert_eq!(
+            roles,
+            vec![
+                SectionRole::Experiments,
+                SectionRole::Experiments,
+                SectionRole::Experiments,
+                SectionRole::Conclusion,
+                SectionRole::References,
+                SectionRole::Appendix,
+                SectionRole::Appendix,
+            ]
+        );
+        assert_eq!(document.sections[1].level, 2);
+        assert_eq!(document.sections[5].level, 1);
+    }
+}
@@ -0,0 +1,413 @@
+//! This module embeds the processed papers and finds the papers similar to a query.
+//! The embeddings of the title, the abstract and the summary overview are requested from an
+//! OpenAI-compatible `/embeddings` endpoint and kept in a vector index next to the cache.
+use crate::common::Paper;
+use crate::llm::OPENAI_BASE_URL;
+use crate::utils::notion_url;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dotenvy::dotenv;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+/// Number of papers embedded in one request by `VectorIndex::add_papers`
+const EMBEDDING_BATCH_SIZE: usize = 64;
+
+/// A server with the OpenAI embeddings API: `{base_url}/embeddings`.
+#[derive(Debug, Clone)]
+pub struct EmbeddingClient {
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+impl EmbeddingClient {
+    /// `EMBEDDING_BASE_URL`, `EMBEDDING_API_KEY` and `EMBEDDING_MODEL`; the URL and the key
+    /// default to those of the LLM, and the model to `DEFAULT_EMBEDDING_MODEL`.
+    pub fn new() -> EmbeddingClient {
+        dotenv().ok();
+        let env = |key: &str| std::env::var(key).ok().filter(|x| !x.trim().is_empty());
+        let base_url = env("EMBEDDING_BASE_URL")
+            .or(env("LLM_BASE_URL"))
+            .unwrap_or(String::from(OPENAI_BASE_URL));
+        let api_key = env("EMBEDDING_API_KEY")
+            .or(env("LLM_API_KEY"))
+            .or(env("OPENAI_API_KEY"));
+        let model = env("EMBEDDING_MODEL").unwrap_or(String::from(DEFAULT_EMBEDDING_MODEL));
+        return EmbeddingClient::from_url(&base_url, api_key, &model);
+    }
+
+    pub fn from_url(base_url: &str, api_key: Option<String>, model: &str) -> EmbeddingClient {
+        EmbeddingClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model: model.to_string(),
+        }
+    }
+
+    /// Embeddings of `texts`, in the same order.
+    pub async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        #[derive(Deserialize)]
+        struct Embedding {
+            #[serde(default)]
+            index: usize,
+            embedding: Vec<f32>,
+        }
+        #[derive(Deserialize)]
+        struct Response {
+            data: Vec<Embedding>,
+        }
+
+        let url = format!("{}/embeddings", self.base_url);
+        let body = serde_json::json!({ "model": self.model, "input": texts });
+        let mut builder = reqwest::Client::new()
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+        if let Some(api_key) = self.api_key.as_ref() {
+            builder = builder.header("Authorization", format!("Bearer {}", api_key));
+        }
+        let response = builder.send().await?;
+        let status = response.status();
+        let content = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow::anyhow!(
+                "Embedding server returned {}: {}",
+                status,
+                content
+            ));
+        }
+        let mut response = serde_json::from_str::<Response>(&content)
+            .map_err(|e| anyhow::anyhow!("Failed to parse JSON: {} CONTENT: {}", e, content))?;
+        if response.data.len() != texts.len() {
+            return Err(anyhow::anyhow!(
+                "Expected {} embeddings, got {}",
+                texts.len(),
+                response.data.len()
+            ));
+        }
+        response.data.sort_by_key(|embedding| embedding.index);
+        return Ok(response
+            .data
+            .into_iter()
+            .map(|embedding| embedding.embedding)
+            .collect());
+    }
+}
+
+/// Text embedded for a paper: the title, the abstract and the overview of the summary.
+pub fn paper_text(paper: &Paper) -> String {
+    return [
+        paper.title.as_str(),
+        paper.abstract_text.as_str(),
+        paper.summary.overview.as_str(),
+    ]
+    .iter()
+    .filter(|text| !text.trim().is_empty())
+    .cloned()
+    .collect::<Vec<&str>>()
+    .join("\n\n");
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum::<f32>();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    return dot / (norm_a * norm_b);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddedPaper {
+    pub title: String,
+    pub arxiv_id: String,
+    pub ss_id: String,
+    /// ID of the Notion page of the paper
+    pub page_id: String,
+    /// Embedding model that produced the vector
+    pub model: String,
+    pub embedded_at: DateTime<Utc>,
+    pub vector: Vec<f32>,
+}
+
+impl EmbeddedPaper {
+    pub fn from_paper(paper: &Paper, model: &str, vector: Vec<f32>) -> EmbeddedPaper {
+        EmbeddedPaper {
+            title: paper.title.clone(),
+            arxiv_id: paper.arxiv_id.clone(),
+            ss_id: paper.ss_id.clone(),
+            page_id: paper.page_id.clone(),
+            model: model.to_string(),
+            embedded_at: Utc::now(),
+            vector,
+        }
+    }
+
+    /// Link to the Notion page of the paper (empty if it was not posted).
+    pub fn notion_url(&self) -> String {
+        if self.page_id.is_empty() {
+            return String::new();
+        }
+        return notion_url(&self.page_id);
+    }
+
+    fn is_same_paper(&self, other: &EmbeddedPaper) -> bool {
+        if !self.arxiv_id.is_empty() && self.arxiv_id == other.arxiv_id {
+            return true;
+        }
+        if !self.ss_id.is_empty() && self.ss_id == other.ss_id {
+            return true;
+        }
+        return self.title.to_lowercase() == other.title.to_lowercase();
+    }
+}
+
+/// Embeddings of the processed papers, stored as JSON in `{CACHE_DIR}/embeddings.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VectorIndex {
+    #[serde(skip_serializing, default = "PathBuf::default")]
+    pub path: PathBuf,
+    pub papers: Vec<EmbeddedPaper>,
+}
+
+impl VectorIndex {
+    pub fn new() -> VectorIndex {
+        dotenv().ok();
+        let cache_dir = std::env::var("CACHE_DIR").unwrap_or(String::from(".cache"));
+        return VectorIndex::from_path(&Path::new(&cache_dir).join("embeddings.json"));
+    }
+
+    pub fn from_path(path: &Path) -> VectorIndex {
+        VectorIndex {
+            path: path.to_path_buf(),
+            papers: Vec::new(),
+        }
+    }
+
+    /// Load the index from `VectorIndex::new().path`; an empty index if the file does not exist.
+    pub fn load() -> Result<VectorIndex> {
+        return VectorIndex::load_from(&VectorIndex::new().path);
+    }
+
+    pub fn load_from(path: &Path) -> Result<VectorIndex> {
+        if !path.exists() {
+            return Ok(VectorIndex::from_path(path));
+        }
+        let mut index = serde_json::from_str::<VectorIndex>(&std::fs::read_to_string(path)?)?;
+        index.path = path.to_path_buf();
+        return Ok(index);
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        std::fs::write(&self.path, serde_json::to_string(&self)?)?;
+        return Ok(());
+    }
+
+    pub fn len(&self) -> usize {
+        return self.papers.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.papers.is_empty();
+    }
+
+    /// Add a paper, replacing the previous embedding of the same paper.
+    pub fn upsert(&mut self, paper: EmbeddedPaper) {
+        self.papers.retain(|other| !other.is_same_paper(&paper));
+        self.papers.push(paper);
+    }
+
+    /// Embed the paper, add it to the index and save the index.
+    pub async fn add_paper(&mut self, client: &EmbeddingClient, paper: &Paper) -> Result<()> {
+        let vector = client
+            .embed(&[paper_text(paper)])
+            .await?
+            .pop()
+            .ok_or(anyhow::anyhow!("No embedding was returned"))?;
+        self.upsert(EmbeddedPaper::from_paper(paper, &client.model, vector));
+        return self.save();
+    }
+
+    /// Embed the papers in requests of `EMBEDDING_BATCH_SIZE` papers, add them to the index
+    /// and save the index after each request.
+    pub async fn add_papers(&mut self, client: &EmbeddingClient, papers: &[Paper]) -> Result<()> {
+        for chunk in papers.chunks(EMBEDDING_BATCH_SIZE) {
+            let texts = chunk.iter().map(paper_text).collect::<Vec<String>>();
+            let vectors = client.embed(&texts).await?;
+            for (paper, vector) in chunk.iter().zip(vectors) {
+                self.upsert(EmbeddedPaper::from_paper(paper, &client.model, vector));
+            }
+            self.save()?;
+        }
+        return Ok(());
+    }
+
+    /// Whether the paper has an embedding by `model`.
+    pub fn contains(&self, paper: &Paper, model: &str) -> bool {
+        let query = EmbeddedPaper::from_paper(paper, model, Vec::new());
+        return self
+            .papers
+            .iter()
+            .any(|other| other.model == model && other.is_same_paper(&query));
+    }
+
+    /// The stored paper with the arXiv ID or the title (case-insensitive).
+    pub fn find(&self, arxiv_id: Option<&str>, title: Option<&str>) -> Option<&EmbeddedPaper> {
+        return self.papers.iter().find(|paper| {
+            arxiv_id.map(|id| paper.arxiv_id == id).unwrap_or(false)
+                || title
+                    .map(|title| paper.title.to_lowercase() == title.trim().to_lowercase())
+                    .unwrap_or(false)
+        });
+    }
+
+    /// The `k` papers embedded by `model` most similar to `vector`, the most similar first.
+    pub fn search(&self, vector: &[f32], model: &str, k: usize) -> Vec<(&EmbeddedPaper, f32)> {
+        let mut scores = self
+            .papers
+            .iter()
+            .filter(|paper| paper.model == model && paper.vector.len() == vector.len())
+            .map(|paper| (paper, cosine_similarity(&paper.vector, vector)))
+            .collect::<Vec<(&EmbeddedPaper, f32)>>();
+        scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        scores.truncate(k);
+        return scores;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::PaperBuilder;
+    use crate::common::Summary;
+    use crate::utils::s;
+
+    #[test]
+    fn test_vector_index() {
+        let path = std::env::temp_dir().join("arxiv-batch-test-embeddings.json");
+        let _ = std::fs::remove_file(&path);
+        let mut index = VectorIndex::load_from(&path).unwrap();
+        assert!(index.is_empty());
+
+        let model = DEFAULT_EMBEDDING_MODEL;
+        index.upsert(EmbeddedPaper::from_paper(
+            &PaperBuilder::new("Transformers")
+                .arxiv_id("1706.03762")
+                .page_id("1234-abcd")
+                .build(),
+            model,
+            vec![1.0, 0.0],
+        ));
+        index.upsert(EmbeddedPaper::from_paper(
+            &PaperBuilder::new("ResNet")
+                .arxiv_id("1512.03385")
+                .page_id("1234-abcd")
+                .build(),
+            model,
+            vec![0.0, 1.0],
+        ));
+        index.upsert(EmbeddedPaper::from_paper(
+            &PaperBuilder::new("BERT")
+                .arxiv_id("1810.04805")
+                .page_id("1234-abcd")
+                .build(),
+            model,
+            vec![0.8, 0.2],
+        ));
+        index.upsert(EmbeddedPaper::from_paper(
+            &PaperBuilder::new("Old")
+                .arxiv_id("0000.00000")
+                .page_id("1234-abcd")
+                .build(),
+            "other-model",
+            vec![1.0, 0.0],
+        ));
+        // the same paper replaces its previous embedding
+        index.upsert(EmbeddedPaper::from_paper(
+            &PaperBuilder::new("transformers")
+                .arxiv_id("1706.03762")
+                .page_id("1234-abcd")
+                .build(),
+            model,
+            vec![0.9, 0.1],
+        ));
+        assert_eq!(index.len(), 4);
+        index.save().unwrap();
+
+        let index = VectorIndex::load_from(&path).unwrap();
+        let results = index.search(&[1.0, 0.0], model, 2);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0.title, "transformers");
+        assert_eq!(results[1].0.title, "BERT");
+        assert_eq!(results[0].0.notion_url(), "https://www.notion.so/1234abcd");
+        assert_eq!(
+            index.find(Some("1512.03385"), None).unwrap().title,
+            "ResNet"
+        );
+        assert_eq!(
+            index.find(None, Some(" bert ")).unwrap().arxiv_id,
+            "1810.04805"
+        );
+        assert!(index.find(Some("9999.99999"), None).is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_embedding_client() {
+        let body = serde_json::json!({
+            "object": "list",
+            "data": [
+                {"object": "embedding", "index": 1, "embedding": [0.0, 1.0]},
+                {"object": "embedding", "index": 0, "embedding": [1.0, 0.0]}
+            ],
+            "model": "local-embedding"
+        });
+        let (url, mut requests) = crate::llm::tests::stub_server(&body.to_string()).await;
+        let client = EmbeddingClient::from_url(&format!("{}/v1/", url), None, "local-embedding");
+        let vectors = client.embed(&[s("first"), s("second")]).await.unwrap();
+        assert_eq!(vectors, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+
+        let raw = requests.recv().await.unwrap();
+        assert!(raw.starts_with("POST /v1/embeddings"));
+        assert!(raw.contains(r#""model":"local-embedding""#));
+
+        let path = std::env::temp_dir().join("arxiv-batch-test-add-papers.json");
+        let _ = std::fs::remove_file(&path);
+        let mut index = VectorIndex::load_from(&path).unwrap();
+        let papers = vec![
+            PaperBuilder::new("Transformers")
+                .arxiv_id("1706.03762")
+                .build(),
+            PaperBuilder::new("ResNet").arxiv_id("1512.03385").build(),
+        ];
+        assert!(!index.contains(&papers[0], "local-embedding"));
+        index.add_papers(&client, &papers).await.unwrap();
+        assert!(index.contains(&papers[0], "local-embedding"));
+        assert!(!index.contains(&papers[0], DEFAULT_EMBEDDING_MODEL));
+        let index = VectorIndex::load_from(&path).unwrap();
+        assert_eq!(
+            index.find(Some("1512.03385"), None).unwrap().vector,
+            vec![0.0, 1.0]
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let paper = PaperBuilder::new("A Tiny Transformer")
+            .summary(Summary {
+                overview: "A transformer.".to_string(),
+                ..Default::default()
+            })
+            .build();
+        assert_eq!(paper_text(&paper), "A Tiny Transformer\n\nA transformer.");
+    }
+}
@@ -0,0 +1,320 @@
+//! This module checks that the summary is supported by the paper.
+//! The numbers, dataset names and method names in the summary fields are looked up in the
+//! text of the paper, and those not found are reported as unsupported claims.
+use crate::common::Paper;
+use crate::schema::SummarySchema;
+use dotenvy::dotenv;
+use regex::Regex;
+
+/// Abbreviations used in summaries that are not names of datasets or methods.
+const GENERIC_NAMES: [&str; 8] = ["AI", "ML", "NLP", "CV", "LLM", "LLMs", "SOTA", "API"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClaimKind {
+    Number,
+    Dataset,
+    Method,
+}
+
+impl ClaimKind {
+    pub fn as_str(&self) -> &str {
+        return match self {
+            ClaimKind::Number => "number",
+            ClaimKind::Dataset => "dataset",
+            ClaimKind::Method => "method",
+        };
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Claim {
+    /// Summary field the claim was found in
+    pub field: String,
+    pub kind: ClaimKind,
+    pub text: String,
+}
+
+impl std::fmt::Display for Claim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} \"{}\" ({})",
+            self.kind.as_str(),
+            self.text,
+            self.field
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaithfulnessReport {
+    /// Number of the claims looked up in the paper
+    pub checked: usize,
+    /// Claims not found in the paper
+    pub unsupported: Vec<Claim>,
+}
+
+impl FaithfulnessReport {
+    pub fn passed(&self) -> bool {
+        return self.unsupported.is_empty();
+    }
+
+    pub fn reason(&self) -> String {
+        return self.claims().join("; ");
+    }
+
+    pub fn claims(&self) -> Vec<String> {
+        return self
+            .unsupported
+            .iter()
+            .map(|claim| claim.to_string())
+            .collect();
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FaithfulnessChecker {
+    /// Summarize a paper again when its summary has unsupported claims
+    pub resummarize: bool,
+    number_ptn: Regex,
+    name_ptn: Regex,
+}
+
+impl FaithfulnessChecker {
+    pub fn new() -> FaithfulnessChecker {
+        dotenv().ok();
+        let resummarize = std::env::var("RESUMMARIZE_UNFAITHFUL")
+            .map(|x| matches!(x.trim().to_lowercase().as_str(), "true" | "1" | "yes"))
+            .unwrap_or(false);
+        FaithfulnessChecker {
+            resummarize,
+            number_ptn: Regex::new(r"[0-9]+(?:,[0-9]{3})*(?:\.[0-9]+)?").unwrap(),
+            name_ptn: Regex::new(r"[A-Za-z][A-Za-z0-9]*(?:[-.][A-Za-z0-9]+)*").unwrap(),
+        }
+    }
+
+    pub fn resummarize(&mut self, resummarize: bool) -> &mut Self {
+        self.resummarize = resummarize;
+        return self;
+    }
+
+    /// Look up the claims of each summary field in the paper.
+    pub fn check(&self, paper: &Paper, schema: &SummarySchema) -> FaithfulnessReport {
+        let source = Source::new(self, paper);
+        let mut claims: Vec<Claim> = Vec::new();
+        for field in schema.fields.iter() {
+            let dataset_field = field.name.starts_with("dataset");
+            match field.type_name.as_str() {
+                "boolean" => continue,
+                "array" => {
+                    let columns = field.item_names();
+                    for row in paper.summary.rows(&field.name, &columns) {
+                        for (column, cell) in columns.iter().zip(row.iter()) {
+                            let dataset = dataset_field || column.contains("dataset");
+                            self.extract(&field.name, cell, dataset, &mut claims);
+                        }
+                    }
+                }
+                _ => {
+                    let text = paper.summary.text(&field.name);
+                    self.extract(&field.name, &text, dataset_field, &mut claims);
+                }
+            }
+        }
+
+        let mut report = FaithfulnessReport {
+            checked: claims.len(),
+            unsupported: Vec::new(),
+        };
+        for claim in claims {
+            if !source.supports(&claim) {
+                report.unsupported.push(claim);
+            }
+        }
+        return report;
+    }
+
+    /// Add the numbers and the names in `text` that are not in `claims` yet.
+    fn extract(&self, field: &str, text: &str, dataset: bool, claims: &mut Vec<Claim>) {
+        let mut found = Vec::new();
+        for number in self.number_ptn.find_iter(text) {
+            // small integers are counts and section numbers rather than results
+            let number = number.as_str();
+            if number.contains('.') || number.replace(',', "").len() > 1 {
+                found.push((ClaimKind::Number, number));
+            }
+        }
+        let kind = if dataset {
+            ClaimKind::Dataset
+        } else {
+            ClaimKind::Method
+        };
+        for name in self.name_ptn.find_iter(text) {
+            if is_name(name.as_str()) {
+                found.push((kind, name.as_str()));
+            }
+        }
+        for (kind, text) in found {
+            if !claims
+                .iter()
+                .any(|claim| claim.kind == kind && claim.text == text)
+            {
+                claims.push(Claim {
+                    field: field.to_string(),
+                    kind,
+                    text: text.to_string(),
+                });
+            }
+        }
+    }
+}
+
+/// Whether a word looks like the name of a dataset or a method: "ImageNet", "BERT", "ResNet-50".
+fn is_name(word: &str) -> bool {
+    if word.len() < 2 || GENERIC_NAMES.contains(&word) {
+        return false;
+    }
+    let uppercase = word.chars().filter(|c| c.is_ascii_uppercase()).count();
+    let digits = word.chars().any(|c| c.is_ascii_digit());
+    return uppercase >= 2 || (uppercase >= 1 && digits);
+}
+
+/// Lowercase letters and digits only, so that "ResNet-50" matches "resnet50".
+fn compact(text: &str) -> String {
+    return text
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect();
+}
+
+/// Numbers as written and precision: "27.3" -> (27.3, 0.05)
+fn parse_number(text: &str) -> Option<(f64, f64)> {
+    let text = text.replace(',', "");
+    let decimals = text.split_once('.').map(|(_, x)| x.len()).unwrap_or(0);
+    let value = text.parse::<f64>().ok()?;
+    return Some((value, 0.5 * 10f64.powi(-(decimals as i32)) + 1e-9));
+}
+
+/// The text of the paper prepared for the lookups.
+struct Source {
+    compact_text: String,
+    numbers: Vec<f64>,
+}
+
+impl Source {
+    fn new(checker: &FaithfulnessChecker, paper: &Paper) -> Source {
+        let mut text = vec![
+            paper.title.clone(),
+            paper.abstract_text.clone(),
+            paper.document.get_text(),
+        ];
+        for figure in paper.document.figures.iter() {
+            text.push(figure.caption.clone());
+        }
+        for table in paper.document.tables.iter() {
+            text.push(table.caption.clone());
+            for row in table.rows.iter() {
+                text.push(row.join(" "));
+            }
+        }
+        for reference in paper.references.iter() {
+            text.push(reference.title.clone());
+        }
+        let text = text.join("\n");
+        let numbers = checker
+            .number_ptn
+            .find_iter(&text)
+            .filter_map(|number| parse_number(number.as_str()).map(|(value, _)| value))
+            .collect();
+        Source {
+            compact_text: compact(&text),
+            numbers,
+        }
+    }
+
+    /// A number is supported when the paper has it, rounded or as a percentage
+    /// (27.3 for 27.34 or 0.273); a name when the paper mentions it.
+    fn supports(&self, claim: &Claim) -> bool {
+        if claim.kind != ClaimKind::Number {
+            return self.compact_text.contains(&compact(&claim.text));
+        }
+        let (value, tolerance) = match parse_number(&claim.text) {
+            Some(number) => number,
+            None => return true,
+        };
+        return self.numbers.iter().any(|number| {
+            (number - value).abs() <= tolerance
+                || (number * 100.0 - value).abs() <= tolerance
+                || (number / 100.0 - value).abs() <= tolerance
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::PaperBuilder;
+    use crate::common::KeyResult;
+    use crate::document::Table;
+
+    fn paper() -> Paper {
+        return PaperBuilder::new("A Tiny Transformer")
+            .section(
+                "1 Introduction",
+                "TinyFormer is a small transformer for machine translation.",
+            )
+            .section(
+                "4 Experiments",
+                "We train on WMT14 En-De with 4.5M sentence pairs and compare with ResNet-50.",
+            )
+            .table(Table {
+                label: "Table 1".to_string(),
+                caption: "BLEU scores.".to_string(),
+                section: 1,
+                rows: vec![vec![
+                    "TinyFormer".to_string(),
+                    "27.34".to_string(),
+                    "0.912".to_string(),
+                ]],
+            })
+            .build();
+    }
+
+    #[test]
+    fn test_faithful_summary() {
+        let schema = SummarySchema::from_env().unwrap();
+        let checker = FaithfulnessChecker::new();
+        let mut paper = paper();
+        paper.summary.overview = "TinyFormerはResNet50と比較し、BLEU 27.3を達成した。".to_string();
+        paper.summary.datasets = "WMT14 En-De (4.5M文対)".to_string();
+        paper.summary.experiments = "精度は91.2%、3種類の設定で評価。".to_string();
+        paper.summary.results = vec![KeyResult {
+            dataset: "WMT14 En-De".to_string(),
+            metric: "BLEU".to_string(),
+            value: "27.3".to_string(),
+            baseline: String::new(),
+        }];
+        let report = checker.check(&paper, &schema);
+        assert!(report.passed(), "{}", report.reason());
+        assert!(report.checked >= 6);
+    }
+
+    #[test]
+    fn test_unsupported_claims() {
+        let schema = SummarySchema::from_env().unwrap();
+        let checker = FaithfulnessChecker::new();
+        let mut paper = paper();
+        paper.summary.overview = "TinyFormerはBLEU 28.4を達成し、SOTAを更新した。".to_string();
+        paper.summary.datasets = "WMT14, ImageNet".to_string();
+        let report = checker.check(&paper, &schema);
+        assert_eq!(
+            report.claims(),
+            vec![
+                "number \"28.4\" (overview)",
+                "dataset \"ImageNet\" (datasets)"
+            ]
+        );
+        assert!(!report.passed());
+    }
+}
@@ -0,0 +1,940 @@
+//! This module extracts the original text of arXiv papers from their LaTeX source (e-print).
+//! The LaTeX source keeps math, tables and multi-column layouts intact,
+//! so it is preferred over the PDF whenever it is available.
+use crate::document::{Document, DocumentSection, Figure, Table};
+use crate::section::SectionRole;
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use fxhash::FxHashMap;
+use regex::Regex;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MATH_ENVIRONMENTS: [&str; 7] = [
+    "equation",
+    "align",
+    "gather",
+    "multline",
+    "eqnarray",
+    "displaymath",
+    "math",
+];
+
+/// Environments removed from the running text.
+const SKIPPED_ENVIRONMENTS: [&str; 2] = ["thebibliography", "comment"];
+
+/// Float environments, moved from the running text to the figures and tables of the document.
+const FLOAT_ENVIRONMENTS: [&str; 4] = ["figure", "wrapfigure", "table", "wraptable"];
+
+/// Commands drawing the rules of a table.
+const RULE_COMMANDS: [&str; 6] = [
+    "hline",
+    "toprule",
+    "midrule",
+    "bottomrule",
+    "cline",
+    "cmidrule",
+];
+
+const MAX_INPUT_DEPTH: usize = 10;
+
+/// Time limit of an e-print download; the PDF is parsed instead once it runs out.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Title, level and whether the heading is in the appendix.
+type Heading = (String, u8, bool);
+
+#[derive(Clone, Debug, Default)]
+pub struct LatexSource {
+    /// Text files in the source archive: relative path -> content
+    pub files: FxHashMap<String, String>,
+}
+
+/// Downloads e-prints from arXiv, waiting `wait_time` between the downloads
+/// so that a run does not send its requests to arxiv.org back to back.
+/// Clones share the time of the last download.
+#[derive(Clone, Debug)]
+pub struct EprintDownloader {
+    base_url: String,
+    timeout: Duration,
+    wait_time: Duration,
+    last_download: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Default for EprintDownloader {
+    fn default() -> Self {
+        return EprintDownloader::new(0);
+    }
+}
+
+impl EprintDownloader {
+    /// `wait_time` is in seconds, as the `--wait-time` of the collector.
+    pub fn new(wait_time: u64) -> EprintDownloader {
+        EprintDownloader {
+            base_url: String::from("https://arxiv.org/e-print"),
+            timeout: DOWNLOAD_TIMEOUT,
+            wait_time: Duration::from_secs(wait_time),
+            last_download: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        return self;
+    }
+
+    pub fn eprint_url(&self, arxiv_id: &str) -> String {
+        return format!("{}/{}", self.base_url.trim_end_matches('/'), arxiv_id);
+    }
+
+    /// Download the e-print source of an arXiv paper.
+    pub async fn download(&self, arxiv_id: &str) -> Result<LatexSource> {
+        self.wait().await;
+        let client = reqwest::Client::builder().timeout(self.timeout).build()?;
+        let response = client
+            .get(self.eprint_url(arxiv_id))
+            .header("User-Agent", "arxiv-batch")
+            .send()
+            .await?
+            .error_for_status()?;
+        let bytes = response.bytes().await?;
+        return LatexSource::from_bytes(&bytes);
+    }
+
+    /// Wait until `wait_time` has passed since the previous download.
+    async fn wait(&self) {
+        let wait = {
+            let mut last_download = self.last_download.lock().unwrap();
+            let wait = match *last_download {
+                Some(last) => self.wait_time.saturating_sub(last.elapsed()),
+                None => Duration::ZERO,
+            };
+            *last_download = Some(Instant::now() + wait);
+            wait
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Whether the download failed because it ran out of time.
+pub fn is_timeout(error: &anyhow::Error) -> bool {
+    return error
+        .downcast_ref::<reqwest::Error>()
+        .map(|e| e.is_timeout())
+        .unwrap_or(false);
+}
+
+impl LatexSource {
+    pub fn from_tarball(path: &Path) -> Result<LatexSource> {
+        let bytes = std::fs::read(path)?;
+        return Self::from_bytes(&bytes);
+    }
+
+    /// Unpack an e-print: a gzipped tarball, a gzipped single TeX file or a plain TeX file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<LatexSource> {
+        let bytes = if bytes.starts_with(&[0x1f, 0x8b]) {
+            let mut decoded = Vec::new();
+            GzDecoder::new(bytes).read_to_end(&mut decoded)?;
+            decoded
+        } else {
+            bytes.to_vec()
+        };
+        if bytes.starts_with(b"%PDF") {
+            return Err(anyhow::anyhow!(
+                "No LaTeX source available: e-print is a PDF"
+            ));
+        }
+
+        let mut files = FxHashMap::default();
+        let is_tar = bytes.len() > 262 && &bytes[257..262] == b"ustar";
+        if is_tar {
+            let mut archive = tar::Archive::new(bytes.as_slice());
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let path = entry.path()?.to_string_lossy().to_string();
+                let path = path.trim_start_matches("./").to_string();
+                if !(path.ends_with(".tex") || path.ends_with(".bbl")) {
+                    continue;
+                }
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content)?;
+                files.insert(path, String::from_utf8_lossy(&content).to_string());
+            }
+        } else {
+            files.insert(
+                String::from("main.tex"),
+                String::from_utf8_lossy(&bytes).to_string(),
+            );
+        }
+
+        if files.is_empty() {
+            return Err(anyhow::anyhow!("No TeX files found in the e-print"));
+        }
+        return Ok(LatexSource { files });
+    }
+
+    /// The root TeX file: the one with `\documentclass` and `\begin{document}`.
+    pub fn main_file(&self) -> Option<String> {
+        let mut candidates = self
+            .files
+            .iter()
+            .filter(|(path, content)| {
+                path.ends_with(".tex")
+                    && content.contains("\\documentclass")
+                    && content.contains("\\begin{document}")
+            })
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<String>>();
+        candidates.sort_by_key(|path| (path.matches('/').count(), path.clone()));
+        if let Some(main) = candidates
+            .iter()
+            .find(|path| path.ends_with("main.tex") || path.ends_with("ms.tex"))
+        {
+            return Some(main.clone());
+        }
+        return candidates.first().cloned();
+    }
+
+    /// The content of the root file with comments removed and `\input`/`\include` expanded.
+    pub fn resolve(&self) -> Result<String> {
+        let main = self
+            .main_file()
+            .ok_or(anyhow::anyhow!("No main TeX file found"))?;
+        let dir = match main.rsplit_once('/') {
+            Some((dir, _)) => format!("{}/", dir),
+            None => String::new(),
+        };
+        return Ok(self.expand(&main, &dir, 0));
+    }
+
+    /// Find an `\input` target relative to the directory of the root file.
+    fn find_file(&self, name: &str, dir: &str) -> Option<String> {
+        let name = name.trim().trim_start_matches("./");
+        return [
+            format!("{}{}", dir, name),
+            format!("{}{}.tex", dir, name),
+            name.to_string(),
+            format!("{}.tex", name),
+        ]
+        .into_iter()
+        .find(|candidate| self.files.contains_key(candidate));
+    }
+
+    fn expand(&self, path: &str, dir: &str, depth: usize) -> String {
+        let content = match self.files.get(path) {
+            Some(content) => strip_comments(content),
+            None => return String::new(),
+        };
+        if depth >= MAX_INPUT_DEPTH {
+            return content;
+        }
+        let input_ptn = Regex::new(r"\\(?:input|include|subfile)\s*\{([^}]+)\}").unwrap();
+        return input_ptn
+            .replace_all(&content, |caps: &regex::Captures| {
+                match self.find_file(&caps[1], dir) {
+                    Some(path) => self.expand(&path, dir, depth + 1),
+                    None => String::new(),
+                }
+            })
+            .to_string();
+    }
+
+    /// Convert the LaTeX source into the same section structure as the parsed PDF.
+    pub fn to_document(&self) -> Result<Document> {
+        let source = self.resolve()?;
+        let body = match (
+            source.find("\\begin{document}"),
+            source.find("\\end{document}"),
+        ) {
+            (Some(start), Some(end)) if start < end => {
+                &source[start + "\\begin{document}".len()..end]
+            }
+            (Some(start), None) => &source[start + "\\begin{document}".len()..],
+            _ => source.as_str(),
+        };
+        let mut body = body.to_string();
+
+        let mut sections: Vec<DocumentSection> = Vec::new();
+
+        // abstract
+        let abstract_ptn = Regex::new(r"(?s)\\begin\{abstract\}(.*?)\\end\{abstract\}").unwrap();
+        if let Some(caps) = abstract_ptn.captures(&body) {
+            let contents = paragraphs(&caps[1]);
+            if !contents.is_empty() {
+                sections.push(DocumentSection::new(0, "Abstract", contents));
+            }
+        }
+        body = abstract_ptn.replace_all(&body, "\n\n").to_string();
+        for env in SKIPPED_ENVIRONMENTS {
+            body = remove_environment(&body, env);
+        }
+
+        // sections
+        let heading_ptn =
+            Regex::new(r"\\(appendix\b|(?:sub){0,2}section\b\*?|chapter\b\*?)").unwrap();
+        let mut counters = [0usize; 3];
+        let mut in_appendix = false;
+        let mut current: Option<Heading> = None;
+        let mut position = 0;
+        let mut chunks: Vec<(Option<Heading>, String)> = Vec::new();
+        while let Some(m) = heading_ptn.find_at(&body, position) {
+            chunks.push((current.clone(), body[position..m.start()].to_string()));
+            let command = m.as_str().trim_start_matches('\\');
+            if command == "appendix" {
+                in_appendix = true;
+                counters = [0; 3];
+                current = None;
+                position = m.end();
+                continue;
+            }
+
+            let starred = command.ends_with('*');
+            let level = match command.trim_end_matches('*') {
+                "subsubsection" => 3,
+                "subsection" => 2,
+                _ => 1,
+            };
+            let (title, end) = match read_argument(&body, m.end()) {
+                Some(argument) => argument,
+                None => {
+                    position = m.end();
+                    continue;
+                }
+            };
+            let title = latex_to_text(&title);
+            let title = if starred {
+                title
+            } else {
+                counters[level - 1] += 1;
+                for counter in counters.iter_mut().skip(level) {
+                    *counter = 0;
+                }
+                let mut numbering = counters[..level]
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<String>>();
+                if in_appendix {
+                    // a subsection right after \appendix belongs to appendix A
+                    let letter = b'A' + (counters[0].saturating_sub(1) % 26) as u8;
+                    numbering[0] = (letter as char).to_string();
+                }
+                format!("{} {}", numbering.join("."), title)
+            };
+            current = Some((title, level as u8, in_appendix));
+            position = end;
+        }
+        chunks.push((current.clone(), body[position..].to_string()));
+
+        let has_headings = chunks.iter().any(|(heading, _)| heading.is_some());
+        let mut figures: Vec<Figure> = Vec::new();
+        let mut tables: Vec<Table> = Vec::new();
+        for (heading, chunk) in chunks {
+            let chunk = extract_floats(&chunk, sections.len(), &mut figures, &mut tables);
+            let contents = paragraphs(&chunk);
+            match heading {
+                Some((title, level, appendix)) => {
+                    let mut section = DocumentSection::new(sections.len(), &title, contents);
+                    section.level = level;
+                    if appendix {
+                        section.role = SectionRole::Appendix;
+                    }
+                    sections.push(section);
+                }
+                None => {
+                    // text before the first heading is usually the title block
+                    if !has_headings && !contents.is_empty() {
+                        sections.push(DocumentSection::new(sections.len(), "", contents));
+                    }
+                }
+            }
+        }
+
+        if sections.is_empty() {
+            return Err(anyhow::anyhow!("No text found in the LaTeX source"));
+        }
+        let mut document = Document {
+            sections,
+            figures,
+            tables,
+        };
+        document.resolve_roles();
+        return Ok(document);
+    }
+}
+
+/// Remove `%` comments, keeping escaped `\%`.
+pub fn strip_comments(text: &str) -> String {
+    return text
+        .lines()
+        .map(|line| {
+            let mut escaped = false;
+            for (i, c) in line.char_indices() {
+                if c == '%' && !escaped {
+                    return &line[..i];
+                }
+                escaped = c == '\\' && !escaped;
+            }
+            line
+        })
+        .collect::<Vec<&str>>()
+        .join("\n");
+}
+
+/// Remove `\begin{env}...\end{env}` (and the starred variant) from the text.
+pub fn remove_environment(text: &str, env: &str) -> String {
+    let ptn = Regex::new(&format!(
+        r"(?s)\\begin\{{{env}(\*?)\}}.*?\\end\{{{env}\*?\}}",
+        env = regex::escape(env)
+    ))
+    .unwrap();
+    return ptn.replace_all(text, "\n\n").to_string();
+}
+
+/// Move the figure and table environments of `text` into `figures` and `tables`
+/// and return the text without them. Floats are numbered in the order of appearance.
+fn extract_floats(
+    text: &str,
+    section: usize,
+    figures: &mut Vec<Figure>,
+    tables: &mut Vec<Table>,
+) -> String {
+    let float_ptn = Regex::new(&format!(
+        r"(?s)\\begin\{{({envs})\*?\}}(.*?)\\end\{{(?:{envs})\*?\}}",
+        envs = FLOAT_ENVIRONMENTS.join("|")
+    ))
+    .unwrap();
+    return float_ptn
+        .replace_all(text, |caps: &regex::Captures| {
+            let caption = match caps[2].rfind("\\caption") {
+                Some(start) => read_argument(&caps[2], start + "\\caption".len())
+                    .map(|(caption, _)| latex_to_text(&caption))
+                    .unwrap_or_default(),
+                None => String::new(),
+            };
+            if caps[1].ends_with("figure") {
+                if !caption.is_empty() {
+                    figures.push(Figure {
+                        label: format!("Figure {}", figures.len() + 1),
+                        caption,
+                        section,
+                    });
+                }
+            } else {
+                let rows = tabular_rows(&caps[2]);
+                if !caption.is_empty() || !rows.is_empty() {
+                    tables.push(Table {
+                        label: format!("Table {}", tables.len() + 1),
+                        caption,
+                        section,
+                        rows,
+                    });
+                }
+            }
+            return String::from("\n\n");
+        })
+        .to_string();
+}
+
+/// Extract the cells of the first `tabular` (or `tabular*`, `tabularx`) environment.
+pub fn tabular_rows(text: &str) -> Vec<Vec<String>> {
+    let tabular_ptn =
+        Regex::new(r"(?s)\\begin\{(tabular\*?|tabularx)\}(.*?)\\end\{(?:tabular\*?|tabularx)\}")
+            .unwrap();
+    let caps = match tabular_ptn.captures(text) {
+        Some(caps) => caps,
+        None => return Vec::new(),
+    };
+
+    // skip the column specification, preceded by the width for tabular* and tabularx
+    let mut body = caps[2].to_string();
+    let n_arguments = if &caps[1] == "tabular" { 1 } else { 2 };
+    for _ in 0..n_arguments {
+        if let Some((_, end)) = read_argument(&body, 0) {
+            body = body[end..].to_string();
+        }
+    }
+
+    let rule_ptn = Regex::new(&format!(
+        r"\\(?:{})\b(?:\([^)]*\))?(?:\{{[^}}]*\}})?",
+        RULE_COMMANDS.join("|")
+    ))
+    .unwrap();
+    let body = rule_ptn.replace_all(&body, "");
+    let row_ptn = Regex::new(r"\\\\(?:\[[^\]]*\])?").unwrap();
+    return row_ptn
+        .split(&body)
+        .map(|row| {
+            split_cells(row)
+                .iter()
+                .map(|cell| latex_to_text(cell))
+                .collect::<Vec<String>>()
+        })
+        .filter(|cells| cells.iter().any(|cell| !cell.is_empty()))
+        .collect();
+}
+
+/// Split a table row on `&`, keeping escaped `\&`.
+fn split_cells(row: &str) -> Vec<&str> {
+    let mut cells = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in row.char_indices() {
+        if c == '&' && !escaped {
+            cells.push(&row[start..i]);
+            start = i + 1;
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    cells.push(&row[start..]);
+    return cells;
+}
+
+/// Read a `{...}` argument starting at `start`, skipping whitespace and an optional `[...]`.
+/// Returns the content of the braces and the position after the closing brace.
+pub fn read_argument(text: &str, start: usize) -> Option<(String, usize)> {
+    let bytes = text.as_bytes();
+    let mut i = start;
+    while i < bytes.len() && (bytes[i] as char).is_whitespace() {
+        i += 1;
+    }
+    if i < bytes.len() && bytes[i] == b'[' {
+        while i < bytes.len() && bytes[i] != b']' {
+            i += 1;
+        }
+        i += 1;
+        while i < bytes.len() && (bytes[i] as char).is_whitespace() {
+            i += 1;
+        }
+    }
+    if i >= bytes.len() || bytes[i] != b'{' {
+        return None;
+    }
+    let mut depth = 0;
+    let content_start = i + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((text[content_start..i].to_string(), i + 1));
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    return None;
+}
+
+/// Split LaTeX text into paragraphs of plain text.
+pub fn paragraphs(text: &str) -> Vec<String> {
+    let blank_ptn = Regex::new(r"\n\s*\n").unwrap();
+    return blank_ptn
+        .split(text)
+        .map(latex_to_text)
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect();
+}
+
+/// Convert LaTeX markup into plain text.
+/// Math is kept as LaTeX (`$...$`, `$$...$$`), citations and references are kept as `[key]`.
+pub fn latex_to_text(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut i = 0;
+    let mut out = String::new();
+    convert(&chars, &mut i, &mut out);
+
+    let out = out.replace("``", "\"").replace("''", "\"");
+    return out.split_whitespace().collect::<Vec<&str>>().join(" ");
+}
+
+fn convert(chars: &[char], i: &mut usize, out: &mut String) {
+    while *i < chars.len() {
+        let c = chars[*i];
+        match c {
+            '}' => {
+                *i += 1;
+                return;
+            }
+            '{' => {
+                *i += 1;
+                convert(chars, i, out);
+            }
+            '~' => {
+                out.push(' ');
+                *i += 1;
+            }
+            '$' => {
+                let display = *i + 1 < chars.len() && chars[*i + 1] == '$';
+                let delimiter = if display { "$$" } else { "$" };
+                *i += delimiter.len();
+                let math = read_until(chars, i, delimiter);
+                if display {
+                    out.push_str(&format!(" $${}$$ ", math.trim()));
+                } else {
+                    out.push_str(&format!("${}$", math.trim()));
+                }
+            }
+            '\\' => {
+                *i += 1;
+                convert_command(chars, i, out);
+            }
+            _ => {
+                out.push(c);
+                *i += 1;
+            }
+        }
+    }
+}
+
+fn convert_command(chars: &[char], i: &mut usize, out: &mut String) {
+    if *i >= chars.len() {
+        return;
+    }
+    // control symbols: \%, \&, \\, \(, \[, ...
+    if !chars[*i].is_ascii_alphabetic() {
+        let c = chars[*i];
+        *i += 1;
+        match c {
+            '(' => {
+                let math = read_until(chars, i, "\\)");
+                out.push_str(&format!("${}$", math.trim()));
+            }
+            '[' => {
+                let math = read_until(chars, i, "\\]");
+                out.push_str(&format!(" $${}$$ ", math.trim()));
+            }
+            '%' | '&' | '_' | '#' | '$' | '{' | '}' => out.push(c),
+            _ => out.push(' '),
+        }
+        return;
+    }
+
+    let mut name = String::new();
+    while *i < chars.len() && chars[*i].is_ascii_alphabetic() {
+        name.push(chars[*i]);
+        *i += 1;
+    }
+    if *i < chars.len() && chars[*i] == '*' {
+        *i += 1;
+    }
+
+    match name.as_str() {
+        "begin" | "end" => {
+            let env = read_group(chars, i).unwrap_or_default();
+            let env_name = env.trim_end_matches('*');
+            if name == "begin" && MATH_ENVIRONMENTS.contains(&env_name) {
+                let math = read_until(chars, i, &format!("\\end{{{}}}", env));
+                let label_ptn = Regex::new(r"\\label\{[^}]*\}").unwrap();
+                let math = label_ptn.replace_all(&math, "");
+                out.push_str(&format!(" $${}$$ ", math.trim()));
+            } else if name == "begin" {
+                // skip the arguments of tabular-like environments: \begin{tabular}{lcr}
+                skip_optional(chars, i);
+            }
+            out.push(' ');
+        }
+        "item" => {
+            skip_optional(chars, i);
+            out.push_str(" - ");
+        }
+        "multicolumn" | "multirow" => {
+            // \multicolumn{2}{c}{text}: keep only the text
+            read_group(chars, i);
+            skip_optional(chars, i);
+            read_group(chars, i);
+        }
+        "cite" | "citep" | "citet" | "citealp" | "citealt" | "citeauthor" | "citeyear"
+        | "autocite" | "parencite" | "textcite" => {
+            skip_optional(chars, i);
+            skip_optional(chars, i);
+            let keys = read_group(chars, i).unwrap_or_default();
+            out.push_str(&format!("[{}]", keys.trim()));
+        }
+        "ref" | "eqref" | "autoref" | "cref" | "Cref" | "pageref" => {
+            let key = read_group(chars, i).unwrap_or_default();
+            out.push_str(&format!("[{}]", key.trim()));
+        }
+        "url" => {
+            out.push_str(&read_group(chars, i).unwrap_or_default());
+        }
+        "href" | "textcolor" | "colorbox" => {
+            read_group(chars, i);
+        }
+        "footnote" | "footnotetext" => {
+            skip_optional(chars, i);
+            if let Some(note) = read_group(chars, i) {
+                out.push_str(&format!(" ({})", latex_to_text(&note)));
+            }
+        }
+        "label" | "vspace" | "hspace" | "includegraphics" | "bibliography"
+        | "bibliographystyle" | "newcommand" | "renewcommand" | "thanks" | "setlength"
+        | "addtolength" | "caption" => {
+            skip_optional(chars, i);
+            read_group(chars, i);
+            if name == "newcommand" || name == "renewcommand" || name == "setlength" {
+                skip_optional(chars, i);
+                read_group(chars, i);
+            }
+        }
+        _ => {
+            // unknown commands: keep the content of the argument, drop the command itself
+            skip_optional(chars, i);
+        }
+    }
+}
+
+/// Skip an optional `[...]` argument.
+fn skip_optional(chars: &[char], i: &mut usize) {
+    let mut j = *i;
+    while j < chars.len() && chars[j] == ' ' {
+        j += 1;
+    }
+    if j < chars.len() && chars[j] == '[' {
+        while j < chars.len() && chars[j] != ']' {
+            j += 1;
+        }
+        *i = j + 1;
+    }
+}
+
+/// Read the raw content of a `{...}` group.
+fn read_group(chars: &[char], i: &mut usize) -> Option<String> {
+    let mut j = *i;
+    while j < chars.len() && chars[j].is_whitespace() {
+        j += 1;
+    }
+    if j >= chars.len() || chars[j] != '{' {
+        return None;
+    }
+    let mut depth = 0;
+    let start = j + 1;
+    while j < chars.len() {
+        match chars[j] {
+            '\\' => j += 1,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    *i = j + 1;
+                    return Some(chars[start..j].iter().collect());
+                }
+            }
+            _ => {}
+        }
+        j += 1;
+    }
+    *i = chars.len();
+    return Some(chars[start..].iter().collect());
+}
+
+/// Read the raw text until `delimiter` and move past it.
+fn read_until(chars: &[char], i: &mut usize, delimiter: &str) -> String {
+    let delimiter = delimiter.chars().collect::<Vec<char>>();
+    let start = *i;
+    while *i < chars.len() {
+        if chars[*i] == '\\' && delimiter[0] != '\\' {
+            *i += 2;
+            continue;
+        }
+        if chars[*i..].starts_with(&delimiter) {
+            let text = chars[start..*i].iter().collect();
+            *i += delimiter.len();
+            return text;
+        }
+        *i += 1;
+    }
+    return chars[start.min(chars.len())..].iter().collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn fixture() -> LatexSource {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("src/fixtures/latex/2401.00001.tar.gz");
+        return LatexSource::from_tarball(&path).unwrap();
+    }
+
+    #[test]
+    fn test_latex_to_text() {
+        assert_eq!(
+            latex_to_text(r"We use \textbf{multi-head} attention~\cite{vaswani2017}."),
+            "We use multi-head attention [vaswani2017]."
+        );
+        assert_eq!(
+            latex_to_text(r"The loss is $\mathcal{L} = -\log p(y|x)$ and 5\% lower."),
+            r"The loss is $\mathcal{L} = -\log p(y|x)$ and 5% lower."
+        );
+        assert_eq!(
+            latex_to_text(r"See Table~\ref{tab:main}\footnote{Averaged over \emph{3} runs.}."),
+            "See Table [tab:main] (Averaged over 3 runs.)."
+        );
+        assert_eq!(
+            latex_to_text("\\begin{equation}\\label{eq:1} y = Wx \\end{equation}"),
+            "$$y = Wx$$"
+        );
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        assert_eq!(
+            strip_comments("text % comment\n50\\% of % the rest"),
+            "text \n50\\% of "
+        );
+    }
+
+    #[test]
+    fn test_from_tarball() {
+        let source = fixture();
+        assert_eq!(source.main_file(), Some(String::from("main.tex")));
+        let resolved = source.resolve().unwrap();
+        assert!(resolved.contains("Transformers have become"));
+        assert!(resolved.contains("\\subsection{Encoder}"));
+        assert!(!resolved.contains("\\input"));
+        assert!(!resolved.contains("TODO"));
+    }
+
+    #[test]
+    fn test_to_document() {
+        let document = fixture().to_document().unwrap();
+        let titles = document
+            .iter()
+            .map(|section| section.title.clone())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            titles,
+            [
+                "Abstract",
+                "1 Introduction",
+                "2 Method",
+                "2.1 Encoder",
+                "3 Experiments",
+                "4 Results",
+                "5 Conclusion",
+                "Acknowledgments",
+                "A Hyperparameters",
+            ]
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+        );
+        assert_eq!(document.sections[3].level, 2);
+        assert_eq!(document.sections[8].role, SectionRole::Appendix);
+        assert_eq!(document.find_by_role(SectionRole::Introduction).len(), 1);
+        assert!(document
+            .text_by_role(SectionRole::Method)
+            .contains("$h = \\mathrm{softmax}(QK^\\top / \\sqrt{d})V$"));
+        assert!(document.get_text().contains("[vaswani2017]"));
+
+        assert_eq!(document.figures.len(), 1);
+        assert_eq!(document.figures[0].label, "Figure 1");
+        assert_eq!(
+            document.figures[0].caption,
+            "The architecture of the encoder."
+        );
+        assert_eq!(document.figures[0].section, 3);
+        assert_eq!(document.tables.len(), 1);
+        assert_eq!(document.tables[0].caption, "Results on WMT14.");
+        assert_eq!(
+            document.tables[0].rows,
+            vec![vec!["Model", "BLEU"], vec!["Ours", "27.3"]]
+        );
+        assert!(!document.get_text().contains("Results on WMT14."));
+    }
+
+    #[test]
+    fn test_heading_commands() {
+        let main = r"\documentclass{article}
+\sectionfont{\large}
+\begin{document}
+\section{Method}
+\sectionmark{Method} We use \subsectionautorefname{} here.
+\appendix
+\subsection{Proofs}
+The proofs.
+\section*{Extra}
+More.
+\end{document}";
+        let mut files = FxHashMap::default();
+        files.insert(String::from("main.tex"), main.to_string());
+        let document = LatexSource { files }.to_document().unwrap();
+        let titles = document
+            .iter()
+            .map(|section| section.title.clone())
+            .collect::<Vec<String>>();
+        assert_eq!(titles, ["1 Method", "A.1 Proofs", "Extra"]);
+        assert!(document.sections[0].contents.join(" ").contains("We use"));
+        assert_eq!(document.sections[1].role, SectionRole::Appendix);
+    }
+
+    #[test]
+    fn test_tabular_rows() {
+        let table = r"
+\begin{tabular}{l|cc}
+\toprule
+Model & BLEU & \multicolumn{1}{c}{Cost} \\
+\midrule
+Ours~\cite{ours} & \textbf{27.3} & $2.3 \cdot 10^{19}$ \\[2pt]
+R\&D &  & 1.0 \\ \cmidrule(lr){1-3}
+\bottomrule
+\end{tabular}";
+        assert_eq!(
+            tabular_rows(table),
+            vec![
+                vec!["Model", "BLEU", "Cost"],
+                vec!["Ours [ours]", "27.3", r"$2.3 \cdot 10^{19}$"],
+                vec!["R&D", "", "1.0"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_pdf() {
+        assert!(LatexSource::from_bytes(b"%PDF-1.5 ...").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_waits_between_requests() {
+        let (url, mut requests) =
+            crate::llm::tests::stub_server("\\section{Introduction} Hello.").await;
+        let mut downloader = EprintDownloader::new(0);
+        downloader.base_url = format!("{}/e-print", url);
+        downloader.wait_time = Duration::from_millis(300);
+
+        let time = Instant::now();
+        downloader.download("2401.00001").await.unwrap();
+        downloader.clone().download("2401.00002").await.unwrap();
+        assert!(time.elapsed() >= Duration::from_millis(300));
+        let raw = requests.recv().await.unwrap();
+        assert!(raw.starts_with("GET /e-print/2401.00001 "));
+    }
+
+    #[tokio::test]
+    async fn test_download_timeout() {
+        // a server that accepts the connection and never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let mut downloader = EprintDownloader::new(0);
+        downloader.base_url = url;
+        downloader.timeout(Duration::from_millis(200));
+        let error = downloader.download("2401.00001").await.unwrap_err();
+        assert!(is_timeout(&error));
+    }
+}
@@ -0,0 +1,468 @@
+//! This module provides the LLM backends used by `AI`.
+//! Any server with an OpenAI-compatible chat completions API can be used
+//! (OpenAI, vLLM, llama.cpp server, Ollama), as well as Azure OpenAI.
+use anyhow::Result;
+use async_trait::async_trait;
+use dotenvy::dotenv;
+use openai_tools::{Message, ResponseFormat};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+pub const AZURE_API_VERSION: &str = "2024-10-21";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+}
+
+impl ChatRequest {
+    pub fn new(model: &str, messages: Vec<Message>) -> ChatRequest {
+        ChatRequest {
+            model: model.to_string(),
+            messages,
+            temperature: None,
+            response_format: None,
+        }
+    }
+
+    pub fn temperature(&mut self, temperature: f32) -> &mut Self {
+        self.temperature = Some(temperature);
+        return self;
+    }
+
+    pub fn response_format(&mut self, response_format: ResponseFormat) -> &mut Self {
+        self.response_format = Some(response_format);
+        return self;
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChatResponse {
+    pub content: String,
+    /// Model that generated the response, as reported by the server
+    pub model: String,
+    pub usage: Usage,
+}
+
+/// Errors that retrying the same request with the same model does not fix.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LlmError {
+    /// The prompt does not fit in the context window of the model
+    ContextLength(String),
+    /// The model refused to answer
+    Refusal(String),
+}
+
+impl std::fmt::Display for LlmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LlmError::ContextLength(message) => {
+                write!(f, "The prompt is too long for the model: {}", message)
+            }
+            LlmError::Refusal(message) => write!(f, "The model refused to answer: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+/// Whether an error response of the server says that the prompt is too long.
+fn is_context_length_error(content: &str) -> bool {
+    let content = content.to_lowercase();
+    return content.contains("context_length_exceeded")
+        || content.contains("maximum context length")
+        || content.contains("context window");
+}
+
+#[async_trait]
+pub trait LlmProvider: Send + Sync + std::fmt::Debug {
+    /// Name of the provider for logs: "openai", "azure", ...
+    fn name(&self) -> &str;
+
+    async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse>;
+}
+
+/// A server with the OpenAI chat completions API: `{base_url}/chat/completions`.
+#[derive(Debug, Clone)]
+pub struct OpenAICompatible {
+    pub base_url: String,
+    /// Sent as a bearer token (local servers usually do not need one)
+    pub api_key: Option<String>,
+}
+
+impl OpenAICompatible {
+    pub fn new(base_url: &str, api_key: Option<String>) -> OpenAICompatible {
+        OpenAICompatible {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAICompatible {
+    fn name(&self) -> &str {
+        return "openai";
+    }
+
+    async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let url = format!("{}/chat/completions", self.base_url);
+        let mut builder = reqwest::Client::new().post(url);
+        if let Some(api_key) = self.api_key.as_ref() {
+            builder = builder.bearer_auth(api_key);
+        }
+        return send(builder, request).await;
+    }
+}
+
+/// Azure OpenAI: the model ID of the request is the name of the deployment.
+#[derive(Debug, Clone)]
+pub struct AzureOpenAI {
+    /// Endpoint of the resource: "https://{resource}.openai.azure.com"
+    pub endpoint: String,
+    pub api_key: String,
+    pub api_version: String,
+}
+
+impl AzureOpenAI {
+    pub fn new(endpoint: &str, api_key: &str, api_version: &str) -> AzureOpenAI {
+        AzureOpenAI {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            api_version: api_version.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for AzureOpenAI {
+    fn name(&self) -> &str {
+        return "azure";
+    }
+
+    async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let url = format!(
+            "{}/openai/deployments/{}/chat/completions?api-version={}",
+            self.endpoint, request.model, self.api_version
+        );
+        let builder = reqwest::Client::new()
+            .post(url)
+            .header("api-key", &self.api_key);
+        return send(builder, request).await;
+    }
+}
+
+async fn send(builder: reqwest::RequestBuilder, request: &ChatRequest) -> Result<ChatResponse> {
+    let response = builder
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(request)?)
+        .send()
+        .await?;
+    let status = response.status();
+    let content = response.text().await?;
+    if !status.is_success() {
+        if is_context_length_error(&content) {
+            return Err(LlmError::ContextLength(content).into());
+        }
+        return Err(anyhow::anyhow!(
+            "LLM server returned {}: {}",
+            status,
+            content
+        ));
+    }
+    return parse_response(&content);
+}
+
+/// Parse a chat completions response. `usage` is optional because some local servers omit it.
+pub fn parse_response(content: &str) -> Result<ChatResponse> {
+    #[derive(Deserialize)]
+    struct ResponseMessage {
+        #[serde(default)]
+        content: Option<String>,
+        /// Set instead of `content` when the model refuses to follow a JSON schema
+        #[serde(default)]
+        refusal: Option<String>,
+    }
+    #[derive(Deserialize)]
+    struct Choice {
+        message: ResponseMessage,
+    }
+    #[derive(Deserialize)]
+    struct Response {
+        #[serde(default)]
+        model: String,
+        choices: Vec<Choice>,
+        #[serde(default)]
+        usage: Option<Usage>,
+    }
+
+    let response = serde_json::from_str::<Response>(content)
+        .map_err(|e| anyhow::anyhow!("Failed to parse JSON: {} CONTENT: {}", e, content))?;
+    let message = response
+        .choices
+        .into_iter()
+        .next()
+        .map(|choice| choice.message);
+    let content = match message {
+        Some(ResponseMessage {
+            content: Some(content),
+            ..
+        }) => content,
+        Some(ResponseMessage {
+            refusal: Some(refusal),
+            ..
+        }) => return Err(LlmError::Refusal(refusal).into()),
+        _ => return Err(anyhow::anyhow!("The response has no message")),
+    };
+    return Ok(ChatResponse {
+        content,
+        model: response.model,
+        usage: response.usage.unwrap_or_default(),
+    });
+}
+
+/// Shared flag to cancel the requests in flight, e.g. on Ctrl-C.
+#[derive(Clone, Debug)]
+pub struct CancelToken {
+    sender: Arc<tokio::sync::watch::Sender<bool>>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        let (sender, _) = tokio::sync::watch::channel(false);
+        CancelToken {
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// A token cancelled when the process receives Ctrl-C.
+    pub fn on_ctrl_c() -> CancelToken {
+        let token = CancelToken::new();
+        let cloned = token.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                eprintln!("WARNING: Interrupted, cancelling the running requests...");
+                cloned.cancel();
+            }
+        });
+        return token;
+    }
+
+    pub fn cancel(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return *self.sender.borrow();
+    }
+
+    /// Wait until the token is cancelled.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.sender.subscribe();
+        let _ = receiver.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// Build the provider from the environment.
+///
+/// - `LLM_PROVIDER`: "openai" (default, also for OpenAI-compatible servers) or "azure"
+/// - `LLM_BASE_URL`: base URL of the API, or the endpoint of the Azure resource
+/// - `LLM_API_KEY`: API key (defaults to `OPENAI_API_KEY`)
+/// - `AZURE_API_VERSION`: API version of Azure OpenAI
+pub fn from_env() -> Result<Arc<dyn LlmProvider>> {
+    dotenv().ok();
+    let env = |key: &str| std::env::var(key).ok().filter(|x| !x.trim().is_empty());
+    let api_key = env("LLM_API_KEY").or(env("OPENAI_API_KEY"));
+    let provider = env("LLM_PROVIDER").unwrap_or(String::from("openai"));
+    match provider.to_lowercase().as_str() {
+        "openai" | "openai-compatible" | "vllm" | "llama.cpp" | "ollama" => {
+            let base_url = env("LLM_BASE_URL").unwrap_or(String::from(OPENAI_BASE_URL));
+            return Ok(Arc::new(OpenAICompatible::new(&base_url, api_key)));
+        }
+        "azure" => {
+            let endpoint = env("LLM_BASE_URL")
+                .ok_or(anyhow::anyhow!("LLM_BASE_URL is not set for Azure OpenAI."))?;
+            let api_key =
+                api_key.ok_or(anyhow::anyhow!("LLM_API_KEY is not set for Azure OpenAI."))?;
+            let api_version = env("AZURE_API_VERSION").unwrap_or(String::from(AZURE_API_VERSION));
+            return Ok(Arc::new(AzureOpenAI::new(
+                &endpoint,
+                &api_key,
+                &api_version,
+            )));
+        }
+        _ => return Err(anyhow::anyhow!("Unknown LLM provider: {}", provider)),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::utils::s;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Start a server that answers every request with `body` and returns the raw requests.
+    pub async fn stub_server(body: &str) -> (String, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let body = body.to_string();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(_) => return,
+                };
+                let request = read_request(&mut socket).await;
+                let _ = tx.send(request);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+        return (url, rx);
+    }
+
+    /// A chat completions response of "local-model" with `content`, using 100 + 20 tokens.
+    pub fn chat_response(content: &str) -> serde_json::Value {
+        return serde_json::json!({
+            "model": "local-model",
+            "choices": [{"message": {"role": "assistant", "content": content}}],
+            "usage": {"prompt_tokens": 100, "completion_tokens": 20, "total_tokens": 120}
+        });
+    }
+
+    /// An `AI` of "local-model" whose requests are all answered with `content`,
+    /// and the raw requests it sent.
+    pub async fn stub_ai(
+        content: &str,
+    ) -> (crate::ai::AI, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        let (url, requests) = stub_server(&chat_response(content).to_string()).await;
+        let mut ai = crate::ai::AI::new("local-model").unwrap();
+        ai.provider(Arc::new(OpenAICompatible::new(&url, None)));
+        return (ai, requests);
+    }
+
+    pub async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut chunk).await.unwrap_or(0);
+            if n == 0 {
+                break;
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&buffer).to_string();
+            if let Some(header_end) = text.find("\r\n\r\n") {
+                let content_length = text[..header_end]
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        if name.eq_ignore_ascii_case("content-length") {
+                            return value.trim().parse::<usize>().ok();
+                        }
+                        None
+                    })
+                    .unwrap_or(0);
+                if buffer.len() >= header_end + 4 + content_length {
+                    break;
+                }
+            }
+        }
+        return String::from_utf8_lossy(&buffer).to_string();
+    }
+
+    pub const RESPONSE: &str = r#"{"id":"chatcmpl-1","object":"chat.completion","created":0,"model":"llama-3.1-8b","choices":[{"index":0,"message":{"role":"assistant","content":"Hello!"},"finish_reason":"stop"}],"usage":{"prompt_tokens":12,"completion_tokens":3,"total_tokens":15}}"#;
+
+    #[tokio::test]
+    async fn test_openai_compatible() {
+        let (url, mut requests) = stub_server(RESPONSE).await;
+        let provider = OpenAICompatible::new(&format!("{}/v1/", url), Some(s("sk-test")));
+        let mut request = ChatRequest::new("llama-3.1-8b", vec![Message::new("user", "Hi")]);
+        request.temperature(0.5);
+
+        let response = provider.chat(&request).await.unwrap();
+        assert_eq!(response.content, "Hello!");
+        assert_eq!(response.model, "llama-3.1-8b");
+        assert_eq!(response.usage.total_tokens, 15);
+
+        let raw = requests.recv().await.unwrap();
+        assert!(raw.starts_with("POST /v1/chat/completions HTTP/1.1"));
+        assert!(raw.to_lowercase().contains("authorization: bearer sk-test"));
+        assert!(raw.contains(r#""model":"llama-3.1-8b""#));
+        assert!(raw.contains(r#""temperature":0.5"#));
+        assert!(!raw.contains("response_format"));
+    }
+
+    #[tokio::test]
+    async fn test_azure_openai() {
+        let (url, mut requests) = stub_server(RESPONSE).await;
+        let provider = AzureOpenAI::new(&url, "azure-key", "2024-10-21");
+        let request = ChatRequest::new("summarizer", vec![Message::new("user", "Hi")]);
+
+        let response = provider.chat(&request).await.unwrap();
+        assert_eq!(response.content, "Hello!");
+
+        let raw = requests.recv().await.unwrap();
+        assert!(raw.starts_with(
+            "POST /openai/deployments/summarizer/chat/completions?api-version=2024-10-21 HTTP/1.1"
+        ));
+        assert!(raw.to_lowercase().contains("api-key: azure-key"));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_token() {
+        let token = CancelToken::new();
+        assert!(!token.is_cancelled());
+        let cloned = token.clone();
+        let waiter = tokio::spawn(async move { cloned.cancelled().await });
+        token.cancel();
+        waiter.await.unwrap();
+        assert!(token.is_cancelled());
+        // a token that is already cancelled does not wait
+        token.cancelled().await;
+    }
+
+    #[test]
+    fn test_parse_response_without_usage() {
+        let response =
+            parse_response(r#"{"choices":[{"message":{"role":"assistant","content":"ok"}}]}"#)
+                .unwrap();
+        assert_eq!(response.content, "ok");
+        assert_eq!(response.usage, Usage::default());
+        assert!(parse_response(r#"{"error":{"message":"invalid key"}}"#).is_err());
+
+        let error = parse_response(
+            r#"{"choices":[{"message":{"role":"assistant","content":null,"refusal":"I can't help with that."}}]}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<LlmError>(),
+            Some(&LlmError::Refusal(s("I can't help with that.")))
+        );
+        assert!(is_context_length_error(
+            r#"{"error":{"code":"context_length_exceeded"}}"#
+        ));
+    }
+}
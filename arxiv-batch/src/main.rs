@@ -1,8 +1,27 @@
 pub mod ai;
+pub mod batch;
 pub mod cache;
 pub mod collector;
 pub mod common;
+pub mod cost;
+pub mod digest;
+pub mod document;
+pub mod embedding;
+pub mod faithfulness;
+pub mod latex;
+pub mod llm;
+pub mod prompt;
+pub mod qa;
+pub mod quality;
+pub mod reference;
 pub mod reporter;
+pub mod schema;
+pub mod section;
+pub mod serializer;
+pub mod store;
+pub mod summary_cache;
+pub mod tokens;
+pub mod triage;
 pub mod utils;
 
 use crate::common::StatusCode;
@@ -10,9 +29,12 @@ use anyhow::Result;
 use chrono::{DateTime, Utc};
 use clap::{Args, Parser, Subcommand};
 use dotenvy::dotenv;
+use fxhash::FxHashMap;
 use indicatif::ProgressBar;
 use serde::{Deserialize, Serialize};
+use std::io::Write;
 use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, BufReader};
 
 // CLI SETTISNGS ---------------------------------------------------------------
 /// Command-line interface
@@ -35,8 +57,20 @@ enum Commands {
     /// Post specific date's arXiv papers to Notion
     #[command(name = "post-arxiv-papers")]
     PostArxivPapers(PostArxivPapersArgs),
+    /// Parse papers and save the sections to the local store
+    #[command(name = "parse")]
+    Parse(ParseArgs),
     #[command(name = "build-cache")]
     BuildCache,
+    /// Inspect or prune the cached summaries
+    #[command(name = "summary-cache")]
+    SummaryCache(SummaryCacheArgs),
+    /// Ask questions about a paper
+    #[command(name = "ask")]
+    Ask(AskArgs),
+    /// Find the processed papers similar to a paper or a text
+    #[command(name = "similar")]
+    Similar(SimilarArgs),
 }
 
 #[derive(Debug, Args)]
@@ -53,9 +87,13 @@ struct PostANewPaperArgs {
     /// Wait time in seconds between retry attempts
     #[arg(long, default_value_t = 30)]
     wait_time: u64,
-    /// OpenAI model ID: "gpt-4o-mini"
+    /// OpenAI model IDs in the order they are tried: "gpt-4o-mini,gpt-4o".
+    /// "my-model=32000" sets the context window of a model
     #[arg(long, default_value_t = String::from("gpt-4o-mini"))]
     model_id: String,
+    /// Summarize the paper again even if its summary is cached
+    #[arg(long)]
+    force_resummarize: bool,
     /// Verbose mode
     #[arg(short, long)]
     verbose: bool,
@@ -72,6 +110,51 @@ struct PostArxivPapersArgs {
     /// Wait time in seconds between retry attempts
     #[arg(long, default_value_t = 30)]
     wait_time: u64,
+    /// OpenAI model IDs in the order they are tried: "gpt-4o-mini,gpt-4o".
+    /// "my-model=32000" sets the context window of a model
+    #[arg(long, default_value_t = String::from("gpt-4o-mini"))]
+    model_id: String,
+    /// Summarize the paper again even if its summary is cached
+    #[arg(long)]
+    force_resummarize: bool,
+    /// Stop summarizing papers once the cost of the run reaches this amount in USD
+    #[arg(long)]
+    max_cost: Option<f64>,
+    /// Stop summarizing papers once the run has used this many tokens
+    #[arg(long)]
+    max_tokens: Option<u64>,
+    /// Summarize the papers in one job of the OpenAI Batch API: half the price,
+    /// but the results may take up to 24 hours
+    #[arg(long)]
+    batch: bool,
+    /// Verbose mode
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[derive(Debug, Args)]
+struct AskArgs {
+    /// Title of the paper
+    #[arg(long)]
+    title: String,
+    /// arXiv ID of the paper, to find it in the local store without looking it up
+    #[arg(long)]
+    arxiv_id: Option<String>,
+    /// Path to the PDF file or URL, parsed if the paper is not stored yet
+    #[arg(long)]
+    pdf: Option<String>,
+    /// Question to answer; without it, questions are read from the standard input
+    #[arg(long)]
+    question: Option<String>,
+    /// Passages retrieved for each question
+    #[arg(long, default_value_t = 5)]
+    top_k: usize,
+    /// Maximum number of retry attempts
+    #[arg(long, default_value_t = 15)]
+    max_retry_count: u64,
+    /// Wait time in seconds between retry attempts
+    #[arg(long, default_value_t = 30)]
+    wait_time: u64,
     /// OpenAI model ID: "gpt-4o-mini"
     #[arg(long, default_value_t = String::from("gpt-4o-mini"))]
     model_id: String,
@@ -80,6 +163,88 @@ struct PostArxivPapersArgs {
     verbose: bool,
 }
 
+#[derive(Debug, Args)]
+struct SimilarArgs {
+    /// Title of the paper
+    #[arg(long, conflicts_with_all = ["arxiv_id", "text"])]
+    title: Option<String>,
+    /// arXiv ID of a processed paper
+    #[arg(long, conflicts_with = "text")]
+    arxiv_id: Option<String>,
+    /// Free text to search for
+    #[arg(long)]
+    text: Option<String>,
+    /// Number of papers to show
+    #[arg(long, default_value_t = 10)]
+    top_k: usize,
+    /// Embed the papers posted to Notion that are not in the vector index yet
+    #[arg(long, conflicts_with_all = ["title", "arxiv_id", "text"])]
+    reindex: bool,
+    /// Maximum number of retry attempts
+    #[arg(long, default_value_t = 15)]
+    max_retry_count: u64,
+    /// Wait time in seconds between retry attempts
+    #[arg(long, default_value_t = 30)]
+    wait_time: u64,
+}
+
+#[derive(Debug, Args)]
+struct SummaryCacheArgs {
+    #[command(subcommand)]
+    command: SummaryCacheCommands,
+}
+
+#[derive(Subcommand, Debug)]
+enum SummaryCacheCommands {
+    /// List the cached summaries
+    #[command(name = "list")]
+    List,
+    /// Remove the cached summaries matching all the given conditions
+    #[command(name = "prune")]
+    Prune(PruneArgs),
+}
+
+#[derive(Debug, Args)]
+struct PruneArgs {
+    /// Remove the summaries older than this number of days
+    #[arg(long)]
+    older_than_days: Option<i64>,
+    /// Remove the summaries generated by this model
+    #[arg(long)]
+    model_id: Option<String>,
+    /// Remove the summaries generated with another prompt template version
+    #[arg(long)]
+    keep_template_version: Option<String>,
+    /// Remove all the summaries
+    #[arg(long)]
+    all: bool,
+}
+
+#[derive(Debug, Args)]
+struct ParseArgs {
+    /// Date of the arXiv papers to parse: "YYYY-MM-DD"
+    #[arg(long, conflicts_with = "title", required_unless_present = "title")]
+    date: Option<String>,
+    /// Title of the paper to parse
+    #[arg(long)]
+    title: Option<String>,
+    /// Path to the PDF file or URL (only with --title)
+    #[arg(long, requires = "title")]
+    pdf: Option<String>,
+    /// Parse the papers again even if they are already stored
+    #[arg(long)]
+    force: bool,
+    /// Maximum number of retry attempts
+    #[arg(long, default_value_t = 15)]
+    max_retry_count: u64,
+    /// Wait time in seconds between retry attempts
+    #[arg(long, default_value_t = 30)]
+    wait_time: u64,
+    /// Verbose mode
+    #[arg(short, long)]
+    verbose: bool,
+}
+
 // CONFIGURATION SETTINGS -----------------------------------------------------
 
 /// Configuration settings
@@ -100,8 +265,98 @@ struct Config {
     /// OpenAI API key
     #[serde(rename = "OPENAI_API_KEY", default = "String::new")]
     openai_api_key: String,
+    /// LLM backend: "openai" (also for OpenAI-compatible servers such as vLLM and Ollama) or "azure"
+    #[serde(rename = "LLM_PROVIDER", default = "String::new")]
+    llm_provider: String,
+    /// Base URL of the LLM API: "http://localhost:11434/v1", or the endpoint of the Azure resource
+    #[serde(rename = "LLM_BASE_URL", default = "String::new")]
+    llm_base_url: String,
+    /// API key of the LLM backend (defaults to OPENAI_API_KEY)
+    #[serde(rename = "LLM_API_KEY", default = "String::new")]
+    llm_api_key: String,
+    /// API version of Azure OpenAI
+    #[serde(rename = "AZURE_API_VERSION", default = "String::new")]
+    azure_api_version: String,
     #[serde(rename = "CACHE_DIR", default = "String::new")]
     cache_dir: String,
+    /// Prices in USD per million tokens (input/output) added to the built-in table:
+    /// "gpt-4o-mini=0.15/0.6,my-model=1/2"
+    #[serde(rename = "LLM_PRICES", default = "String::new")]
+    llm_prices: String,
+    /// Timeout of a request to the LLM in seconds (300)
+    #[serde(rename = "LLM_TIMEOUT", default = "String::new")]
+    llm_timeout: String,
+    /// Section roles to be summarized: "introduction,method,experiments,results,conclusion"
+    #[serde(rename = "SUMMARY_SECTIONS", default = "String::new")]
+    summary_sections: String,
+    /// Source of the original text of arXiv papers: "latex" (falls back to PDF) or "pdf"
+    #[serde(rename = "TEXT_SOURCE", default = "String::new")]
+    text_source: String,
+    /// Directory of the prompt templates ("summary.toml"); the built-in template is used if empty
+    #[serde(rename = "PROMPT_DIR", default = "String::new")]
+    prompt_dir: String,
+    /// Definition of the summary fields (TOML); the built-in definition is used if empty
+    #[serde(rename = "SUMMARY_SCHEMA", default = "String::new")]
+    summary_schema: String,
+    /// Repair requests sent when the summary does not match the schema (2)
+    #[serde(rename = "SUMMARY_REPAIR_ATTEMPTS", default = "String::new")]
+    summary_repair_attempts: String,
+    /// Accept summaries with missing fields when the repairs fail: "true" or "false"
+    #[serde(rename = "ALLOW_PARTIAL_SUMMARY", default = "String::new")]
+    allow_partial_summary: String,
+    /// Language of the summary: "ja", "en" or "bilingual"
+    #[serde(rename = "OUTPUT_LANGUAGE", default = "String::new")]
+    output_language: String,
+    /// References included in the prompt, the most relevant first (20; 0 to leave them out)
+    #[serde(rename = "REFERENCES_TOP_K", default = "String::new")]
+    references_top_k: String,
+    /// Tokens of the references included in the prompt (4000)
+    #[serde(rename = "REFERENCES_MAX_TOKENS", default = "String::new")]
+    references_max_tokens: String,
+    /// Interest profile of the team (TOML) used to triage the arXiv papers; no triage if empty
+    #[serde(rename = "INTEREST_PROFILE", default = "String::new")]
+    interest_profile: String,
+    /// Summarize a paper again when its summary has numbers or names not found in the paper:
+    /// "true" or "false"
+    #[serde(rename = "RESUMMARIZE_UNFAITHFUL", default = "String::new")]
+    resummarize_unfaithful: String,
+    /// Notion property listing the claims of a summary not found in the paper
+    /// (defaults to "Unsupported Claims"; not posted if the paper database lacks it)
+    #[serde(rename = "UNSUPPORTED_CLAIMS_PROPERTY", default = "String::new")]
+    unsupported_claims_property: String,
+    /// Notion page under which the daily digests are posted
+    #[serde(rename = "NOTION_DIGEST_PAGE_ID", default = "String::new")]
+    notion_digest_page_id: String,
+    /// Directory of the Markdown digests (defaults to CACHE_DIR/digests)
+    #[serde(rename = "DIGEST_DIR", default = "String::new")]
+    digest_dir: String,
+    /// Base URL of the embeddings API (defaults to LLM_BASE_URL)
+    #[serde(rename = "EMBEDDING_BASE_URL", default = "String::new")]
+    embedding_base_url: String,
+    /// API key of the embeddings API (defaults to LLM_API_KEY)
+    #[serde(rename = "EMBEDDING_API_KEY", default = "String::new")]
+    embedding_api_key: String,
+    /// Embedding model: "text-embedding-3-small"
+    #[serde(rename = "EMBEDDING_MODEL", default = "String::new")]
+    embedding_model: String,
+    /// Seconds between the checks of a job of the Batch API (60)
+    #[serde(rename = "BATCH_POLL_INTERVAL", default = "String::new")]
+    batch_poll_interval: String,
+    /// Format of the paper passed to the LLM: "xml", "markdown" or "json"
+    #[serde(rename = "PAPER_FORMAT", default = "String::new")]
+    paper_format: String,
+    /// Quality gate: minimum ratio of readable words (0.7)
+    #[serde(rename = "QUALITY_MIN_READABLE_RATIO", default = "String::new")]
+    quality_min_readable_ratio: String,
+    /// Quality gate: minimum characters per page of a PDF (500)
+    #[serde(rename = "QUALITY_MIN_CHARS_PER_PAGE", default = "String::new")]
+    quality_min_chars_per_page: String,
+    /// Quality gate: accepted languages: "en"
+    #[serde(rename = "QUALITY_LANGUAGES", default = "String::new")]
+    quality_languages: String,
+    /// Quality gate: section roles that must be present: "introduction"
+    #[serde(rename = "QUALITY_REQUIRED_SECTIONS", default = "String::new")]
+    quality_required_sections: String,
 }
 
 impl Config {
@@ -118,7 +373,103 @@ impl Config {
         std::env::set_var("NOTION_PAPER_DATABASE_ID", &self.notion_paper_database_id);
         std::env::set_var("NOTION_AUTHOR_DATABASE_ID", &self.notion_author_database_id);
         std::env::set_var("OPENAI_API_KEY", &self.openai_api_key);
+        if !self.llm_provider.is_empty() {
+            std::env::set_var("LLM_PROVIDER", &self.llm_provider);
+        }
+        if !self.llm_base_url.is_empty() {
+            std::env::set_var("LLM_BASE_URL", &self.llm_base_url);
+        }
+        if !self.llm_api_key.is_empty() {
+            std::env::set_var("LLM_API_KEY", &self.llm_api_key);
+        }
+        if !self.azure_api_version.is_empty() {
+            std::env::set_var("AZURE_API_VERSION", &self.azure_api_version);
+        }
         std::env::set_var("CACHE_DIR", &self.cache_dir);
+        if !self.summary_sections.is_empty() {
+            std::env::set_var("SUMMARY_SECTIONS", &self.summary_sections);
+        }
+        if !self.text_source.is_empty() {
+            std::env::set_var("TEXT_SOURCE", &self.text_source);
+        }
+        if !self.prompt_dir.is_empty() {
+            std::env::set_var("PROMPT_DIR", &self.prompt_dir);
+        }
+        if !self.summary_schema.is_empty() {
+            std::env::set_var("SUMMARY_SCHEMA", &self.summary_schema);
+        }
+        if !self.llm_prices.is_empty() {
+            std::env::set_var("LLM_PRICES", &self.llm_prices);
+        }
+        if !self.llm_timeout.is_empty() {
+            std::env::set_var("LLM_TIMEOUT", &self.llm_timeout);
+        }
+        if !self.summary_repair_attempts.is_empty() {
+            std::env::set_var("SUMMARY_REPAIR_ATTEMPTS", &self.summary_repair_attempts);
+        }
+        if !self.allow_partial_summary.is_empty() {
+            std::env::set_var("ALLOW_PARTIAL_SUMMARY", &self.allow_partial_summary);
+        }
+        if !self.output_language.is_empty() {
+            std::env::set_var("OUTPUT_LANGUAGE", &self.output_language);
+        }
+        if !self.references_top_k.is_empty() {
+            std::env::set_var("REFERENCES_TOP_K", &self.references_top_k);
+        }
+        if !self.references_max_tokens.is_empty() {
+            std::env::set_var("REFERENCES_MAX_TOKENS", &self.references_max_tokens);
+        }
+        if !self.interest_profile.is_empty() {
+            std::env::set_var("INTEREST_PROFILE", &self.interest_profile);
+        }
+        if !self.resummarize_unfaithful.is_empty() {
+            std::env::set_var("RESUMMARIZE_UNFAITHFUL", &self.resummarize_unfaithful);
+        }
+        if !self.unsupported_claims_property.is_empty() {
+            std::env::set_var(
+                "UNSUPPORTED_CLAIMS_PROPERTY",
+                &self.unsupported_claims_property,
+            );
+        }
+        if !self.notion_digest_page_id.is_empty() {
+            std::env::set_var("NOTION_DIGEST_PAGE_ID", &self.notion_digest_page_id);
+        }
+        if !self.digest_dir.is_empty() {
+            std::env::set_var("DIGEST_DIR", &self.digest_dir);
+        }
+        if !self.embedding_base_url.is_empty() {
+            std::env::set_var("EMBEDDING_BASE_URL", &self.embedding_base_url);
+        }
+        if !self.embedding_api_key.is_empty() {
+            std::env::set_var("EMBEDDING_API_KEY", &self.embedding_api_key);
+        }
+        if !self.embedding_model.is_empty() {
+            std::env::set_var("EMBEDDING_MODEL", &self.embedding_model);
+        }
+        if !self.batch_poll_interval.is_empty() {
+            std::env::set_var("BATCH_POLL_INTERVAL", &self.batch_poll_interval);
+        }
+        if !self.paper_format.is_empty() {
+            std::env::set_var("PAPER_FORMAT", &self.paper_format);
+        }
+        if !self.quality_min_readable_ratio.is_empty() {
+            std::env::set_var(
+                "QUALITY_MIN_READABLE_RATIO",
+                &self.quality_min_readable_ratio,
+            );
+        }
+        if !self.quality_min_chars_per_page.is_empty() {
+            std::env::set_var(
+                "QUALITY_MIN_CHARS_PER_PAGE",
+                &self.quality_min_chars_per_page,
+            );
+        }
+        if !self.quality_languages.is_empty() {
+            std::env::set_var("QUALITY_LANGUAGES", &self.quality_languages);
+        }
+        if !self.quality_required_sections.is_empty() {
+            std::env::set_var("QUALITY_REQUIRED_SECTIONS", &self.quality_required_sections);
+        }
     }
 }
 
@@ -150,6 +501,7 @@ async fn main() {
                 args.max_retry_count,
                 args.wait_time,
                 args.model_id.clone(),
+                args.force_resummarize,
                 args.verbose,
             )
             .await;
@@ -160,15 +512,47 @@ async fn main() {
             let date: DateTime<Utc> = DateTime::parse_from_str(&date, "%Y-%m-%d %H:%M:%S%z")
                 .unwrap()
                 .into();
+            let budget = cost::Budget {
+                max_cost: args.max_cost,
+                max_tokens: args.max_tokens,
+            };
+            let mode = SummaryMode {
+                force_resummarize: args.force_resummarize,
+                batch: args.batch,
+            };
             post_arxiv_papers(
                 date,
                 args.max_retry_count,
                 args.wait_time,
                 args.model_id.clone(),
+                budget,
+                mode,
                 args.verbose,
             )
             .await;
         }
+        Some(Commands::Parse(args)) => {
+            if let Some(date) = args.date.as_ref() {
+                parse_arxiv_papers(
+                    utils::datetime_from_str(date),
+                    args.force,
+                    args.max_retry_count,
+                    args.wait_time,
+                    args.verbose,
+                )
+                .await;
+            } else if let Some(title) = args.title.as_ref() {
+                parse_a_paper(
+                    title.clone(),
+                    args.pdf.clone(),
+                    args.force,
+                    args.max_retry_count,
+                    args.wait_time,
+                    args.verbose,
+                )
+                .await;
+            }
+        }
         Some(Commands::BuildCache) => {
             let result = cache::Cache::build().await;
             match result {
@@ -188,6 +572,15 @@ async fn main() {
                 }
             }
         }
+        Some(Commands::SummaryCache(args)) => {
+            manage_summary_cache(&args.command);
+        }
+        Some(Commands::Ask(args)) => {
+            ask_a_paper(args).await;
+        }
+        Some(Commands::Similar(args)) => {
+            find_similar_papers(args).await;
+        }
         None => {
             eprintln!("WARNING: No subcommand specified.");
         }
@@ -202,6 +595,7 @@ async fn post_a_new_paper(
     max_retry_count: u64,
     wait_time: u64,
     model_id: String,
+    force_resummarize: bool,
     verbose: bool,
 ) {
     let time = std::time::Instant::now();
@@ -220,8 +614,26 @@ async fn post_a_new_paper(
 
     // Collect paper metadata
     let collector = collector::Collector::new(max_retry_count, wait_time);
-    let reporter = reporter::Reporter::new();
-    let ai = ai::AI::new(&model_id);
+    let mut reporter = match reporter::Reporter::new() {
+        Ok(reporter) => reporter,
+        Err(e) => {
+            eprintln!("WARNING: Failed to configure the reporter: {}", e);
+            return;
+        }
+    };
+    reporter.check_properties().await;
+    let mut ai = match ai::AI::new(&model_id) {
+        Ok(ai) => ai,
+        Err(e) => {
+            eprintln!("WARNING: Failed to configure the LLM: {}", e);
+            return;
+        }
+    };
+    let cancel = llm::CancelToken::on_ctrl_c();
+    ai.cancel_token(cancel.clone())
+        .summary_cache(summary_cache::SummaryCache::new())
+        .force_resummarize(force_resummarize)
+        .faithfulness_checker(faithfulness::FaithfulnessChecker::new());
 
     match collector.update_from_ss(&mut paper, true).await {
         Ok(_) => {
@@ -268,7 +680,8 @@ async fn post_a_new_paper(
     }
 
     // Get original text
-    match paper.get_original_text(pdf, verbose).await {
+    let store = store::SectionStore::new();
+    match store.get_original_text(&mut paper, pdf, verbose).await {
         Ok(_) => {
             if verbose {
                 println!(
@@ -283,6 +696,19 @@ async fn post_a_new_paper(
         }
     }
 
+    // Check the quality of the extracted text
+    let report = quality::QualityGate::new().assess(&paper.document);
+    if !report.passed() {
+        eprintln!(
+            "WARNING: The paper needs review: {}: {}",
+            paper.title,
+            report.reason()
+        );
+        cache.add_needs_review(cache::PaperCache::from_paper(&paper, Some(report.reason())));
+        cache.save().unwrap();
+        return;
+    }
+
     // Get keywords
     match paper.get_keywords() {
         Ok(_) => {
@@ -300,8 +726,16 @@ async fn post_a_new_paper(
     }
 
     // Summarize the paper
-    match ai.summarize(&mut paper).await {
+    let result = ai.summarize(&mut paper).await;
+    println!("Usage: {}", paper.usage);
+    match result {
         Ok(_) => {
+            if !paper.summary.unsupported_claims.is_empty() {
+                println!(
+                    "Unsupported claims: {}",
+                    paper.summary.unsupported_claims.join("; ")
+                );
+            }
             if verbose {
                 println!(
                     "Finished summarizing the paper: {:.2}s",
@@ -311,6 +745,10 @@ async fn post_a_new_paper(
         }
         Err(e) => {
             eprintln!("WARNING: Failed to summarize the paper: {}", e);
+            if cancel.is_cancelled() {
+                cache.save().unwrap();
+                return;
+            }
         }
     }
 
@@ -343,6 +781,9 @@ async fn post_a_new_paper(
     match reporter.add_a_paper(&mut paper, &mut cache).await {
         Ok(code) => match code {
             StatusCode::Success => {
+                if let Some(mut index) = load_vector_index() {
+                    add_embedding(&mut index, &paper).await;
+                }
                 if verbose {
                     println!(
                         "Finished reporting the paper to Notion: {:.2}s",
@@ -377,11 +818,22 @@ async fn post_a_new_paper(
     cache.save().unwrap();
 }
 
+/// How `post_arxiv_papers` summarizes the papers.
+#[derive(Clone, Copy, Debug, Default)]
+struct SummaryMode {
+    /// Summarize the paper again even if its summary is cached
+    force_resummarize: bool,
+    /// Summarize the papers in one job of the Batch API before posting them
+    batch: bool,
+}
+
 async fn post_arxiv_papers(
     date: DateTime<Utc>,
     max_retry_count: u64,
     wait_time: u64,
     model_id: String,
+    budget: cost::Budget,
+    mode: SummaryMode,
     verbose: bool,
 ) {
     let time = std::time::Instant::now();
@@ -392,6 +844,16 @@ async fn post_arxiv_papers(
             return;
         }
     };
+    let mut batch_client = None;
+    if mode.batch {
+        match batch::BatchClient::new() {
+            Ok(client) => batch_client = Some(client),
+            Err(e) => {
+                eprintln!("WARNING: Failed to configure the Batch API: {}", e);
+                return;
+            }
+        }
+    }
 
     // Collect arXiv papers
     let collector = collector::Collector::new(max_retry_count, wait_time);
@@ -410,8 +872,90 @@ async fn post_arxiv_papers(
         );
     }
 
-    let ai = ai::AI::new(&model_id);
-    let reporter = reporter::Reporter::new();
+    // Triage the papers against the interest profile of the team
+    let cancel = llm::CancelToken::on_ctrl_c();
+    let triage = triage::InterestProfile::from_env()
+        .and_then(|profile| profile.map(triage::Triage::new).transpose());
+    let mut triage_usage = None;
+    match triage {
+        Ok(Some(mut triage)) => {
+            triage.cancel_token(cancel.clone()).budget(budget.clone());
+            let result = triage.triage(papers).await;
+            for (paper, score) in result.skipped.iter() {
+                println!("Skipped by triage: {}: {}", score, paper.title);
+            }
+            if verbose {
+                for (paper, score) in result.selected.iter() {
+                    println!("Selected by triage: {}: {}", score, paper.title);
+                }
+            }
+            println!(
+                "Triage: {} papers selected, {} skipped",
+                result.selected.len(),
+                result.skipped.len()
+            );
+            if let Some(usage) = triage.usage() {
+                println!("Triage usage: {}", usage);
+            }
+            triage_usage = triage.usage();
+            papers = result
+                .selected
+                .into_iter()
+                .map(|(paper, _)| paper)
+                .collect();
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("WARNING: Failed to load the interest profile: {}", e);
+            return;
+        }
+    }
+
+    let mut ai = match ai::AI::new(&model_id) {
+        Ok(ai) => ai,
+        Err(e) => {
+            eprintln!("WARNING: Failed to configure the LLM: {}", e);
+            return;
+        }
+    };
+    ai.cancel_token(cancel.clone())
+        .summary_cache(summary_cache::SummaryCache::new())
+        .force_resummarize(mode.force_resummarize)
+        .faithfulness_checker(faithfulness::FaithfulnessChecker::new());
+    // the ratings of the triage count toward the total usage and the budget
+    if let Some(usage) = triage_usage.as_ref() {
+        ai.add_usage(usage);
+    }
+    let mut reporter = match reporter::Reporter::new() {
+        Ok(reporter) => reporter,
+        Err(e) => {
+            eprintln!("WARNING: Failed to configure the reporter: {}", e);
+            return;
+        }
+    };
+    reporter.check_properties().await;
+    let mut store = store::SectionStore::new();
+    store.wait_time(wait_time);
+    let quality_gate = quality::QualityGate::new();
+    let mut vector_index = load_vector_index();
+    // results of the papers summarized in a batch, by index
+    let mut batch_results: FxHashMap<usize, Result<()>> = FxHashMap::default();
+    if let Some(client) = batch_client.as_mut() {
+        client.cancel_token(cancel.clone());
+        batch_results = summarize_in_batch(
+            &mut papers,
+            &collector,
+            client,
+            &ai,
+            &cache,
+            &budget,
+            verbose,
+        )
+        .await;
+    }
+    // titles and unsupported claims of the papers flagged by the faithfulness check
+    let mut unfaithful_papers: Vec<(String, Vec<String>)> = Vec::new();
+    let mut budget_used_up = false;
 
     let bar = ProgressBar::new(papers.len() as u64);
     bar.set_style(
@@ -421,7 +965,12 @@ async fn post_arxiv_papers(
             .progress_chars("=> "),
     );
     bar.set_message("Processing papers");
-    for paper in papers.iter_mut() {
+    for (index, paper) in papers.iter_mut().enumerate() {
+        // stop after Ctrl-C and save the papers processed so far
+        if cancel.is_cancelled() {
+            bar.println("Cancelled: the remaining papers are skipped");
+            break;
+        }
         let time = std::time::Instant::now();
         bar.println(format!(
             "Start processing a paper: {}",
@@ -440,8 +989,13 @@ async fn post_arxiv_papers(
             bar.inc(1);
             continue;
         }
-        // Collect paper metadata
-        match collector.update_from_ss(paper, false).await {
+        // Collect paper metadata (already collected for the papers summarized in a batch)
+        let metadata = if batch_results.contains_key(&index) {
+            Ok(())
+        } else {
+            collector.update_from_ss(paper, false).await
+        };
+        match metadata {
             Ok(_) => {
                 bar.set_message(format!(
                     "Finished getting metadata from SS: ({:.2}s)",
@@ -463,7 +1017,7 @@ async fn post_arxiv_papers(
         }
 
         // Get original text
-        match paper.get_original_text(None, verbose).await {
+        match store.get_original_text(paper, None, verbose).await {
             Ok(_) => {
                 bar.set_message(format!(
                     "Finished getting original text: ({:.2}s)",
@@ -481,13 +1035,16 @@ async fn post_arxiv_papers(
             }
         }
 
-        if paper.original_text.len() < 4 {
-            eprintln!("WARNING: The paper is too short: {}", paper.title);
+        // Check the quality of the extracted text
+        let report = quality_gate.assess(&paper.document);
+        if !report.passed() {
+            eprintln!(
+                "WARNING: The paper needs review: {}: {}",
+                paper.title,
+                report.reason()
+            );
             bar.inc(1);
-            cache.failed_papers.push(cache::PaperCache::from_paper(
-                &paper,
-                Some(String::from("The paper is too short")),
-            ));
+            cache.add_needs_review(cache::PaperCache::from_paper(paper, Some(report.reason())));
             continue;
         }
 
@@ -510,9 +1067,36 @@ async fn post_arxiv_papers(
             }
         }
 
+        // Skip the papers left to summarize once the budget of the run is used up; the papers
+        // summarized in a batch are paid and the cached summaries cost nothing
+        if !batch_results.contains_key(&index) && !ai.has_cached_summary(paper) {
+            if let Some(reason) = budget.exceeded(&ai.run_usage()) {
+                if !budget_used_up {
+                    bar.println(format!(
+                        "The budget is used up ({}): the papers left to summarize are skipped",
+                        reason
+                    ));
+                    budget_used_up = true;
+                }
+                bar.inc(1);
+                continue;
+            }
+        }
+
         // Summarize the paper
-        match ai.summarize(paper).await {
+        let result = match batch_results.remove(&index) {
+            Some(result) => result,
+            None => ai.summarize(paper).await,
+        };
+        bar.println(format!("Usage: {}", paper.usage));
+        match result {
             Ok(_) => {
+                if !paper.summary.unsupported_claims.is_empty() {
+                    unfaithful_papers.push((
+                        paper.title.clone(),
+                        paper.summary.unsupported_claims.clone(),
+                    ));
+                }
                 bar.set_message(format!(
                     "Finished summarizing the paper: ({:.2}s)",
                     time.elapsed().as_secs_f32()
@@ -520,6 +1104,9 @@ async fn post_arxiv_papers(
             }
             Err(e) => {
                 eprintln!("WARNING: Failed to summarize the paper: {}", e);
+                if cancel.is_cancelled() {
+                    break;
+                }
                 bar.inc(1);
                 cache.failed_papers.push(cache::PaperCache::from_paper(
                     &paper,
@@ -565,6 +1152,9 @@ async fn post_arxiv_papers(
         match reporter.add_a_paper(paper, &mut cache).await {
             Ok(status) => match status {
                 StatusCode::Success => {
+                    if let Some(index) = vector_index.as_mut() {
+                        add_embedding(index, paper).await;
+                    }
                     bar.set_message(format!(
                         "Finished reporting the paper to Notion: ({:.2}s)",
                         time.elapsed().as_secs_f32()
@@ -607,8 +1197,565 @@ async fn post_arxiv_papers(
         bar.inc(1);
     }
     bar.finish();
+    if !cancel.is_cancelled() {
+        post_digest(&date, &papers, &ai, &reporter, &budget).await;
+    }
+    println!("Total usage: {}", ai.run_usage());
+    if !unfaithful_papers.is_empty() {
+        println!(
+            "Papers with unsupported claims: {}",
+            unfaithful_papers.len()
+        );
+        for (title, claims) in unfaithful_papers.iter() {
+            println!("- {}: {}", title, claims.join("; "));
+        }
+    }
     cache.save().unwrap();
 }
 
+async fn parse_a_paper(
+    title: String,
+    pdf: Option<String>,
+    force: bool,
+    max_retry_count: u64,
+    wait_time: u64,
+    verbose: bool,
+) {
+    let time = std::time::Instant::now();
+    let mut paper = common::Paper::default();
+    paper.title = title;
+
+    // Collect paper metadata to identify the paper in the store
+    let collector = collector::Collector::new(max_retry_count, wait_time);
+    if let Err(e) = collector.update_from_ss(&mut paper, true).await {
+        eprintln!(
+            "WARNING: Failed to collect paper metadata from Semantic Scholar: {}",
+            e
+        );
+    }
+    if let Err(e) = collector.update_from_arxiv(&mut paper, true).await {
+        eprintln!(
+            "WARNING: Failed to collect paper metadata from arXiv: {}",
+            e
+        );
+    }
+
+    let store = store::SectionStore::new();
+    if store.contains(&paper) && !force {
+        println!("The paper is already parsed: {:?}", store.path(&paper));
+        return;
+    }
+
+    match paper
+        .get_original_text(pdf, &latex::EprintDownloader::default(), verbose)
+        .await
+    {
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("WARNING: Failed to get original text: {}", e);
+            return;
+        }
+    }
+    if verbose {
+        let report = quality::QualityGate::new().assess(&paper.document);
+        if report.passed() {
+            println!(
+                "Quality check passed: readable word ratio {:.2}, language {}",
+                report.readable_word_ratio, report.language
+            );
+        } else {
+            println!("The paper needs review: {}", report.reason());
+        }
+    }
+    match store.save(&paper) {
+        Ok(_) => {
+            println!(
+                "Finished parsing the paper: {:?} ({:.2}s)",
+                store.path(&paper),
+                time.elapsed().as_secs_f32()
+            );
+        }
+        Err(e) => {
+            eprintln!("WARNING: Failed to save the sections: {}", e);
+        }
+    }
+}
+
+async fn parse_arxiv_papers(
+    date: DateTime<Utc>,
+    force: bool,
+    max_retry_count: u64,
+    wait_time: u64,
+    verbose: bool,
+) {
+    let time = std::time::Instant::now();
+
+    // Collect arXiv papers
+    let collector = collector::Collector::new(max_retry_count, wait_time);
+    let mut papers = match collector.collect_papers_from_arxiv(date).await {
+        Ok(papers) => papers,
+        Err(e) => {
+            eprintln!("WARNING: Failed to collect arXiv papers: {}", e);
+            return;
+        }
+    };
+
+    if verbose {
+        println!(
+            "Finished collecting arXiv papers: {:.2}s",
+            time.elapsed().as_secs_f32()
+        );
+    }
+
+    let store = store::SectionStore::new();
+    let eprints = latex::EprintDownloader::new(wait_time);
+    let bar = ProgressBar::new(papers.len() as u64);
+    bar.set_style(
+        indicatif::ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] [{bar:10.green/blue}] {pos:>3}/{len:3}: {msg}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    bar.set_message("Parsing papers");
+    let mut parsed_count = 0;
+    for paper in papers.iter_mut() {
+        if store.contains(paper) && !force {
+            bar.inc(1);
+            continue;
+        }
+        match paper.get_original_text(None, &eprints, verbose).await {
+            Ok(_) => {}
+            Err(e) => {
+                bar.println(format!(
+                    "WARNING: Failed to get original text: {}: {}",
+                    e, paper.title
+                ));
+                bar.inc(1);
+                continue;
+            }
+        }
+        match store.save(paper) {
+            Ok(_) => {
+                parsed_count += 1;
+            }
+            Err(e) => {
+                bar.println(format!("WARNING: Failed to save the sections: {}", e));
+            }
+        }
+        bar.inc(1);
+    }
+    bar.finish();
+    println!(
+        "Finished parsing {} papers: {:?} ({:.2}s)",
+        parsed_count,
+        store.dir,
+        time.elapsed().as_secs_f32()
+    );
+}
+
+fn manage_summary_cache(command: &SummaryCacheCommands) {
+    let cache = summary_cache::SummaryCache::new();
+    match command {
+        SummaryCacheCommands::List => {
+            let entries = match cache.entries() {
+                Ok(entries) => entries,
+                Err(e) => {
+                    eprintln!("WARNING: Failed to read the summary cache: {}", e);
+                    return;
+                }
+            };
+            for entry in entries.iter() {
+                println!(
+                    "{} {} {} {} ${:.4} {}",
+                    entry.key.get(..12).unwrap_or(&entry.key),
+                    entry.created_at.format("%Y-%m-%d %H:%M"),
+                    entry.model_id,
+                    entry.template_version,
+                    entry.usage.cost,
+                    entry.title
+                );
+            }
+            println!("{} summaries in {:?}", entries.len(), cache.dir);
+        }
+        SummaryCacheCommands::Prune(args) => {
+            if !args.all
+                && args.older_than_days.is_none()
+                && args.model_id.is_none()
+                && args.keep_template_version.is_none()
+            {
+                eprintln!(
+                    "WARNING: No condition specified. Use --all to remove all the summaries."
+                );
+                return;
+            }
+            let threshold = args
+                .older_than_days
+                .map(|days| Utc::now() - chrono::Duration::days(days));
+            let result = cache.prune(|entry| {
+                if let Some(threshold) = threshold {
+                    if entry.created_at >= threshold {
+                        return false;
+                    }
+                }
+                if let Some(model_id) = args.model_id.as_ref() {
+                    if &entry.model_id != model_id {
+                        return false;
+                    }
+                }
+                if let Some(version) = args.keep_template_version.as_ref() {
+                    if &entry.template_version == version {
+                        return false;
+                    }
+                }
+                return true;
+            });
+            match result {
+                Ok(count) => println!("Removed {} summaries from {:?}", count, cache.dir),
+                Err(e) => eprintln!("WARNING: Failed to prune the summary cache: {}", e),
+            }
+        }
+    }
+}
+
+async fn ask_a_paper(args: &AskArgs) {
+    let mut paper = common::Paper::default();
+    paper.title = args.title.clone();
+
+    // Load the parsed sections from the store, or parse the PDF
+    let store = store::SectionStore::new();
+    let mut stored = false;
+    if let Some(arxiv_id) = args.arxiv_id.as_ref() {
+        paper.arxiv_id = arxiv_id.clone();
+    }
+    if !paper.arxiv_id.is_empty() && args.pdf.is_none() {
+        stored = match store.load(&mut paper) {
+            Ok(stored) => stored,
+            Err(e) => {
+                eprintln!("WARNING: Failed to load the sections: {}", e);
+                false
+            }
+        };
+    }
+    if !stored {
+        let collector = collector::Collector::new(args.max_retry_count, args.wait_time);
+        if let Err(e) = collector.update_from_ss(&mut paper, true).await {
+            eprintln!(
+                "WARNING: Failed to collect paper metadata from Semantic Scholar: {}",
+                e
+            );
+        }
+        if let Err(e) = collector.update_from_arxiv(&mut paper, true).await {
+            eprintln!(
+                "WARNING: Failed to collect paper metadata from arXiv: {}",
+                e
+            );
+        }
+        if let Err(e) = store
+            .get_original_text(&mut paper, args.pdf.clone(), args.verbose)
+            .await
+        {
+            eprintln!("WARNING: Failed to get original text: {}", e);
+            return;
+        }
+    }
+
+    let mut ai = match ai::AI::new(&args.model_id) {
+        Ok(ai) => ai,
+        Err(e) => {
+            eprintln!("WARNING: Failed to configure the LLM: {}", e);
+            return;
+        }
+    };
+    let cancel = llm::CancelToken::on_ctrl_c();
+    ai.cancel_token(cancel.clone());
+    let mut chat = qa::PaperChat::new(&ai, &paper);
+    chat.top_k(args.top_k);
+
+    if let Some(question) = args.question.as_ref() {
+        print_answer(chat.ask(question).await, args.verbose);
+    } else {
+        println!(
+            "Ask questions about \"{}\" (an empty line to quit)",
+            paper.title
+        );
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        loop {
+            print!("> ");
+            let _ = std::io::stdout().flush();
+            let line = tokio::select! {
+                line = lines.next_line() => line,
+                _ = cancel.cancelled() => break,
+            };
+            let question = match line {
+                Ok(Some(line)) if !line.trim().is_empty() => line,
+                Ok(_) => break,
+                Err(e) => {
+                    eprintln!("WARNING: Failed to read the question: {}", e);
+                    break;
+                }
+            };
+            print_answer(chat.ask(question.trim()).await, args.verbose);
+            if cancel.is_cancelled() {
+                break;
+            }
+        }
+    }
+    println!("Usage: {}", ai.run_usage());
+}
+
+fn print_answer(answer: Result<qa::Answer>, verbose: bool) {
+    match answer {
+        Ok(answer) => {
+            println!("{}\n", answer.text);
+            let mut sections = Vec::new();
+            for passage in answer.passages.iter() {
+                if !sections.contains(&passage.section) {
+                    sections.push(passage.section.clone());
+                }
+            }
+            println!("Passages: {}", sections.join(", "));
+            if verbose {
+                for passage in answer.passages.iter() {
+                    println!("[{}] {}", passage.section, passage.text);
+                }
+            }
+        }
+        Err(e) => eprintln!("WARNING: Failed to answer the question: {}", e),
+    }
+}
+
+/// Prepare the papers to be posted (metadata, original text, quality and keywords) and
+/// summarize them in one batch within the budget. The papers that fail here are left to
+/// the posting loop, which records the failures; so are all the papers when the batch
+/// cannot be submitted.
+async fn summarize_in_batch(
+    papers: &mut [common::Paper],
+    collector: &collector::Collector,
+    client: &batch::BatchClient,
+    ai: &ai::AI,
+    cache: &cache::Cache,
+    budget: &cost::Budget,
+    verbose: bool,
+) -> FxHashMap<usize, Result<()>> {
+    let mut store = store::SectionStore::new();
+    store.wait_time(collector.wait_time());
+    let quality_gate = quality::QualityGate::new();
+    let mut indices = Vec::new();
+    for (index, paper) in papers.iter_mut().enumerate() {
+        if cache.is_exist_paper(&paper.title) {
+            continue;
+        }
+        if collector.update_from_ss(paper, false).await.is_err()
+            || store.get_original_text(paper, None, verbose).await.is_err()
+            || !quality_gate.assess(&paper.document).passed()
+            || paper.get_keywords().is_err()
+        {
+            continue;
+        }
+        indices.push(index);
+    }
+
+    println!("Summarizing {} papers in a batch", indices.len());
+    return match client.summarize(ai, papers, &indices, budget).await {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!(
+                "WARNING: Failed to summarize the papers in a batch: {}; summarizing them one by one",
+                e
+            );
+            FxHashMap::default()
+        }
+    };
+}
+
+/// Write the digest of the day's papers to a Markdown file and post it to Notion.
+async fn post_digest(
+    date: &DateTime<Utc>,
+    papers: &[common::Paper],
+    ai: &ai::AI,
+    reporter: &reporter::Reporter,
+    budget: &cost::Budget,
+) {
+    let mut digest = digest::Digest::from_papers(&date.format("%Y-%m-%d").to_string(), papers);
+    if digest.is_empty() {
+        return;
+    }
+    // the groups are written without the trends once the budget is used up
+    if let Some(reason) = budget.exceeded(&ai.run_usage()) {
+        println!(
+            "The budget is used up ({}): the trends of the digest are skipped",
+            reason
+        );
+    } else if let Err(e) = digest.write_narrative(ai).await {
+        eprintln!("WARNING: Failed to write the trends of the digest: {}", e);
+    }
+    match digest.save_markdown() {
+        Ok(path) => println!("Digest: {}", path.display()),
+        Err(e) => eprintln!("WARNING: Failed to save the digest: {}", e),
+    }
+    match reporter.add_digest(&digest).await {
+        Ok(page_id) => println!("Digest page: {}", utils::notion_url(&page_id)),
+        Err(e) => eprintln!("WARNING: Failed to post the digest to Notion: {}", e),
+    }
+}
+
+/// The vector index, or `None` if it cannot be read: the papers are then not embedded,
+/// so that the stored embeddings are not overwritten.
+fn load_vector_index() -> Option<embedding::VectorIndex> {
+    return match embedding::VectorIndex::load() {
+        Ok(index) => Some(index),
+        Err(e) => {
+            eprintln!(
+                "WARNING: Failed to load the vector index, the papers are not embedded: {}",
+                e
+            );
+            None
+        }
+    };
+}
+
+/// Embed a posted paper for `similar`; a failure does not stop the run.
+async fn add_embedding(index: &mut embedding::VectorIndex, paper: &common::Paper) {
+    let client = embedding::EmbeddingClient::new();
+    if let Err(e) = index.add_paper(&client, paper).await {
+        eprintln!("WARNING: Failed to embed the paper: {}", e);
+    }
+}
+
+/// Embed the posted papers missing from the vector index, with the overview of their cached
+/// summary if any, so that the papers processed before the index existed can be found.
+async fn reindex_papers() {
+    let mut index = match embedding::VectorIndex::load() {
+        Ok(index) => index,
+        Err(e) => {
+            eprintln!("WARNING: Failed to load the vector index: {}", e);
+            return;
+        }
+    };
+    let reporter = match reporter::Reporter::new() {
+        Ok(reporter) => reporter,
+        Err(e) => {
+            eprintln!("WARNING: Failed to configure the reporter: {}", e);
+            return;
+        }
+    };
+    let mut papers = match reporter.get_posted_papers().await {
+        Ok(papers) => papers,
+        Err(e) => {
+            eprintln!("WARNING: Failed to load papers from database: {}", e);
+            return;
+        }
+    };
+    let client = embedding::EmbeddingClient::new();
+    papers.retain(|paper| !paper.title.is_empty() && !index.contains(paper, &client.model));
+    if papers.is_empty() {
+        println!("Every posted paper is already embedded.");
+        return;
+    }
+
+    let summaries = summary_cache::SummaryCache::new()
+        .entries()
+        .unwrap_or_else(|e| {
+            eprintln!("WARNING: Failed to read the summary cache: {}", e);
+            Vec::new()
+        });
+    for paper in papers.iter_mut() {
+        // the newest summary of the paper
+        let entry = summaries.iter().rev().find(|entry| {
+            (!paper.arxiv_id.is_empty() && entry.arxiv_id == paper.arxiv_id)
+                || entry.title.to_lowercase() == paper.title.to_lowercase()
+        });
+        if let Some(entry) = entry {
+            paper.summary.overview = entry.summary.overview.clone();
+        }
+    }
+
+    println!("Embedding {} papers...", papers.len());
+    match index.add_papers(&client, &papers).await {
+        Ok(_) => println!(
+            "Finished embedding: {} papers in the vector index",
+            index.len()
+        ),
+        Err(e) => eprintln!("WARNING: Failed to embed the papers: {}", e),
+    }
+}
+
+async fn find_similar_papers(args: &SimilarArgs) {
+    if args.reindex {
+        reindex_papers().await;
+        return;
+    }
+    let index = match embedding::VectorIndex::load() {
+        Ok(index) => index,
+        Err(e) => {
+            eprintln!("WARNING: Failed to load the vector index: {}", e);
+            return;
+        }
+    };
+    if index.is_empty() {
+        eprintln!("WARNING: No paper has been embedded yet.");
+        return;
+    }
+    let client = embedding::EmbeddingClient::new();
+
+    // a processed paper is compared by its stored embedding, anything else is embedded
+    let stored = index
+        .find(args.arxiv_id.as_deref(), args.title.as_deref())
+        .filter(|paper| paper.model == client.model);
+    let vector = match stored {
+        Some(paper) => paper.vector.clone(),
+        None => {
+            let text = if let Some(text) = args.text.as_ref() {
+                text.clone()
+            } else if let Some(title) = args.title.as_ref() {
+                let mut paper = common::Paper::default();
+                paper.title = title.clone();
+                let collector = collector::Collector::new(args.max_retry_count, args.wait_time);
+                if let Err(e) = collector.update_from_ss(&mut paper, false).await {
+                    eprintln!(
+                        "WARNING: Failed to collect paper metadata from Semantic Scholar: {}",
+                        e
+                    );
+                }
+                embedding::paper_text(&paper)
+            } else if args.arxiv_id.is_some() {
+                eprintln!("WARNING: The paper is not in the vector index: use --title or --text.");
+                return;
+            } else {
+                eprintln!("WARNING: Give --title, --arxiv-id or --text.");
+                return;
+            };
+            match client.embed(&[text]).await {
+                Ok(mut vectors) => vectors.pop().unwrap_or_default(),
+                Err(e) => {
+                    eprintln!("WARNING: Failed to embed the query: {}", e);
+                    return;
+                }
+            }
+        }
+    };
+
+    let mut rank = 0;
+    for (paper, score) in index.search(&vector, &client.model, args.top_k + 1) {
+        // the query paper itself
+        if stored
+            .map(|query| std::ptr::eq(query, paper))
+            .unwrap_or(false)
+            || rank >= args.top_k
+        {
+            continue;
+        }
+        rank += 1;
+        println!("{}. [{:.3}] {}", rank, score, paper.title);
+        let url = paper.notion_url();
+        if !url.is_empty() {
+            println!("   {}", url);
+        }
+    }
+    if rank == 0 {
+        println!("No similar paper was found.");
+    }
+}
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,324 @@
+//! This module loads the prompt templates used by `AI`.
+//! Templates are TOML files in `PROMPT_DIR`, one per output language ("summary.toml",
+//! "summary_en.toml", "summary_bilingual.toml"); the built-in templates are used
+//! when no directory is configured, and fill in the optional messages a template leaves out.
+//! Messages may contain the variables `{title}`, `{abstract}`, `{instruction}`, `{paper_xml}`,
+//! `{references_xml}` and `{notes_xml}`; the messages of the Q&A about a paper may contain
+//! `{title}`, `{passages_xml}` and `{question}`.
+use anyhow::Result;
+use dotenvy::dotenv;
+use fxhash::FxHashMap;
+use openai_tools::Message;
+use serde::Deserialize;
+use std::path::Path;
+use std::str::FromStr;
+
+const TEMPLATE_JA: &str = include_str!("prompts/summary.toml");
+const TEMPLATE_EN: &str = include_str!("prompts/summary_en.toml");
+const TEMPLATE_BILINGUAL: &str = include_str!("prompts/summary_bilingual.toml");
+
+/// Language of the summary.
+/// In bilingual mode every text field is written in Japanese and in English (`{field}_en`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputLanguage {
+    #[default]
+    Japanese,
+    English,
+    Bilingual,
+}
+
+impl OutputLanguage {
+    /// Read the language from `OUTPUT_LANGUAGE` (Japanese if it is not set or unknown).
+    pub fn from_env() -> OutputLanguage {
+        dotenv().ok();
+        return match std::env::var("OUTPUT_LANGUAGE") {
+            Ok(language) if !language.trim().is_empty() => language.parse().unwrap_or_else(|e| {
+                eprintln!("WARNING: {}", e);
+                OutputLanguage::Japanese
+            }),
+            _ => OutputLanguage::Japanese,
+        };
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputLanguage::Japanese => "ja",
+            OutputLanguage::English => "en",
+            OutputLanguage::Bilingual => "bilingual",
+        }
+    }
+
+    /// File name of the template in `PROMPT_DIR`
+    pub fn template_file(&self) -> &'static str {
+        match self {
+            OutputLanguage::Japanese => "summary.toml",
+            OutputLanguage::English => "summary_en.toml",
+            OutputLanguage::Bilingual => "summary_bilingual.toml",
+        }
+    }
+
+    fn default_template(&self) -> &'static str {
+        match self {
+            OutputLanguage::Japanese => TEMPLATE_JA,
+            OutputLanguage::English => TEMPLATE_EN,
+            OutputLanguage::Bilingual => TEMPLATE_BILINGUAL,
+        }
+    }
+}
+
+impl FromStr for OutputLanguage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.trim().to_lowercase().as_str() {
+            "ja" | "japanese" => Ok(OutputLanguage::Japanese),
+            "en" | "english" => Ok(OutputLanguage::English),
+            "bilingual" | "ja+en" => Ok(OutputLanguage::Bilingual),
+            _ => Err(anyhow::anyhow!("Unknown output language: {}", s)),
+        };
+    }
+}
+
+impl std::fmt::Display for OutputLanguage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PromptTemplate {
+    /// Version recorded on each summary (defaults to a hash of the template)
+    #[serde(default)]
+    pub version: String,
+    pub system: String,
+    pub instruction: String,
+    /// User messages to summarize the whole paper
+    pub summary: Vec<String>,
+    /// User messages to extract the key points of a part of a long paper
+    pub section: Vec<String>,
+    /// User messages to merge the key points into the summary
+    pub merge: Vec<String>,
+    /// Messages appended when the response is not valid JSON
+    pub retry_system: String,
+    pub retry_user: String,
+    /// Message asking to fix a response that does not match the schema (`{error}`)
+    #[serde(default)]
+    pub repair: String,
+    /// System message of the Q&A about a paper (`{title}`)
+    #[serde(default)]
+    pub ask_system: String,
+    /// Question with the passages retrieved from the paper (`{passages_xml}`, `{question}`)
+    #[serde(default)]
+    pub ask: String,
+    /// System message of the daily digest
+    #[serde(default)]
+    pub digest_system: String,
+    /// Request of the trend narrative of a day's papers (`{date}`, `{papers_xml}`)
+    #[serde(default)]
+    pub digest: String,
+}
+
+impl PromptTemplate {
+    pub fn parse(text: &str, name: &str) -> Result<PromptTemplate> {
+        let mut template: PromptTemplate = toml::from_str(text)
+            .map_err(|e| anyhow::anyhow!("Failed to parse prompt template {}: {}", name, e))?;
+        if template.version.trim().is_empty() {
+            template.version = format!("{}-{:08x}", name, fxhash::hash32(text));
+        }
+        return Ok(template);
+    }
+
+    pub fn load(path: &Path) -> Result<PromptTemplate> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or(String::from("summary"));
+        return PromptTemplate::parse(&text, &name);
+    }
+
+    /// Load the template of `language` from `PROMPT_DIR`, or the built-in template if it is not set.
+    pub fn from_env(language: OutputLanguage) -> Result<PromptTemplate> {
+        dotenv().ok();
+        return match std::env::var("PROMPT_DIR") {
+            Ok(dir) if !dir.trim().is_empty() => {
+                let mut template =
+                    PromptTemplate::load(&Path::new(dir.trim()).join(language.template_file()))?;
+                template.fill_missing(language)?;
+                Ok(template)
+            }
+            _ => PromptTemplate::parse(language.default_template(), "summary"),
+        };
+    }
+
+    /// Take the optional messages left out of the template from the built-in template of `language`.
+    pub fn fill_missing(&mut self, language: OutputLanguage) -> Result<()> {
+        let defaults = PromptTemplate::parse(language.default_template(), "summary")?;
+        for (message, default) in [
+            (&mut self.repair, defaults.repair),
+            (&mut self.ask_system, defaults.ask_system),
+            (&mut self.ask, defaults.ask),
+            (&mut self.digest_system, defaults.digest_system),
+            (&mut self.digest, defaults.digest),
+        ] {
+            if message.trim().is_empty() {
+                *message = default;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Build the system message and the user messages of `templates` with `vars`.
+    /// `{instruction}` is always available.
+    pub fn messages(&self, templates: &[String], vars: &[(&str, &str)]) -> Vec<Message> {
+        let instruction = render(&self.instruction, vars);
+        let mut vars = vars.to_vec();
+        vars.push(("instruction", instruction.trim()));
+
+        let mut messages = vec![Message::new("system", &render(&self.system, &vars))];
+        for template in templates {
+            messages.push(Message::new("user", &render(template, &vars)));
+        }
+        return messages;
+    }
+
+    pub fn retry_messages(&self) -> Vec<Message> {
+        return vec![
+            Message::new("system", &self.retry_system),
+            Message::new("user", &self.retry_user),
+        ];
+    }
+
+    pub fn repair_message(&self, error: &str) -> Message {
+        return Message::new("user", &render(&self.repair, &[("error", error)]));
+    }
+
+    pub fn ask_system_message(&self, title: &str) -> Message {
+        return Message::new("system", &render(&self.ask_system, &[("title", title)]));
+    }
+
+    pub fn digest_system_message(&self) -> Message {
+        return Message::new("system", &self.digest_system);
+    }
+
+    pub fn digest_message(&self, date: &str, papers_xml: &str) -> Message {
+        return Message::new(
+            "user",
+            &render(&self.digest, &[("date", date), ("papers_xml", papers_xml)]),
+        );
+    }
+
+    pub fn ask_message(&self, passages_xml: &str, question: &str) -> Message {
+        return Message::new(
+            "user",
+            &render(
+                &self.ask,
+                &[("passages_xml", passages_xml), ("question", question)],
+            ),
+        );
+    }
+}
+
+/// Replace the `{name}` placeholders of `vars` in `template`.
+/// Unknown placeholders are kept as they are, and the values are not expanded again.
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    let vars = vars.iter().cloned().collect::<FxHashMap<&str, &str>>();
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after
+            .find('}')
+            .and_then(|end| vars.get(&after[..end]).map(|value| (end, *value)));
+        match value {
+            Some((end, value)) => {
+                text.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = after;
+            }
+        }
+    }
+    text.push_str(rest);
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let vars = [("title", "Attention {title}"), ("paper_xml", "<paper/>")];
+        assert_eq!(
+            render("Title: {title}\n{paper_xml} {\"json\": {unknown}}", &vars),
+            "Title: Attention {title}\n<paper/> {\"json\": {unknown}}"
+        );
+    }
+
+    #[test]
+    fn test_default_template() {
+        let template = PromptTemplate::parse(TEMPLATE_JA, "summary").unwrap();
+        assert_eq!(template.version, "summary-ja-4");
+        let messages = template.messages(&template.summary, &[("title", "A Paper")]);
+        assert_eq!(messages.len(), template.summary.len() + 1);
+        assert_eq!(messages[0].role, "system");
+        assert!(messages[1].content.ends_with("A Paper"));
+        assert!(messages[2].content.contains("[is_survey]"));
+    }
+
+    #[test]
+    fn test_output_language() {
+        assert_eq!(
+            "EN".parse::<OutputLanguage>().unwrap(),
+            OutputLanguage::English
+        );
+        assert_eq!(
+            "ja+en".parse::<OutputLanguage>().unwrap(),
+            OutputLanguage::Bilingual
+        );
+        assert!("fr".parse::<OutputLanguage>().is_err());
+        for language in [
+            OutputLanguage::Japanese,
+            OutputLanguage::English,
+            OutputLanguage::Bilingual,
+        ] {
+            let template = PromptTemplate::parse(language.default_template(), "summary").unwrap();
+            assert!(template.version.contains(language.as_str()));
+        }
+    }
+
+    #[test]
+    fn test_template_without_version() {
+        let text = r#"
+            system = "You are a research assistant."
+            instruction = "Summarize {title}."
+            summary = ["{instruction}", "{paper_xml}"]
+            section = ["{paper_xml}"]
+            merge = ["{notes_xml}"]
+            retry_system = "Answer in JSON."
+            retry_user = "Summarize."
+        "#;
+        let mut template = PromptTemplate::parse(text, "english").unwrap();
+        assert!(template.repair.is_empty());
+        template.fill_missing(OutputLanguage::English).unwrap();
+        assert!(template.version.starts_with("english-"));
+        assert_eq!(template.version.len(), "english-".len() + 8);
+
+        let messages = template.messages(&template.summary, &[("title", "BERT")]);
+        assert_eq!(messages[1].content, "Summarize BERT.");
+        assert_eq!(messages[2].content, "{paper_xml}");
+        assert!(template
+            .repair_message("overview: missing")
+            .content
+            .contains("The JSON you returned has the following problems: overview: missing"));
+        assert!(template
+            .digest_message("2024-06-01", "<papers/>")
+            .content
+            .contains("published on 2024-06-01"));
+    }
+}
@@ -0,0 +1,331 @@
+//! This module answers questions about a paper.
+//! The paragraphs, figures and tables of the parsed paper are indexed with BM25; each question
+//! is answered from the passages retrieved for it, and the answer cites their section titles.
+use crate::ai::AI;
+use crate::common::Paper;
+use crate::document::Document;
+use crate::section::SectionRole;
+use crate::serializer::escape_xml;
+use crate::tokens::count_message_tokens;
+use anyhow::Result;
+use fxhash::FxHashMap;
+use openai_tools::Message;
+
+/// Paragraphs longer than this many words are split into several passages.
+const PASSAGE_WORDS: usize = 200;
+
+/// BM25 parameters
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Passage {
+    /// Title of the section, or the label of the figure or the table
+    pub section: String,
+    pub text: String,
+}
+
+impl Passage {
+    /// All the passages of the document except the references.
+    pub fn from_document(document: &Document) -> Vec<Passage> {
+        let mut passages = Vec::new();
+        for section in document.iter() {
+            if section.role == SectionRole::References {
+                continue;
+            }
+            for paragraph in section.contents.iter() {
+                let words = paragraph.split_whitespace().collect::<Vec<&str>>();
+                for chunk in words.chunks(PASSAGE_WORDS) {
+                    passages.push(Passage {
+                        section: section.title.clone(),
+                        text: chunk.join(" "),
+                    });
+                }
+            }
+        }
+        for figure in document.figures.iter() {
+            passages.push(Passage {
+                section: figure.label.clone(),
+                text: figure.caption.clone(),
+            });
+        }
+        for table in document.tables.iter() {
+            let mut text = table.caption.clone();
+            for row in table.rows.iter() {
+                text.push('\n');
+                text.push_str(&row.join(" | "));
+            }
+            passages.push(Passage {
+                section: table.label.clone(),
+                text,
+            });
+        }
+        return passages;
+    }
+}
+
+fn terms(text: &str) -> Vec<String> {
+    return text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_string())
+        .collect();
+}
+
+/// BM25 index of the passages of a paper.
+#[derive(Clone, Debug)]
+pub struct Bm25Index {
+    passages: Vec<Passage>,
+    term_freqs: Vec<FxHashMap<String, usize>>,
+    lengths: Vec<usize>,
+    doc_freqs: FxHashMap<String, usize>,
+    average_length: f64,
+}
+
+impl Bm25Index {
+    pub fn new(passages: Vec<Passage>) -> Bm25Index {
+        let mut term_freqs = Vec::new();
+        let mut lengths = Vec::new();
+        let mut doc_freqs: FxHashMap<String, usize> = FxHashMap::default();
+        for passage in passages.iter() {
+            let mut freqs: FxHashMap<String, usize> = FxHashMap::default();
+            let passage_terms = terms(&format!("{} {}", passage.section, passage.text));
+            for term in passage_terms.iter() {
+                *freqs.entry(term.clone()).or_insert(0) += 1;
+            }
+            for term in freqs.keys() {
+                *doc_freqs.entry(term.clone()).or_insert(0) += 1;
+            }
+            lengths.push(passage_terms.len());
+            term_freqs.push(freqs);
+        }
+        let average_length = if lengths.is_empty() {
+            0.0
+        } else {
+            lengths.iter().sum::<usize>() as f64 / lengths.len() as f64
+        };
+        Bm25Index {
+            passages,
+            term_freqs,
+            lengths,
+            doc_freqs,
+            average_length,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        return self.passages.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.passages.is_empty();
+    }
+
+    /// The `k` passages with the highest BM25 scores for `query`, the best first.
+    /// Passages sharing no term with the query are not returned.
+    pub fn search(&self, query: &str, k: usize) -> Vec<(&Passage, f64)> {
+        let mut query_terms = terms(query);
+        query_terms.sort();
+        query_terms.dedup();
+        let n = self.passages.len() as f64;
+        let mut scores = self
+            .term_freqs
+            .iter()
+            .enumerate()
+            .map(|(i, freqs)| {
+                let mut score = 0.0;
+                for term in query_terms.iter() {
+                    let tf = match freqs.get(term) {
+                        Some(tf) => *tf as f64,
+                        None => continue,
+                    };
+                    let df = self.doc_freqs.get(term).cloned().unwrap_or(0) as f64;
+                    let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+                    let norm = 1.0 - B + B * self.lengths[i] as f64 / self.average_length;
+                    score += idf * tf * (K1 + 1.0) / (tf + K1 * norm);
+                }
+                (i, score)
+            })
+            .filter(|(_, score)| *score > 0.0)
+            .collect::<Vec<(usize, f64)>>();
+        scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        return scores
+            .into_iter()
+            .take(k)
+            .map(|(i, score)| (&self.passages[i], score))
+            .collect();
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Answer {
+    pub text: String,
+    /// Passages the answer is grounded in
+    pub passages: Vec<Passage>,
+}
+
+/// A conversation about a paper. The previous questions and answers are kept
+/// as long as they fit in the context window of the model.
+#[derive(Clone, Debug)]
+pub struct PaperChat<'a> {
+    ai: &'a AI,
+    index: Bm25Index,
+    system: Message,
+    history: Vec<Message>,
+    top_k: usize,
+}
+
+impl<'a> PaperChat<'a> {
+    pub fn new(ai: &'a AI, paper: &Paper) -> PaperChat<'a> {
+        PaperChat {
+            ai,
+            index: Bm25Index::new(Passage::from_document(&paper.document)),
+            system: ai.prompt_template().ask_system_message(&paper.title),
+            history: Vec::new(),
+            top_k: 5,
+        }
+    }
+
+    /// Passages retrieved for each question
+    pub fn top_k(&mut self, top_k: usize) -> &mut Self {
+        self.top_k = top_k;
+        return self;
+    }
+
+    pub fn passages_xml(passages: &[Passage]) -> String {
+        let mut xml = String::from("<passages>");
+        for passage in passages {
+            xml.push_str(
+                format!(
+                    "<passage section=\"{}\">{}</passage>",
+                    escape_xml(&passage.section),
+                    escape_xml(&passage.text)
+                )
+                .as_str(),
+            );
+        }
+        xml.push_str("</passages>");
+        return xml;
+    }
+
+    pub async fn ask(&mut self, question: &str) -> Result<Answer> {
+        let passages = self
+            .index
+            .search(question, self.top_k)
+            .into_iter()
+            .map(|(passage, _)| passage.clone())
+            .collect::<Vec<Passage>>();
+        let question = self
+            .ai
+            .prompt_template()
+            .ask_message(&PaperChat::passages_xml(&passages), question);
+
+        // drop the oldest questions and answers while the conversation is too long
+        let mut messages = self.messages(&question);
+        while self.history.len() >= 2
+            && count_message_tokens(self.ai.model_id(), &messages) > self.ai.prompt_budget()
+        {
+            self.history.drain(..2);
+            messages = self.messages(&question);
+        }
+
+        let text = self.ai.complete(messages).await?;
+        self.history.push(question);
+        self.history.push(Message::new("assistant", &text));
+        return Ok(Answer { text, passages });
+    }
+
+    fn messages(&self, question: &Message) -> Vec<Message> {
+        let mut messages = vec![self.system.clone()];
+        messages.extend(self.history.iter().cloned());
+        messages.push(question.clone());
+        return messages;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{DocumentSection, Table};
+
+    fn document() -> Document {
+        return Document {
+            sections: vec![
+                DocumentSection::new(
+                    0,
+                    "1 Introduction",
+                    vec!["We present a tiny transformer for machine translation.".to_string()],
+                ),
+                DocumentSection::new(
+                    1,
+                    "4 Training",
+                    vec![
+                        "We use the Adam optimizer with a learning rate of 3e-4 and a batch size of 256.".to_string(),
+                        "Training takes 12 hours on 8 GPUs.".to_string(),
+                    ],
+                ),
+                DocumentSection::new(
+                    2,
+                    "References",
+                    vec!["Kingma and Ba. Adam: a method for stochastic optimization learning rate.".to_string()],
+                ),
+            ],
+            tables: vec![Table {
+                label: "Table 1".to_string(),
+                caption: "BLEU scores on WMT14.".to_string(),
+                section: 1,
+                rows: vec![vec!["Tiny".to_string(), "27.3".to_string()]],
+            }],
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn test_bm25_search() {
+        let passages = Passage::from_document(&document());
+        assert_eq!(passages.len(), 4);
+        assert_eq!(passages[3].text, "BLEU scores on WMT14.\nTiny | 27.3");
+
+        let index = Bm25Index::new(passages);
+        let results = index.search("What learning rate did they use?", 2);
+        assert_eq!(results[0].0.section, "4 Training");
+        assert!(results[0].0.text.contains("3e-4"));
+        assert_eq!(index.search("BLEU on WMT14", 1)[0].0.section, "Table 1");
+        assert!(index.search("quantum chromodynamics", 3).is_empty());
+
+        let long = Document {
+            sections: vec![DocumentSection::new(
+                0,
+                "1 Introduction",
+                vec!["word ".repeat(450)],
+            )],
+            ..Default::default()
+        };
+        assert_eq!(Passage::from_document(&long).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_paper_chat() {
+        let (ai, mut requests) = crate::llm::tests::stub_ai("3e-4 [4 Training]").await;
+        let mut paper = Paper::default();
+        paper.title = "A Tiny Transformer".to_string();
+        paper.document = document();
+
+        let mut chat = PaperChat::new(&ai, &paper);
+        chat.top_k(1);
+        let answer = chat.ask("What learning rate did they use?").await.unwrap();
+        assert_eq!(answer.text, "3e-4 [4 Training]");
+        assert_eq!(answer.passages.len(), 1);
+        assert_eq!(answer.passages[0].section, "4 Training");
+
+        let raw = requests.recv().await.unwrap();
+        assert!(raw.contains("A Tiny Transformer"));
+        assert!(raw.contains(r#"<passage section=\"4 Training\">"#));
+
+        chat.ask("How long does training take?").await.unwrap();
+        let raw = requests.recv().await.unwrap();
+        assert!(raw.contains("3e-4 [4 Training]"));
+        assert_eq!(ai.run_usage().requests, 2);
+    }
+}
@@ -0,0 +1,310 @@
+//! This module checks the quality of the extracted text before it is summarized.
+//! Scanned PDFs, papers in other languages and garbled text from broken font encodings
+//! are routed to review instead of being summarized.
+use crate::document::Document;
+use crate::section::SectionRole;
+use dotenvy::dotenv;
+
+/// Frequent English words used to tell English from other languages in Latin script.
+const ENGLISH_STOPWORDS: [&str; 20] = [
+    "the", "of", "and", "to", "in", "a", "is", "that", "for", "we", "with", "on", "as", "are",
+    "by", "this", "be", "an", "our", "from",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QualityReport {
+    /// Ratio of the words that look like natural language
+    pub readable_word_ratio: f32,
+    /// Detected language: "en", "ja", "zh", "ko", "ru" or "unknown"
+    pub language: String,
+    /// Characters per page (`None` if the number of pages is unknown, e.g. LaTeX sources)
+    pub chars_per_page: Option<f32>,
+    pub missing_roles: Vec<SectionRole>,
+    /// Reasons why the text did not pass the gate (empty if it passed)
+    pub reasons: Vec<String>,
+}
+
+impl QualityReport {
+    pub fn passed(&self) -> bool {
+        return self.reasons.is_empty();
+    }
+
+    pub fn reason(&self) -> String {
+        return self.reasons.join("; ");
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct QualityGate {
+    pub min_readable_word_ratio: f32,
+    pub min_chars_per_page: f32,
+    pub min_sections: usize,
+    pub languages: Vec<String>,
+    pub required_roles: Vec<SectionRole>,
+}
+
+impl QualityGate {
+    pub fn new() -> QualityGate {
+        dotenv().ok();
+        let env = |key: &str| std::env::var(key).ok().filter(|x| !x.trim().is_empty());
+        QualityGate {
+            min_readable_word_ratio: env("QUALITY_MIN_READABLE_RATIO")
+                .and_then(|x| x.trim().parse().ok())
+                .unwrap_or(0.7),
+            min_chars_per_page: env("QUALITY_MIN_CHARS_PER_PAGE")
+                .and_then(|x| x.trim().parse().ok())
+                .unwrap_or(500.0),
+            min_sections: 4,
+            languages: env("QUALITY_LANGUAGES")
+                .map(|x| x.split(',').map(|l| l.trim().to_lowercase()).collect())
+                .unwrap_or(vec![String::from("en")]),
+            required_roles: env("QUALITY_REQUIRED_SECTIONS")
+                .map(|x| SectionRole::parse_list(&x))
+                .unwrap_or(vec![SectionRole::Introduction]),
+        }
+    }
+
+    pub fn assess(&self, document: &Document) -> QualityReport {
+        let text = document.get_text();
+        let readable_word_ratio = readable_word_ratio(&text);
+        let language = detect_language(&text);
+        let pages = document
+            .iter()
+            .map(|section| section.page_end)
+            .max()
+            .unwrap_or(0);
+        let chars_per_page = if pages > 0 {
+            Some(text.chars().count() as f32 / pages as f32)
+        } else {
+            None
+        };
+        let missing_roles = self
+            .required_roles
+            .iter()
+            .filter(|role| document.find_by_role(**role).is_empty())
+            .cloned()
+            .collect::<Vec<SectionRole>>();
+
+        let mut reasons = Vec::new();
+        if document.len() < self.min_sections {
+            reasons.push(format!("too few sections ({})", document.len()));
+        }
+        if readable_word_ratio < self.min_readable_word_ratio {
+            reasons.push(format!(
+                "low readable word ratio ({:.2} < {:.2})",
+                readable_word_ratio, self.min_readable_word_ratio
+            ));
+        }
+        if !self.languages.is_empty() && !self.languages.contains(&language) {
+            reasons.push(format!("unsupported language ({})", language));
+        }
+        if let Some(chars_per_page) = chars_per_page {
+            if chars_per_page < self.min_chars_per_page {
+                reasons.push(format!(
+                    "too little text per page ({:.0} < {:.0})",
+                    chars_per_page, self.min_chars_per_page
+                ));
+            }
+        }
+        if !missing_roles.is_empty() {
+            reasons.push(format!(
+                "missing sections ({})",
+                missing_roles
+                    .iter()
+                    .map(|role| role.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ));
+        }
+
+        return QualityReport {
+            readable_word_ratio,
+            language,
+            chars_per_page,
+            missing_roles,
+            reasons,
+        };
+    }
+}
+
+/// Ratio of the words made of letters (with a vowel for Latin script) among the words of the text.
+/// Numbers and math are ignored; broken font encodings produce symbols and consonant runs instead.
+pub fn readable_word_ratio(text: &str) -> f32 {
+    let mut total = 0;
+    let mut readable = 0;
+    for word in text.split_whitespace() {
+        if word.contains('$') || word.chars().all(|c| !c.is_alphabetic()) {
+            continue;
+        }
+        total += 1;
+        let word = word.trim_matches(|c: char| c.is_ascii_punctuation());
+        if is_readable(word) {
+            readable += 1;
+        }
+    }
+    if total == 0 {
+        return 0.0;
+    }
+    return readable as f32 / total as f32;
+}
+
+fn is_readable(word: &str) -> bool {
+    // CJK text is not separated by spaces, so only Latin words have a length limit
+    if word.is_empty() || (word.is_ascii() && word.len() > 30) {
+        return false;
+    }
+    if word
+        .chars()
+        .any(|c| c == '\u{fffd}' || c.is_control() || ('\u{e000}'..='\u{f8ff}').contains(&c))
+    {
+        return false;
+    }
+    if !word
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '\'' || c == '’')
+    {
+        return false;
+    }
+    if word.is_ascii() {
+        return word
+            .to_lowercase()
+            .chars()
+            .any(|c| matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y'));
+    }
+    return true;
+}
+
+/// Detect the language of the text from its script, and from stopwords for Latin script.
+pub fn detect_language(text: &str) -> String {
+    let (mut latin, mut kana, mut han, mut hangul, mut cyrillic) = (0, 0, 0, 0, 0);
+    for c in text.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' => latin += 1,
+            '\u{3040}'..='\u{30ff}' => kana += 1,
+            '\u{4e00}'..='\u{9fff}' => han += 1,
+            '\u{ac00}'..='\u{d7af}' => hangul += 1,
+            '\u{0400}'..='\u{04ff}' => cyrillic += 1,
+            _ => {}
+        }
+    }
+    let cjk = kana + han + hangul;
+    if cjk + cyrillic > latin {
+        if hangul > kana + han {
+            return String::from("ko");
+        } else if kana > 0 && kana * 10 >= han {
+            return String::from("ja");
+        } else if han > 0 {
+            return String::from("zh");
+        }
+        return String::from("ru");
+    }
+
+    let words = text
+        .split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphabetic())
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<String>>();
+    if words.is_empty() {
+        return String::from("unknown");
+    }
+    let stopwords = words
+        .iter()
+        .filter(|word| ENGLISH_STOPWORDS.contains(&word.as_str()))
+        .count();
+    if stopwords as f32 / words.len() as f32 >= 0.1 {
+        return String::from("en");
+    }
+    return String::from("unknown");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::DocumentSection;
+
+    const ENGLISH: &str = "We propose a new network architecture based solely on attention \
+        mechanisms, dispensing with recurrence and convolutions entirely. Experiments on two \
+        machine translation tasks show that these models are superior in quality.";
+
+    fn document(text: &str, pages: i32) -> Document {
+        let titles = [
+            "1 Introduction",
+            "2 Method",
+            "3 Experiments",
+            "4 Conclusion",
+        ];
+        return Document {
+            sections: titles
+                .iter()
+                .enumerate()
+                .map(|(index, title)| {
+                    let mut section = DocumentSection::new(index, title, vec![text.to_string()]);
+                    section.page_start = pages;
+                    section.page_end = pages;
+                    section
+                })
+                .collect(),
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn test_readable_word_ratio() {
+        assert!(readable_word_ratio(ENGLISH) > 0.95);
+        assert!(readable_word_ratio("The loss $\\mathcal{L}$ drops by 2.1 points.") > 0.95);
+        assert!(readable_word_ratio("Tkh pxr \u{fffd}\u{fffd}qs zzk !#&% bcd") < 0.3);
+        assert_eq!(readable_word_ratio(""), 0.0);
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(detect_language(ENGLISH), "en");
+        assert_eq!(
+            detect_language("本論文では，注意機構のみに基づく新しいネットワークを提案する．"),
+            "ja"
+        );
+        assert_eq!(detect_language("我们提出了一种新的网络结构"), "zh");
+        assert_eq!(
+            detect_language("우리는 새로운 네트워크 구조를 제안한다"),
+            "ko"
+        );
+        assert_eq!(
+            detect_language("Nous proposons une nouvelle architecture de réseau"),
+            "unknown"
+        );
+    }
+
+    #[test]
+    fn test_assess() {
+        let mut gate = QualityGate::new();
+        gate.min_readable_word_ratio = 0.7;
+        gate.min_chars_per_page = 500.0;
+        gate.languages = vec![String::from("en")];
+        gate.required_roles = vec![SectionRole::Introduction];
+
+        let text = [ENGLISH; 4].join(" ");
+        let report = gate.assess(&document(&text, 2));
+        assert!(report.passed(), "{}", report.reason());
+        assert_eq!(report.language, "en");
+
+        // scanned PDF: little text on many pages
+        let report = gate.assess(&document(ENGLISH, 20));
+        assert!(!report.passed());
+        assert!(report.reason().starts_with("too little text per page"));
+
+        // LaTeX source: the number of pages is unknown
+        let report = gate.assess(&document(ENGLISH, 0));
+        assert_eq!(report.chars_per_page, None);
+        assert!(report.passed());
+
+        let report = gate.assess(&Document::default());
+        assert_eq!(
+            report.reason(),
+            "too few sections (0); low readable word ratio (0.00 < 0.70); \
+             unsupported language (unknown); missing sections (introduction)"
+        );
+    }
+}
@@ -0,0 +1,384 @@
+//! This module ranks the references of a paper by their relevance to it, so that the most
+//! relevant references fit in the prompt instead of all of them.
+//! A reference scores by how often it is cited in the text, how often it is cited by other
+//! papers (Semantic Scholar) and how much its title and abstract overlap with the abstract.
+//! `REFERENCES_TOP_K` (20 by default, 0 to leave the references out) and
+//! `REFERENCES_MAX_TOKENS` (4,000 by default) limit the references in the prompt.
+use crate::common::Paper;
+use crate::section::SectionRole;
+use crate::serializer::{serialize_related, PaperFormat};
+use crate::tokens::count_tokens;
+use chrono::Datelike;
+use dotenvy::dotenv;
+use fxhash::{FxHashMap, FxHashSet};
+use regex::Regex;
+
+/// Weights of the in-text citations, the citation count and the overlap with the abstract.
+const MENTION_WEIGHT: f64 = 0.5;
+const CITATION_WEIGHT: f64 = 0.2;
+const OVERLAP_WEIGHT: f64 = 0.3;
+
+const STOP_WORDS: [&str; 24] = [
+    "the", "and", "for", "with", "that", "this", "from", "are", "our", "its", "was", "were",
+    "which", "these", "those", "their", "into", "using", "based", "via", "can", "has", "have",
+    "not",
+];
+
+#[derive(Clone, Debug)]
+pub struct RankedReference<'a> {
+    pub paper: &'a Paper,
+    /// Citations of the reference in the text of the paper
+    pub mentions: usize,
+    pub score: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReferenceRanker {
+    top_k: usize,
+    max_tokens: usize,
+}
+
+impl ReferenceRanker {
+    pub fn new() -> ReferenceRanker {
+        dotenv().ok();
+        let top_k = std::env::var("REFERENCES_TOP_K")
+            .ok()
+            .and_then(|x| x.trim().parse().ok())
+            .unwrap_or(20);
+        let max_tokens = std::env::var("REFERENCES_MAX_TOKENS")
+            .ok()
+            .and_then(|x| x.trim().parse().ok())
+            .unwrap_or(4_000);
+        ReferenceRanker { top_k, max_tokens }
+    }
+
+    pub fn top_k(&mut self, top_k: usize) -> &mut Self {
+        self.top_k = top_k;
+        return self;
+    }
+
+    pub fn max_tokens(&mut self, max_tokens: usize) -> &mut Self {
+        self.max_tokens = max_tokens;
+        return self;
+    }
+
+    /// The references of `paper`, the most relevant first.
+    pub fn rank<'a>(&self, paper: &'a Paper) -> Vec<RankedReference<'a>> {
+        let body = paper
+            .document
+            .iter()
+            .filter(|section| section.role != SectionRole::References)
+            .map(|section| section.get_text())
+            .collect::<Vec<String>>()
+            .join("\n");
+        let entries = paper
+            .document
+            .find_by_role(SectionRole::References)
+            .iter()
+            .flat_map(|section| section.contents.iter().flat_map(|x| x.lines()))
+            .map(|entry| entry.trim().to_string())
+            .filter(|entry| !entry.is_empty())
+            .collect::<Vec<String>>();
+        let numbers = numeric_citations(&body);
+        let abstract_words = words(&paper.abstract_text);
+
+        let mentions = paper
+            .references
+            .iter()
+            .map(|reference| count_mentions(reference, &body, &entries, &numbers))
+            .collect::<Vec<usize>>();
+        let overlaps = paper
+            .references
+            .iter()
+            .map(|reference| {
+                let text = format!("{} {}", reference.title, reference.abstract_text);
+                overlap(&abstract_words, &words(&text))
+            })
+            .collect::<Vec<f64>>();
+        let max_mentions = mentions.iter().max().cloned().unwrap_or(0).max(1) as f64;
+        let max_citations = paper
+            .references
+            .iter()
+            .map(|reference| reference.citation_count)
+            .max()
+            .unwrap_or(0);
+        let max_overlap = overlaps.iter().cloned().fold(0.0, f64::max);
+
+        let mut ranked = paper
+            .references
+            .iter()
+            .enumerate()
+            .map(|(i, reference)| {
+                let mut score = MENTION_WEIGHT * mentions[i] as f64 / max_mentions;
+                if max_citations > 0 {
+                    score += CITATION_WEIGHT * (1.0 + reference.citation_count as f64).ln()
+                        / (1.0 + max_citations as f64).ln();
+                }
+                if max_overlap > 0.0 {
+                    score += OVERLAP_WEIGHT * overlaps[i] / max_overlap;
+                }
+                RankedReference {
+                    paper: reference,
+                    mentions: mentions[i],
+                    score,
+                }
+            })
+            .collect::<Vec<RankedReference>>();
+        ranked.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        return ranked;
+    }
+
+    /// The top-K references of `paper` whose serialization fits in `max_tokens`,
+    /// the most relevant first. A reference too large for the rest of the budget is skipped.
+    pub fn select(&self, paper: &Paper, model_id: &str, format: PaperFormat) -> Vec<Paper> {
+        let mut selected = Vec::new();
+        let mut tokens = 0;
+        for reference in self.rank(paper) {
+            if selected.len() >= self.top_k {
+                break;
+            }
+            let reference = reference.paper.clone();
+            let reference_tokens = count_tokens(
+                model_id,
+                &serialize_related(
+                    std::slice::from_ref(&reference),
+                    "references",
+                    "reference",
+                    format,
+                ),
+            );
+            if tokens + reference_tokens > self.max_tokens {
+                continue;
+            }
+            tokens += reference_tokens;
+            selected.push(reference);
+        }
+        return selected;
+    }
+}
+
+/// Count the citations of each number in the brackets of `text`: "[3]", "[1, 4]", "[2-5]".
+fn numeric_citations(text: &str) -> FxHashMap<u32, usize> {
+    let brackets = Regex::new(r"\[(\d+(?:\s*[,\-–]\s*\d+)*)\]").unwrap();
+    let range = Regex::new(r"^(\d+)\s*[\-–]\s*(\d+)$").unwrap();
+    let mut counts = FxHashMap::default();
+    for captures in brackets.captures_iter(text) {
+        for item in captures[1].split(',') {
+            let item = item.trim();
+            let numbers = match range.captures(item) {
+                Some(bounds) => {
+                    let start = bounds[1].parse::<u32>().unwrap_or(0);
+                    let end = bounds[2].parse::<u32>().unwrap_or(0);
+                    // a long range is more likely a page range than a citation
+                    if end < start || end - start > 20 {
+                        continue;
+                    }
+                    (start..=end).collect::<Vec<u32>>()
+                }
+                None => item.parse::<u32>().into_iter().collect(),
+            };
+            for number in numbers {
+                *counts.entry(number).or_insert(0) += 1;
+            }
+        }
+    }
+    return counts;
+}
+
+/// Citations of `reference` in `body`.
+/// The entry of the bibliography with the title of the reference gives its number ("[12]").
+/// Otherwise author-year citations are counted by the surname of the first author and the
+/// year ("Vaswani et al., 2017", "vaswani2017attention").
+fn count_mentions(
+    reference: &Paper,
+    body: &str,
+    entries: &[String],
+    numbers: &FxHashMap<u32, usize>,
+) -> usize {
+    let title = normalize(&reference.title);
+    let entry = if title.is_empty() {
+        None
+    } else {
+        entries
+            .iter()
+            .find(|entry| normalize(entry).contains(&title))
+    };
+
+    let mut surname = reference
+        .authors
+        .first()
+        .and_then(|author| author.name.split_whitespace().last())
+        .map(|name| name.to_string());
+    if let Some(entry) = entry {
+        let number = Regex::new(r"^\[?(\d+)[\].]").unwrap();
+        if let Some(captures) = number.captures(entry) {
+            let number = captures[1].parse::<u32>().unwrap_or(0);
+            return numbers.get(&number).cloned().unwrap_or(0);
+        }
+        if surname.is_none() {
+            surname = entry
+                .split(|c: char| !c.is_alphabetic() && c != '-')
+                .find(|word| word.chars().count() > 1)
+                .map(|word| word.to_string());
+        }
+    }
+
+    let surname = match surname {
+        Some(surname) => surname,
+        None => return 0,
+    };
+    let pattern = format!(
+        r"(?i)\b{}[^\[\]\n]{{0,40}}?{}",
+        regex::escape(&surname),
+        reference.publication_date.year()
+    );
+    return match Regex::new(&pattern) {
+        Ok(pattern) => pattern.find_iter(body).count(),
+        Err(_) => 0,
+    };
+}
+
+/// Lowercase words and digits separated by single spaces.
+fn normalize(text: &str) -> String {
+    return text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ");
+}
+
+fn words(text: &str) -> FxHashSet<String> {
+    return normalize(text)
+        .split(' ')
+        .filter(|word| word.chars().count() > 2 && !STOP_WORDS.contains(word))
+        .map(|word| word.to_string())
+        .collect();
+}
+
+/// Cosine similarity of two sets of words.
+fn overlap(a: &FxHashSet<String>, b: &FxHashSet<String>) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let common = a.intersection(b).count() as f64;
+    return common / ((a.len() * b.len()) as f64).sqrt();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Author;
+    use crate::document::{Document, DocumentSection};
+    use crate::utils::datetime_from_str;
+
+    fn reference(title: &str, author: &str, year: &str, citation_count: u32) -> Paper {
+        let mut author_ = Author::default();
+        author_.name = author.to_string();
+        let mut paper = Paper::reference(
+            "",
+            title,
+            "",
+            vec![author_],
+            datetime_from_str(&format!("{}-01-01", year)),
+        );
+        paper.citation_count = citation_count;
+        return paper;
+    }
+
+    #[test]
+    fn test_numeric_citations() {
+        let counts = numeric_citations("as in [1, 3] and [2-4], see [3]; pages [10-200]");
+        assert_eq!(counts.get(&3), Some(&3));
+        assert_eq!(counts.get(&1), Some(&1));
+        assert_eq!(counts.get(&4), Some(&1));
+        assert_eq!(counts.get(&10), None);
+    }
+
+    #[test]
+    fn test_rank_references() {
+        let mut paper = Paper::default();
+        paper.abstract_text =
+            "We apply knowledge distillation to compress a large transformer for machine translation."
+                .to_string();
+        paper.references = vec![
+            reference(
+                "Deep Residual Learning for Image Recognition",
+                "Kaiming He",
+                "2016",
+                200_000,
+            ),
+            reference(
+                "Attention Is All You Need",
+                "Ashish Vaswani",
+                "2017",
+                100_000,
+            ),
+            reference(
+                "Distilling the Knowledge in a Neural Network",
+                "Geoffrey Hinton",
+                "2015",
+                20_000,
+            ),
+            reference(
+                "Sequence-Level Knowledge Distillation",
+                "Yoon Kim",
+                "2016",
+                1_000,
+            ),
+        ];
+        paper.document = Document {
+            sections: vec![
+                DocumentSection::new(
+                    0,
+                    "1 Introduction",
+                    vec![
+                        "The transformer (Vaswani et al., 2017) is large [2]. Knowledge distillation [2, 3] compresses it.".to_string(),
+                        "We follow Vaswani et al. (2017) and distill sequences [3] as in [3].".to_string(),
+                    ],
+                ),
+                DocumentSection::new(
+                    1,
+                    "References",
+                    vec![
+                        "[1] K. He et al. Deep residual learning for image recognition. CVPR, 2016.".to_string(),
+                        "[2] G. Hinton et al. Distilling the knowledge in a neural network. 2015.".to_string(),
+                        "[3] Y. Kim and A. Rush. Sequence-level knowledge distillation. EMNLP, 2016.".to_string(),
+                    ],
+                ),
+            ],
+            ..Default::default()
+        };
+
+        let ranked = ReferenceRanker::new().rank(&paper);
+        let titles = ranked
+            .iter()
+            .map(|reference| reference.paper.title.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            titles,
+            vec![
+                "Sequence-Level Knowledge Distillation",
+                "Distilling the Knowledge in a Neural Network",
+                "Attention Is All You Need",
+                "Deep Residual Learning for Image Recognition",
+            ]
+        );
+        let mentions = ranked
+            .iter()
+            .map(|reference| reference.mentions)
+            .collect::<Vec<usize>>();
+        assert_eq!(mentions, vec![3, 2, 2, 0]);
+
+        let mut ranker = ReferenceRanker::new();
+        ranker.top_k(2);
+        let selected = ranker.select(&paper, "gpt-4o-mini", PaperFormat::Xml);
+        assert_eq!(selected.len(), 2);
+        assert_eq!(selected[0].title, titles[0]);
+
+        ranker.top_k(10).max_tokens(0);
+        assert!(ranker
+            .select(&paper, "gpt-4o-mini", PaperFormat::Xml)
+            .is_empty());
+    }
+}
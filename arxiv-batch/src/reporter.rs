@@ -1,5 +1,7 @@
 use crate::cache::{AuthorCache, Cache, PaperCache};
-use crate::common::{Author, Paper, StatusCode};
+use crate::common::{Author, Paper, StatusCode, Summary};
+use crate::digest::Digest;
+use crate::schema::{SummaryField, SummarySchema};
 use crate::utils::s;
 use anyhow::Result;
 use chrono::Datelike;
@@ -8,15 +10,82 @@ use indicatif::ProgressBar;
 use notion_tools::structs::block::*;
 use notion_tools::structs::common::*;
 use notion_tools::structs::page::{Page, PageProperty};
-use notion_tools::structs::query_filter::{FilterItem, QueryFilter, RichTextFilterItem};
+use notion_tools::structs::query_filter::{
+    FilterItem, QueryFilter, RichTextFilterItem, StatusFilterItem,
+};
 use notion_tools::Notion;
 use tokio::time::sleep;
 
-pub struct Reporter {}
+pub struct Reporter {
+    schema: SummarySchema,
+    /// Property listing the claims of a summary not found in the paper
+    unsupported_claims_property: Option<String>,
+}
 
 impl Reporter {
-    pub fn new() -> Reporter {
-        Reporter {}
+    /// Fails when the summary schema is not valid.
+    pub fn new() -> Result<Reporter> {
+        let schema = SummarySchema::from_env()
+            .map_err(|e| anyhow::anyhow!("Failed to load the summary schema: {}", e))?;
+        let unsupported_claims_property = std::env::var("UNSUPPORTED_CLAIMS_PROPERTY")
+            .ok()
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or(s("Unsupported Claims"));
+        return Ok(Reporter {
+            schema,
+            unsupported_claims_property: Some(unsupported_claims_property),
+        });
+    }
+
+    pub fn schema(&mut self, schema: SummarySchema) -> &mut Self {
+        self.schema = schema;
+        return self;
+    }
+
+    /// Stop filling the properties the paper database does not have,
+    /// so that the pages are not rejected by an existing database.
+    pub async fn check_properties(&mut self) {
+        let mut notion = Notion::new();
+        notion.database(std::env::var("NOTION_PAPER_DATABASE_ID").unwrap());
+        match notion.retrieve_a_database().await {
+            Ok(database) if database.status == 200 => {
+                let properties = database.properties.keys().cloned().collect::<Vec<String>>();
+                self.skip_missing_properties(&properties);
+            }
+            Ok(database) => {
+                eprintln!(
+                    "WARNING: Failed to retrieve the paper database: {}",
+                    database.message
+                );
+            }
+            Err(e) => {
+                eprintln!("WARNING: Failed to retrieve the paper database: {}", e);
+            }
+        }
+    }
+
+    fn skip_missing_properties(&mut self, properties: &[String]) {
+        for field in self.schema.fields.iter_mut() {
+            if let Some(name) = field.property.as_ref() {
+                if !properties.contains(name) {
+                    eprintln!(
+                        "WARNING: The paper database has no property \"{}\"; summary field {} is not posted to it",
+                        name, field.name
+                    );
+                    field.property = None;
+                }
+            }
+        }
+        if let Some(name) = self.unsupported_claims_property.as_ref() {
+            if !properties.contains(name) {
+                eprintln!(
+                    "WARNING: The paper database has no property \"{}\"; unsupported claims are not posted to it",
+                    name
+                );
+                self.unsupported_claims_property = None;
+            }
+        }
     }
 
     fn get_pbar(&self, total: u64) -> ProgressBar {
@@ -56,6 +125,41 @@ impl Reporter {
         }
     }
 
+    /// The papers posted to the paper database, with the properties used to embed them.
+    pub async fn get_posted_papers(&self) -> Result<Vec<Paper>> {
+        let mut notion = Notion::new();
+        notion.database(std::env::var("NOTION_PAPER_DATABASE_ID").unwrap());
+        let mut filter = QueryFilter::new();
+        filter.args(FilterItem::status(
+            String::from("Status"),
+            StatusFilterItem::is_not_empty(),
+        ));
+
+        let mut papers = Vec::new();
+        let mut has_more = true;
+        while has_more {
+            let response = notion.query_database(filter.clone()).await?;
+            has_more = response.has_more.unwrap_or(false);
+            filter.start_cursor = response.next_cursor.unwrap_or(String::new());
+            for page in response.results.iter() {
+                let value = |name: &str| {
+                    page.properties
+                        .get(name)
+                        .map(|property| property.get_value())
+                        .unwrap_or_default()
+                };
+                let mut paper = Paper::default();
+                paper.page_id = page.id.clone();
+                paper.title = value("Title");
+                paper.arxiv_id = value("arXiv ID");
+                paper.ss_id = value("SS ID");
+                paper.abstract_text = value("Abstract");
+                papers.push(paper);
+            }
+        }
+        return Ok(papers);
+    }
+
     pub async fn add_authors(
         &self,
         authors: &mut Vec<Author>,
@@ -156,121 +260,114 @@ impl Reporter {
             vec![String::from("Summary")],
         ));
 
-        blocks.push(Block::heading_2(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from("1. Overview")],
-        ));
-        blocks.push(Block::paragraph(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from(paper.summary.overview.clone())],
-        ));
-
-        blocks.push(Block::heading_2(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from("2. Research Question")],
-        ));
-        blocks.push(Block::paragraph(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from(paper.summary.research_question.clone())],
-        ));
+        // tables (positions in `blocks`) are added to the blocks as JSON when they are sent
+        let mut tables: Vec<(usize, serde_json::Value)> = Vec::new();
 
-        blocks.push(Block::heading_2(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from("3. Task")],
-        ));
-        blocks.push(Block::paragraph(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from(paper.summary.task_category.clone())],
-        ));
-
-        blocks.push(Block::heading_2(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from("4. Comparison with Related Works")],
-        ));
-        blocks.push(Block::paragraph(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from(
-                paper.summary.comparison_with_related_works.clone(),
-            )],
-        ));
-
-        blocks.push(Block::heading_2(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from("5. Methodology")],
-        ));
-        blocks.push(Block::paragraph(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from(paper.summary.proposed_method.clone())],
-        ));
-
-        blocks.push(Block::heading_2(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from("6. Datasets")],
-        ));
-        blocks.push(Block::paragraph(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from(paper.summary.datasets.clone())],
-        ));
+        // in bilingual mode each section has a toggle for each language
+        for (heading, field) in self.schema.sections() {
+            blocks.push(Block::heading_2(
+                ParentType::Page,
+                page_id.clone(),
+                vec![String::from(heading)],
+            ));
+            // an array is shown as a table in both languages
+            if field.type_name == "array" {
+                let columns = field.item_names();
+                let rows = paper.summary.rows(&field.name, &columns);
+                if !rows.is_empty() {
+                    tables.push((blocks.len(), table_block(&columns, &rows)));
+                    continue;
+                }
+            }
+            let text = paper.summary.text(&field.name);
+            match paper.summary.english.as_ref() {
+                Some(english) => {
+                    let texts = [("日本語", text), ("English", english.text(&field.name))];
+                    for (language, text) in texts {
+                        let mut toggle = Block::toggle_blocks(
+                            ParentType::Page,
+                            page_id.clone(),
+                            vec![String::from(language)],
+                        );
+                        if let Some(toggle) = toggle.toggle.as_mut() {
+                            toggle.children.push(Block::paragraph(
+                                ParentType::Block,
+                                String::new(),
+                                vec![text],
+                            ));
+                        }
+                        blocks.push(toggle);
+                    }
+                }
+                None => {
+                    blocks.push(Block::paragraph(
+                        ParentType::Page,
+                        page_id.clone(),
+                        vec![text],
+                    ));
+                }
+            }
+        }
 
-        blocks.push(Block::heading_2(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from("7. Experiments")],
-        ));
-        blocks.push(Block::paragraph(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from(paper.summary.experiments.clone())],
-        ));
+        if !paper.summary.missing_fields.is_empty() {
+            blocks.push(Block::paragraph(
+                ParentType::Page,
+                page_id.clone(),
+                vec![format!(
+                    "Missing fields: {}",
+                    paper.summary.missing_fields.join(", ")
+                )],
+            ));
+        }
 
-        blocks.push(Block::heading_2(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from("8. Analysis")],
-        ));
-        blocks.push(Block::paragraph(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from(paper.summary.analysis.clone())],
-        ));
+        // tokens and cost of the summary
+        if paper.usage.requests > 0 {
+            blocks.push(Block::paragraph(
+                ParentType::Page,
+                page_id.clone(),
+                vec![format!("Usage: {}", paper.usage)],
+            ));
+        }
 
-        blocks.push(Block::heading_2(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from("9. Contributions")],
-        ));
-        blocks.push(Block::paragraph(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from(paper.summary.contributions.clone())],
-        ));
+        // the prompt that produced the summary
+        if !paper.summary.template_version.is_empty() {
+            blocks.push(Block::paragraph(
+                ParentType::Page,
+                page_id.clone(),
+                vec![format!(
+                    "Prompt template: {}",
+                    paper.summary.template_version
+                )],
+            ));
+        }
 
-        blocks.push(Block::heading_2(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from("10. Future Works")],
-        ));
-        blocks.push(Block::paragraph(
-            ParentType::Page,
-            page_id.clone(),
-            vec![String::from(paper.summary.future_works.clone())],
-        ));
+        // the model that produced the summary
+        if !paper.summary.model_id.is_empty() {
+            blocks.push(Block::paragraph(
+                ParentType::Page,
+                page_id.clone(),
+                vec![format!("Model: {}", paper.summary.model_id)],
+            ));
+        }
 
         let mut notion = Notion::new();
         notion.database(std::env::var("NOTION_PAPER_DATABASE_ID").unwrap());
-        match notion.append_block_children(page_id.clone(), blocks).await {
+        let result = if tables.is_empty() {
+            notion
+                .append_block_children(page_id.clone(), blocks)
+                .await
+                .map(|_| ())
+        } else {
+            let mut values = Vec::new();
+            for block in blocks {
+                values.push(serde_json::to_value(block).unwrap_or_default());
+            }
+            for (position, table) in tables.into_iter().rev() {
+                values.insert(position, table);
+            }
+            append_block_values(&notion, &page_id, values).await
+        };
+        match result {
             Ok(_) => {
                 return StatusCode::Success;
             }
@@ -365,32 +462,21 @@ impl Reporter {
                 ),
             );
         }
-        properties.insert(
-            s("Domain"),
-            PageProperty::multi_select(paper.summary.domain_as_vec()),
-        );
-        if paper.summary.task_as_vec().len() > 0 {
-            properties.insert(
-                s("Task"),
-                PageProperty::multi_select(paper.summary.task_as_vec()),
-            );
+        for field in self.schema.fields.iter() {
+            if let Some((name, property)) = summary_property(field, &paper.summary) {
+                properties.insert(name, property);
+            }
+        }
+        if let Some(name) = self.unsupported_claims_property.as_ref() {
+            if !paper.summary.unsupported_claims.is_empty() {
+                properties.insert(
+                    name.clone(),
+                    PageProperty::rich_text(vec![RichText::from_str(
+                        paper.summary.unsupported_claims.join("\n"),
+                    )]),
+                );
+            }
         }
-        properties.insert(
-            s("Research Question"),
-            PageProperty::rich_text(vec![RichText::from_str(
-                paper.summary.research_question.clone(),
-            )]),
-        );
-        properties.insert(
-            s("Methodology"),
-            PageProperty::rich_text(vec![RichText::from_str(
-                paper.summary.proposed_method.clone(),
-            )]),
-        );
-        properties.insert(
-            s("Results"),
-            PageProperty::rich_text(vec![RichText::from_str(paper.summary.experiments.clone())]),
-        );
         properties.insert(s("Status"), PageProperty::status(s("Ready")));
 
         let mut author_ids = paper
@@ -455,4 +541,235 @@ impl Reporter {
             }
         }
     }
+
+    /// Publish the digest as a page under `NOTION_DIGEST_PAGE_ID` and return the ID of the page.
+    pub async fn add_digest(&self, digest: &Digest) -> Result<String> {
+        let parent_id = std::env::var("NOTION_DIGEST_PAGE_ID")
+            .ok()
+            .filter(|x| !x.trim().is_empty())
+            .ok_or(anyhow::anyhow!("NOTION_DIGEST_PAGE_ID is not set."))?;
+        let mut properties: FxHashMap<String, PageProperty> = FxHashMap::default();
+        properties.insert(
+            s("title"),
+            PageProperty::title(RichText::from_str(digest.title())),
+        );
+        let mut page = Page::from_properties(properties);
+        page.parent.type_name = ParentType::Page;
+        page.parent.page_id = Some(parent_id.trim().to_string());
+        let notion = Notion::new();
+        let page = notion.create_a_page(&page).await?;
+
+        let mut blocks = Vec::new();
+        if !digest.narrative.is_empty() {
+            blocks.push(text_block("heading_2", &[("Trends", None)]));
+            for paragraph in digest.narrative.split("\n\n") {
+                blocks.push(text_block(
+                    "paragraph",
+                    &digest.link_citations(paragraph.trim()),
+                ));
+            }
+        }
+        for kind in ["Task", "Domain", "Keyword"] {
+            let groups = digest
+                .groups
+                .iter()
+                .filter(|group| group.kind == kind)
+                .collect::<Vec<_>>();
+            if groups.is_empty() {
+                continue;
+            }
+            let heading = format!("{}s", kind);
+            blocks.push(text_block("heading_2", &[(heading.as_str(), None)]));
+            for group in groups {
+                let text = format!("{}: {}", group.name, digest.citations(&group.papers));
+                blocks.push(text_block(
+                    "bulleted_list_item",
+                    &digest.link_citations(&text),
+                ));
+            }
+        }
+        blocks.push(text_block("heading_2", &[("Papers", None)]));
+        for paper in digest.papers.iter() {
+            let url = Some(paper.url.as_str()).filter(|url| !url.is_empty());
+            blocks.push(text_block(
+                "numbered_list_item",
+                &[(paper.title.as_str(), url)],
+            ));
+        }
+        append_block_values(&notion, &page.id, blocks).await?;
+        return Ok(page.id);
+    }
+}
+
+/// A block of `type_name` ("paragraph", "heading_2", ...) made of the pieces of text and their links.
+/// notion-tools serializes the links of rich texts in a form the API rejects.
+fn text_block(type_name: &str, pieces: &[(&str, Option<&str>)]) -> serde_json::Value {
+    let rich_text = pieces
+        .iter()
+        .map(|(text, link)| {
+            let link = link.map(|url| serde_json::json!({ "url": url }));
+            serde_json::json!({
+                "type": "text",
+                "text": {"content": text, "link": link}
+            })
+        })
+        .collect::<Vec<serde_json::Value>>();
+    return serde_json::json!({
+        "object": "block",
+        "type": type_name,
+        type_name: {"rich_text": rich_text}
+    });
+}
+
+/// A table with a header row of `columns` and `rows`.
+fn table_block(columns: &[&str], rows: &[Vec<String>]) -> serde_json::Value {
+    let mut children = vec![table_row(
+        &columns
+            .iter()
+            .map(|column| s(column))
+            .collect::<Vec<String>>(),
+    )];
+    for row in rows {
+        children.push(table_row(row));
+    }
+    return serde_json::json!({
+        "object": "block",
+        "type": "table",
+        "table": {
+            "table_width": columns.len(),
+            "has_column_header": true,
+            "has_row_header": false,
+            "children": children,
+        }
+    });
+}
+
+fn table_row(cells: &[String]) -> serde_json::Value {
+    let cells = cells
+        .iter()
+        .map(|cell| serde_json::json!([RichText::from_str(cell.clone())]))
+        .collect::<Vec<serde_json::Value>>();
+    return serde_json::json!({
+        "object": "block",
+        "type": "table_row",
+        "table_row": {"cells": cells}
+    });
+}
+
+/// Append blocks given as JSON to a page, 100 blocks per request.
+/// notion-tools cannot create a table with its rows, so pages with tables are sent this way.
+async fn append_block_values(
+    notion: &Notion,
+    page_id: &str,
+    blocks: Vec<serde_json::Value>,
+) -> Result<()> {
+    let url = format!("https://api.notion.com/v1/blocks/{}/children", page_id);
+    let client = reqwest::Client::new();
+    for chunk in blocks.chunks(100) {
+        let body = serde_json::json!({ "children": chunk });
+        let response = client
+            .patch(&url)
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", notion.api_key))
+            .header("Notion-Version", "2022-06-28")
+            .body(body.to_string())
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!(
+                "Failed to append block children: {}: {}",
+                status,
+                text
+            ));
+        }
+    }
+    return Ok(());
+}
+
+/// The Notion property of a summary field, if the field is mapped to one.
+/// Empty values are left out.
+fn summary_property(field: &SummaryField, summary: &Summary) -> Option<(String, PageProperty)> {
+    let name = field.property.clone()?;
+    let text = summary.text(&field.name);
+    let property = match field.property_type.as_str() {
+        "multi_select" => {
+            let words = summary
+                .words(&field.name)
+                .into_iter()
+                .filter(|word| !word.trim().is_empty())
+                .collect::<Vec<String>>();
+            if words.is_empty() {
+                return None;
+            }
+            PageProperty::multi_select(words)
+        }
+        "select" if !text.is_empty() => PageProperty::select(text),
+        "number" => PageProperty::number(text.trim().parse::<f64>().ok()?),
+        "checkbox" => PageProperty::checkbox(text == "true"),
+        "rich_text" => PageProperty::rich_text(vec![RichText::from_str(text)]),
+        _ => return None,
+    };
+    return Some((name, property));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_missing_properties() {
+        let mut schema = SummarySchema::from_env().unwrap();
+        for field in schema.fields.iter_mut() {
+            if field.name == "metrics" {
+                field.property = Some(s("Metrics"));
+            }
+        }
+        let mut reporter = Reporter::new().unwrap();
+        reporter.schema(schema);
+        reporter.skip_missing_properties(&[s("Title"), s("Task"), s("Domain")]);
+        let mapped = reporter
+            .schema
+            .fields
+            .iter()
+            .filter_map(|field| field.property.clone())
+            .collect::<Vec<String>>();
+        assert!(mapped.contains(&s("Task")));
+        assert!(!mapped.contains(&s("Metrics")));
+        assert!(reporter.unsupported_claims_property.is_none());
+    }
+
+    #[test]
+    fn test_text_block() {
+        let block = text_block(
+            "paragraph",
+            &[("See ", None), ("[1]", Some("https://www.notion.so/aaaa"))],
+        );
+        assert_eq!(block["type"], "paragraph");
+        let rich_text = block["paragraph"]["rich_text"].as_array().unwrap();
+        assert_eq!(rich_text[0]["text"]["content"], "See ");
+        assert!(rich_text[0]["text"]["link"].is_null());
+        assert_eq!(
+            rich_text[1]["text"]["link"]["url"],
+            "https://www.notion.so/aaaa"
+        );
+    }
+
+    #[test]
+    fn test_table_block() {
+        let rows = vec![vec![s("WMT14 En-De"), s("BLEU"), s("27.3"), s("26.4")]];
+        let table = table_block(&["dataset", "metric", "value", "baseline"], &rows);
+        assert_eq!(table["table"]["table_width"], 4);
+        let children = table["table"]["children"].as_array().unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(
+            children[0]["table_row"]["cells"][0][0]["text"]["content"],
+            "dataset"
+        );
+        assert_eq!(
+            children[1]["table_row"]["cells"][2][0]["text"]["content"],
+            "27.3"
+        );
+    }
 }
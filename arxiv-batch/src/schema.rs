@@ -0,0 +1,381 @@
+//! This module defines the fields of the summary.
+//! The definition is a TOML file (`SUMMARY_SCHEMA`, the built-in "prompts/schema.toml" by default)
+//! that drives both the JSON schema passed to the LLM and the layout of the Notion page.
+use crate::prompt::OutputLanguage;
+use crate::utils::s;
+use anyhow::Result;
+use dotenvy::dotenv;
+use openai_tools::json_schema::JsonSchema;
+use serde::Deserialize;
+use std::path::Path;
+
+const DEFAULT_SCHEMA: &str = include_str!("prompts/schema.toml");
+
+const FIELD_TYPES: [&str; 5] = ["string", "boolean", "number", "integer", "array"];
+const PROPERTY_TYPES: [&str; 5] = ["rich_text", "multi_select", "select", "number", "checkbox"];
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SummaryField {
+    pub name: String,
+    #[serde(rename = "type", default = "default_type")]
+    pub type_name: String,
+    /// Description in Japanese
+    #[serde(default)]
+    pub description: String,
+    /// Description in English (defaults to `description`)
+    #[serde(default)]
+    pub description_en: String,
+    /// Heading of the section on the Notion page
+    #[serde(default)]
+    pub heading: Option<String>,
+    /// Notion property filled with the field
+    #[serde(default)]
+    pub property: Option<String>,
+    #[serde(default = "default_property_type")]
+    pub property_type: String,
+    /// Columns of each element of an "array" field (all of them are strings)
+    #[serde(default)]
+    pub items: Vec<ItemField>,
+}
+
+/// A column of the elements of an "array" field, e.g. the metric of a result.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ItemField {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub description_en: String,
+}
+
+impl ItemField {
+    pub fn description(&self, language: OutputLanguage) -> &str {
+        if language == OutputLanguage::English && !self.description_en.is_empty() {
+            return &self.description_en;
+        }
+        return &self.description;
+    }
+}
+
+fn default_type() -> String {
+    return s("string");
+}
+
+fn default_property_type() -> String {
+    return s("rich_text");
+}
+
+impl SummaryField {
+    fn accepts(&self, value: &serde_json::Value) -> bool {
+        return match self.type_name.as_str() {
+            "string" => value.is_string(),
+            "boolean" => value.is_boolean(),
+            "number" => value.is_number(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "array" => value.as_array().is_some_and(|elements| {
+                elements.iter().all(|element| {
+                    self.items
+                        .iter()
+                        .all(|item| element.get(&item.name).is_some_and(|x| x.is_string()))
+                })
+            }),
+            _ => false,
+        };
+    }
+
+    pub fn description(&self, language: OutputLanguage) -> &str {
+        if language == OutputLanguage::English && !self.description_en.is_empty() {
+            return &self.description_en;
+        }
+        return &self.description;
+    }
+
+    /// Names of the columns of an "array" field
+    pub fn item_names(&self) -> Vec<&str> {
+        return self.items.iter().map(|item| item.name.as_str()).collect();
+    }
+
+    fn expected(&self) -> String {
+        if self.type_name == "array" {
+            return format!("array of {{{}}}", self.item_names().join(", "));
+        }
+        return self.type_name.clone();
+    }
+}
+
+/// A field of a response that does not match the schema.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldError {
+    pub name: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.name.is_empty() {
+            return write!(f, "{}", self.reason);
+        }
+        write!(f, "{}: {}", self.name, self.reason)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SummarySchema {
+    pub fields: Vec<SummaryField>,
+}
+
+impl SummarySchema {
+    pub fn parse(text: &str) -> Result<SummarySchema> {
+        let schema: SummarySchema = toml::from_str(text)
+            .map_err(|e| anyhow::anyhow!("Failed to parse summary schema: {}", e))?;
+        for field in schema.fields.iter() {
+            if field.name.trim().is_empty() {
+                return Err(anyhow::anyhow!("Summary field without a name"));
+            }
+            if !FIELD_TYPES.contains(&field.type_name.as_str()) {
+                return Err(anyhow::anyhow!(
+                    "Unknown type of summary field {}: {}",
+                    field.name,
+                    field.type_name
+                ));
+            }
+            if field.type_name == "array" && field.items.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Summary field {} of type array without items",
+                    field.name
+                ));
+            }
+            if !PROPERTY_TYPES.contains(&field.property_type.as_str()) {
+                return Err(anyhow::anyhow!(
+                    "Unknown property type of summary field {}: {}",
+                    field.name,
+                    field.property_type
+                ));
+            }
+        }
+        return Ok(schema);
+    }
+
+    pub fn load(path: &Path) -> Result<SummarySchema> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+        return SummarySchema::parse(&text);
+    }
+
+    /// Load the schema from `SUMMARY_SCHEMA`, or the built-in schema if it is not set.
+    pub fn from_env() -> Result<SummarySchema> {
+        dotenv().ok();
+        return match std::env::var("SUMMARY_SCHEMA") {
+            Ok(path) if !path.trim().is_empty() => SummarySchema::load(Path::new(path.trim())),
+            _ => SummarySchema::parse(DEFAULT_SCHEMA),
+        };
+    }
+
+    /// The JSON schema of the summary with the descriptions in the output language.
+    /// In bilingual mode each text field has an English counterpart `{name}_en`.
+    pub fn json_schema(&self, language: OutputLanguage) -> JsonSchema {
+        let mut json_schema = JsonSchema::new("summary");
+        for field in self.fields.iter() {
+            if field.type_name == "array" {
+                let items = field
+                    .items
+                    .iter()
+                    .map(|item| (item.name.clone(), s(item.description(language))))
+                    .collect::<Vec<(String, String)>>();
+                json_schema.add_array(&field.name, items);
+                if let Some(property) = json_schema.schema.properties.get_mut(&field.name) {
+                    property.description = Some(s(field.description(language)));
+                }
+                continue;
+            }
+            json_schema.add_property(
+                &field.name,
+                &field.type_name,
+                Some(s(field.description(language))),
+            );
+            if language == OutputLanguage::Bilingual && field.type_name == "string" {
+                json_schema.add_property(
+                    &format!("{}_en", field.name),
+                    &field.type_name,
+                    Some(s(field.description(OutputLanguage::English))),
+                );
+            }
+        }
+        // adding an array resets the required properties
+        let mut required = json_schema
+            .schema
+            .properties
+            .keys()
+            .cloned()
+            .collect::<Vec<String>>();
+        required.sort();
+        json_schema.schema.required = Some(required);
+        return json_schema;
+    }
+
+    /// Check a response of the LLM against the schema.
+    /// Returns the fields (including the `{name}_en` fields in bilingual mode)
+    /// that are missing or have the wrong type.
+    pub fn validate(&self, value: &serde_json::Value, language: OutputLanguage) -> Vec<FieldError> {
+        let object = match value.as_object() {
+            Some(object) => object,
+            None => {
+                return vec![FieldError {
+                    name: String::new(),
+                    reason: s("the response is not a JSON object"),
+                }]
+            }
+        };
+        let mut errors = Vec::new();
+        for field in self.fields.iter() {
+            let mut names = vec![field.name.clone()];
+            if language == OutputLanguage::Bilingual && field.type_name == "string" {
+                names.push(format!("{}_en", field.name));
+            }
+            for name in names {
+                let reason = match object.get(&name) {
+                    None => s("missing"),
+                    Some(value) if !field.accepts(value) => {
+                        format!("expected {}, got {}", field.expected(), value)
+                    }
+                    Some(_) => continue,
+                };
+                errors.push(FieldError { name, reason });
+            }
+        }
+        return errors;
+    }
+
+    /// Fields shown as sections of the Notion page, with their headings.
+    pub fn sections(&self) -> Vec<(&str, &SummaryField)> {
+        return self
+            .fields
+            .iter()
+            .filter_map(|field| field.heading.as_deref().map(|heading| (heading, field)))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_schema() {
+        let schema = SummarySchema::parse(DEFAULT_SCHEMA).unwrap();
+        assert_eq!(schema.fields.len(), 16);
+        assert_eq!(schema.sections().len(), 11);
+        assert_eq!(schema.sections()[0].0, "1. Overview");
+
+        let json_schema =
+            serde_json::to_value(schema.json_schema(OutputLanguage::English)).unwrap();
+        assert_eq!(
+            json_schema["schema"]["properties"]["datasets"]["description"],
+            "A list of the datasets used in this paper."
+        );
+    }
+
+    #[test]
+    fn test_custom_schema() {
+        let schema = SummarySchema::parse(
+            r#"
+            [[fields]]
+            name = "limitations"
+            description = "この論文の限界"
+            heading = "Limitations"
+
+            [[fields]]
+            name = "gpu_hours"
+            type = "number"
+            property = "GPU Hours"
+            property_type = "number"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(schema.fields[0].type_name, "string");
+        assert_eq!(
+            schema.fields[0].description(OutputLanguage::English),
+            "この論文の限界"
+        );
+        let json_schema =
+            serde_json::to_value(schema.json_schema(OutputLanguage::Bilingual)).unwrap();
+        let properties = &json_schema["schema"]["properties"];
+        assert!(properties["limitations_en"].is_object());
+        assert!(properties["gpu_hours_en"].is_null());
+
+        assert!(SummarySchema::parse("[[fields]]\nname = \"x\"\ntype = \"date\"").is_err());
+        assert!(SummarySchema::parse("[[fields]]\nname = \"x\"\ntype = \"array\"").is_err());
+    }
+
+    #[test]
+    fn test_array_field() {
+        let schema = SummarySchema::parse(DEFAULT_SCHEMA).unwrap();
+        let json_schema =
+            serde_json::to_value(schema.json_schema(OutputLanguage::English)).unwrap();
+        let results = &json_schema["schema"]["properties"]["results"];
+        assert_eq!(results["type"], "array");
+        assert_eq!(results["items"]["properties"]["metric"]["type"], "string");
+        let required = json_schema["schema"]["required"].as_array().unwrap();
+        assert_eq!(required.len(), schema.fields.len());
+
+        let field = schema
+            .fields
+            .iter()
+            .find(|field| field.name == "results")
+            .unwrap();
+        assert_eq!(
+            field.item_names(),
+            vec!["dataset", "metric", "value", "baseline"]
+        );
+        let result = serde_json::json!([
+            {"dataset": "WMT14 En-De", "metric": "BLEU", "value": "27.3", "baseline": "26.4"}
+        ]);
+        assert!(field.accepts(&result));
+        assert!(!field.accepts(&serde_json::json!([{"dataset": "WMT14 En-De", "value": 27.3}])));
+        assert!(!field.accepts(&serde_json::json!("27.3 BLEU")));
+        assert_eq!(
+            field.expected(),
+            "array of {dataset, metric, value, baseline}"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = SummarySchema::parse(
+            r#"
+            [[fields]]
+            name = "overview"
+
+            [[fields]]
+            name = "is_survey"
+            type = "boolean"
+
+            [[fields]]
+            name = "gpu_hours"
+            type = "integer"
+            "#,
+        )
+        .unwrap();
+        let value = serde_json::json!({"overview": "A tiny transformer.", "is_survey": false, "gpu_hours": 8});
+        assert!(schema.validate(&value, OutputLanguage::Japanese).is_empty());
+
+        let value = serde_json::json!({"overview": "A tiny transformer.", "is_survey": "no"});
+        let errors = schema
+            .validate(&value, OutputLanguage::Bilingual)
+            .iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            errors,
+            vec![
+                "overview_en: missing",
+                "is_survey: expected boolean, got \"no\"",
+                "gpu_hours: missing"
+            ]
+        );
+        assert_eq!(
+            schema.validate(&serde_json::json!([]), OutputLanguage::Japanese)[0].to_string(),
+            "the response is not a JSON object"
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! This module classifies the raw section titles extracted by rsrpp into canonical roles.
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SectionRole {
+    Abstract,
+    Introduction,
+    RelatedWork,
+    Method,
+    Experiments,
+    Results,
+    Conclusion,
+    References,
+    Appendix,
+    #[default]
+    Other,
+}
+
+const ROMAN_NUMERALS: [&str; 12] = [
+    "i", "ii", "iii", "iv", "v", "vi", "vii", "viii", "ix", "x", "xi", "xii",
+];
+
+/// Keywords for each role, checked in this order.
+/// The first role whose keyword appears in the normalized title wins.
+const ROLE_KEYWORDS: [(SectionRole, &[&str]); 9] = [
+    (SectionRole::References, &["references", "bibliography"]),
+    (
+        SectionRole::Appendix,
+        &["appendix", "appendices", "supplementary", "supplemental"],
+    ),
+    (SectionRole::Abstract, &["abstract"]),
+    (SectionRole::Introduction, &["introduction", "motivation"]),
+    (
+        SectionRole::RelatedWork,
+        &[
+            "related work",
+            "related works",
+            "related research",
+            "prior work",
+            "previous work",
+            "literature",
+            "background",
+        ],
+    ),
+    (
+        SectionRole::Conclusion,
+        &[
+            "conclusion",
+            "concluding",
+            "future work",
+            "future direction",
+            "limitation",
+            "summary",
+        ],
+    ),
+    (
+        SectionRole::Experiments,
+        &[
+            "experiment",
+            "evaluation",
+            "setup",
+            "training",
+            "implementation detail",
+            "benchmark",
+        ],
+    ),
+    (
+        SectionRole::Results,
+        &[
+            "result",
+            "analysis",
+            "analyses",
+            "discussion",
+            "ablation",
+            "findings",
+        ],
+    ),
+    (
+        SectionRole::Method,
+        &[
+            "method",
+            "approach",
+            "proposed",
+            "model",
+            "framework",
+            "architecture",
+            "algorithm",
+            "preliminar",
+            "problem formulation",
+            "problem definition",
+        ],
+    ),
+];
+
+impl SectionRole {
+    pub fn all() -> Vec<SectionRole> {
+        vec![
+            SectionRole::Abstract,
+            SectionRole::Introduction,
+            SectionRole::RelatedWork,
+            SectionRole::Method,
+            SectionRole::Experiments,
+            SectionRole::Results,
+            SectionRole::Conclusion,
+            SectionRole::References,
+            SectionRole::Appendix,
+            SectionRole::Other,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SectionRole::Abstract => "abstract",
+            SectionRole::Introduction => "introduction",
+            SectionRole::RelatedWork => "related_work",
+            SectionRole::Method => "method",
+            SectionRole::Experiments => "experiments",
+            SectionRole::Results => "results",
+            SectionRole::Conclusion => "conclusion",
+            SectionRole::References => "references",
+            SectionRole::Appendix => "appendix",
+            SectionRole::Other => "other",
+        }
+    }
+
+    /// Classify a raw section title such as "1 Introduction", "INTRODUCTION" or "I. Introduction".
+    /// Titles without a keyword are `Other`; `Document::resolve_roles` fills them in from the context.
+    pub fn classify(title: &str) -> SectionRole {
+        let (_, title) = split_numbering(title);
+        let title = normalize(&title);
+        if title.is_empty() {
+            return SectionRole::Other;
+        }
+
+        for (role, keywords) in ROLE_KEYWORDS.iter() {
+            if keywords.iter().any(|keyword| title.contains(keyword)) {
+                return *role;
+            }
+        }
+        return SectionRole::Other;
+    }
+
+    /// Parse a comma-separated list of roles: "introduction,method,experiments".
+    pub fn parse_list(roles: &str) -> Vec<SectionRole> {
+        return roles
+            .split(',')
+            .filter_map(|role| role.trim().parse::<SectionRole>().ok())
+            .collect();
+    }
+}
+
+impl FromStr for SectionRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase().replace([' ', '-'], "_");
+        return SectionRole::all()
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or(anyhow::anyhow!("Unknown section role: {}", s));
+    }
+}
+
+impl std::fmt::Display for SectionRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Infer the hierarchy level of a section from its numbering: "3" -> 1, "3.1" -> 2, "B.2.1" -> 3.
+/// Titles without numbering are treated as top-level sections.
+pub fn section_level(title: &str) -> u8 {
+    match split_numbering(title) {
+        (Some(numbering), _) => numbering.split('.').filter(|x| !x.is_empty()).count() as u8,
+        (None, _) => 1,
+    }
+}
+
+/// Whether the title is numbered with a capital letter: "A. Proof of Theorem 1", "B.2 Hyperparameters".
+/// Such titles are appendices after the references, but subsections in IEEE-style papers.
+pub fn has_letter_numbering(title: &str) -> bool {
+    match split_numbering(title) {
+        (Some(numbering), _) => {
+            let head = numbering.split('.').next().unwrap_or_default();
+            head.len() == 1
+                && head.chars().all(|c| c.is_ascii_uppercase())
+                && !ROMAN_NUMERALS.contains(&head.to_lowercase().as_str())
+        }
+        (None, _) => false,
+    }
+}
+
+/// Lowercased title without numbering and punctuation: "3.1 Scaled Dot-Product" -> "scaled dot product".
+pub fn bare_title(title: &str) -> String {
+    let (_, title) = split_numbering(title);
+    return normalize(&title);
+}
+
+/// Split the leading section number from a title:
+/// "3.1 Model" -> ("3.1", "Model"), "IV. RESULTS" -> ("IV", "RESULTS"), "A. Proof" -> ("A", "Proof").
+fn split_numbering(title: &str) -> (Option<String>, String) {
+    let title = title.trim();
+    let (head, rest) = match title.split_once(|c: char| c.is_whitespace()) {
+        Some((head, rest)) => (head, rest.trim()),
+        None => return (None, title.to_string()),
+    };
+    let token = head.trim_end_matches(['.', ':', ')']);
+    if token.is_empty() {
+        return (None, title.to_string());
+    }
+
+    let is_arabic = token.chars().all(|c| c.is_ascii_digit() || c == '.');
+    let is_roman = ROMAN_NUMERALS.contains(&token.to_lowercase().as_str())
+        && token.chars().all(|c| c.is_ascii_uppercase());
+    // single capital letter (optionally followed by ".1", ".2.3", ...) used for appendices
+    let is_letter = {
+        let mut chars = token.chars();
+        let first = chars.next().unwrap();
+        let rest = chars.as_str();
+        first.is_ascii_uppercase()
+            && (rest.is_empty() && head.len() > token.len()
+                || rest.starts_with('.')
+                    && rest[1..].chars().all(|c| c.is_ascii_digit() || c == '.'))
+    };
+
+    if is_arabic || is_roman || is_letter {
+        return (Some(token.to_string()), rest.to_string());
+    }
+    return (None, title.to_string());
+}
+
+fn normalize(title: &str) -> String {
+    let title = title
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>();
+    return title.split_whitespace().collect::<Vec<&str>>().join(" ");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let cases = vec![
+            ("Abstract", SectionRole::Abstract),
+            ("Introduction", SectionRole::Introduction),
+            ("1 Introduction", SectionRole::Introduction),
+            ("1. INTRODUCTION", SectionRole::Introduction),
+            ("I. Introduction", SectionRole::Introduction),
+            ("2 Related Work", SectionRole::RelatedWork),
+            ("II. BACKGROUND", SectionRole::RelatedWork),
+            ("3 Model Architecture", SectionRole::Method),
+            ("3.1 Proposed Method", SectionRole::Method),
+            ("5 Experiments", SectionRole::Experiments),
+            ("5.1 Experimental Setup", SectionRole::Experiments),
+            ("6 Results", SectionRole::Results),
+            ("Ablation Study", SectionRole::Results),
+            ("7 Conclusion", SectionRole::Conclusion),
+            ("VII. CONCLUSIONS AND FUTURE WORK", SectionRole::Conclusion),
+            ("References", SectionRole::References),
+            ("Appendix", SectionRole::Appendix),
+            ("A. Proof of Theorem 1", SectionRole::Other),
+            ("Acknowledgements", SectionRole::Other),
+            ("Why Self-Attention", SectionRole::Other),
+        ];
+        for (title, role) in cases {
+            assert_eq!(SectionRole::classify(title), role, "{}", title);
+        }
+    }
+
+    #[test]
+    fn test_section_level() {
+        assert_eq!(section_level("Introduction"), 1);
+        assert_eq!(section_level("3 Model Architecture"), 1);
+        assert_eq!(section_level("3.2 Attention"), 2);
+        assert_eq!(section_level("3.2.1 Scaled Dot-Product Attention"), 3);
+        assert_eq!(section_level("IV. RESULTS"), 1);
+        assert_eq!(section_level("B.2 Hyperparameters"), 2);
+    }
+
+    #[test]
+    fn test_has_letter_numbering() {
+        assert!(has_letter_numbering("A. Datasets"));
+        assert!(has_letter_numbering("B.2 Hyperparameters"));
+        assert!(!has_letter_numbering("IV. EXPERIMENTS"));
+        assert!(!has_letter_numbering("V. CONCLUSION"));
+        assert!(!has_letter_numbering("3.1 Encoder"));
+        assert!(!has_letter_numbering("A Transformer"));
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let roles = SectionRole::parse_list("introduction, related work,method,unknown");
+        assert_eq!(
+            roles,
+            vec![
+                SectionRole::Introduction,
+                SectionRole::RelatedWork,
+                SectionRole::Method
+            ]
+        );
+    }
+}
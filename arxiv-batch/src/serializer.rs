@@ -0,0 +1,555 @@
+//! This module serializes papers into the text passed to the LLM.
+//! The same document can be rendered as XML, Markdown or JSON (`PAPER_FORMAT`).
+use crate::common::Paper;
+use crate::document::{DocumentSection, Figure, Table};
+use chrono::Datelike;
+use dotenvy::dotenv;
+use serde_json::json;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PaperFormat {
+    #[default]
+    Xml,
+    Markdown,
+    Json,
+}
+
+impl PaperFormat {
+    /// Read the format from `PAPER_FORMAT` (XML if it is not set or unknown).
+    pub fn from_env() -> PaperFormat {
+        dotenv().ok();
+        return match std::env::var("PAPER_FORMAT") {
+            Ok(format) if !format.trim().is_empty() => format.parse().unwrap_or_else(|e| {
+                eprintln!("WARNING: {}", e);
+                PaperFormat::Xml
+            }),
+            _ => PaperFormat::Xml,
+        };
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaperFormat::Xml => "xml",
+            PaperFormat::Markdown => "markdown",
+            PaperFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for PaperFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.trim().to_lowercase().as_str() {
+            "xml" => Ok(PaperFormat::Xml),
+            "markdown" | "md" => Ok(PaperFormat::Markdown),
+            "json" => Ok(PaperFormat::Json),
+            _ => Err(anyhow::anyhow!("Unknown paper format: {}", s)),
+        };
+    }
+}
+
+impl std::fmt::Display for PaperFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The parts of a paper to be serialized.
+pub struct PaperView<'a> {
+    pub paper: &'a Paper,
+    pub sections: Vec<&'a DocumentSection>,
+    pub figures: Vec<&'a Figure>,
+    pub tables: Vec<&'a Table>,
+}
+
+/// Escape the characters with a special meaning in XML text and attribute values.
+/// Control characters not allowed in XML, common in garbled PDF text, are replaced with spaces.
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    return escaped;
+}
+
+/// A minimal writer that always produces well-formed XML.
+struct XmlWriter {
+    xml: String,
+}
+
+impl XmlWriter {
+    fn new() -> XmlWriter {
+        XmlWriter { xml: String::new() }
+    }
+
+    fn start(&mut self, tag: &str, attributes: &[(&str, String)]) {
+        self.xml.push('<');
+        self.xml.push_str(tag);
+        for (name, value) in attributes {
+            self.xml
+                .push_str(format!(" {}=\"{}\"", name, escape_xml(value)).as_str());
+        }
+        self.xml.push('>');
+    }
+
+    fn end(&mut self, tag: &str) {
+        self.xml.push_str(format!("</{}>", tag).as_str());
+    }
+
+    fn element(&mut self, tag: &str, text: &str) {
+        self.start(tag, &[]);
+        self.xml.push_str(&escape_xml(text));
+        self.end(tag);
+    }
+}
+
+pub fn serialize_paper(view: &PaperView, format: PaperFormat) -> String {
+    return match format {
+        PaperFormat::Xml => paper2xml(view),
+        PaperFormat::Markdown => paper2markdown(view),
+        PaperFormat::Json => paper2json(view),
+    };
+}
+
+/// Serialize the references or the citations of a paper.
+/// `tag` is the name of the list ("references", "citations") and `item_tag` the name of an item.
+pub fn serialize_related(
+    papers: &[Paper],
+    tag: &str,
+    item_tag: &str,
+    format: PaperFormat,
+) -> String {
+    return match format {
+        PaperFormat::Xml => related2xml(papers, tag, item_tag),
+        PaperFormat::Markdown => related2markdown(papers, tag),
+        PaperFormat::Json => related2json(papers, tag),
+    };
+}
+
+/// Serialize the title, the abstract and the key points of the parts of a long paper,
+/// which are merged into its summary.
+pub fn serialize_notes(paper: &Paper, notes: &[String], format: PaperFormat) -> String {
+    return match format {
+        PaperFormat::Xml => notes2xml(paper, notes),
+        PaperFormat::Markdown => notes2markdown(paper, notes),
+        PaperFormat::Json => notes2json(paper, notes),
+    };
+}
+
+fn paper2xml(view: &PaperView) -> String {
+    let mut writer = XmlWriter::new();
+    writer.start("paper", &[]);
+
+    // basic information
+    writer.start("metadata", &[]);
+    writer.element("title", &view.paper.title);
+    writer.start("authors", &[]);
+    for author in view.paper.authors.iter() {
+        writer.element("author", &author.name);
+    }
+    writer.end("authors");
+    writer.end("metadata");
+
+    // contents
+    writer.start("contents", &[]);
+    for section in view.sections.iter() {
+        writer.start(
+            "section",
+            &[
+                ("role", section.role.to_string()),
+                ("level", section.level.to_string()),
+            ],
+        );
+        writer.element("title", &section.title);
+        for paragraph in section.contents.iter() {
+            writer.element("paragraph", paragraph);
+        }
+        writer.end("section");
+    }
+    writer.end("contents");
+
+    // figures and tables
+    writer.start("figures", &[]);
+    for figure in view.figures.iter() {
+        writer.start("figure", &[]);
+        writer.element("label", &figure.label);
+        writer.element("caption", &figure.caption);
+        writer.end("figure");
+    }
+    writer.end("figures");
+    writer.start("tables", &[]);
+    for table in view.tables.iter() {
+        writer.start("table", &[]);
+        writer.element("label", &table.label);
+        writer.element("caption", &table.caption);
+        for row in table.rows.iter() {
+            writer.start("row", &[]);
+            for cell in row.iter() {
+                writer.element("cell", cell);
+            }
+            writer.end("row");
+        }
+        writer.end("table");
+    }
+    writer.end("tables");
+
+    writer.end("paper");
+    return writer.xml;
+}
+
+fn paper2markdown(view: &PaperView) -> String {
+    let mut markdown = format!("# {}\n\n", view.paper.title);
+    if !view.paper.authors.is_empty() {
+        let authors = view
+            .paper
+            .authors
+            .iter()
+            .map(|author| author.name.as_str())
+            .collect::<Vec<&str>>();
+        markdown.push_str(format!("Authors: {}\n\n", authors.join(", ")).as_str());
+    }
+
+    for section in view.sections.iter() {
+        let level = (section.level as usize + 1).clamp(2, 6);
+        markdown.push_str(format!("{} {}\n\n", "#".repeat(level), section.title).as_str());
+        for paragraph in section.contents.iter() {
+            markdown.push_str(format!("{}\n\n", paragraph).as_str());
+        }
+    }
+
+    if !view.figures.is_empty() {
+        markdown.push_str("## Figures\n\n");
+        for figure in view.figures.iter() {
+            markdown.push_str(format!("- **{}**: {}\n", figure.label, figure.caption).as_str());
+        }
+        markdown.push('\n');
+    }
+
+    if !view.tables.is_empty() {
+        markdown.push_str("## Tables\n\n");
+        for table in view.tables.iter() {
+            markdown.push_str(format!("**{}**: {}\n\n", table.label, table.caption).as_str());
+            let width = table.rows.iter().map(|row| row.len()).max().unwrap_or(0);
+            for (i, row) in table.rows.iter().enumerate() {
+                let mut cells = row
+                    .iter()
+                    .map(|cell| cell.replace('|', "\\|").replace('\n', " "))
+                    .collect::<Vec<String>>();
+                cells.resize(width, String::new());
+                markdown.push_str(format!("| {} |\n", cells.join(" | ")).as_str());
+                if i == 0 {
+                    markdown.push_str(format!("|{}\n", " --- |".repeat(width)).as_str());
+                }
+            }
+            markdown.push('\n');
+        }
+    }
+    return markdown;
+}
+
+fn paper2json(view: &PaperView) -> String {
+    let value = json!({
+        "title": view.paper.title,
+        "authors": view
+            .paper
+            .authors
+            .iter()
+            .map(|author| author.name.clone())
+            .collect::<Vec<String>>(),
+        "sections": view
+            .sections
+            .iter()
+            .map(|section| json!({
+                "title": section.title,
+                "role": section.role,
+                "level": section.level,
+                "paragraphs": section.contents,
+            }))
+            .collect::<Vec<serde_json::Value>>(),
+        "figures": view.figures,
+        "tables": view
+            .tables
+            .iter()
+            .map(|table| json!({
+                "label": table.label,
+                "caption": table.caption,
+                "rows": table.rows,
+            }))
+            .collect::<Vec<serde_json::Value>>(),
+    });
+    return value.to_string();
+}
+
+fn related2xml(papers: &[Paper], tag: &str, item_tag: &str) -> String {
+    let mut writer = XmlWriter::new();
+    writer.start(tag, &[]);
+    for paper in papers {
+        writer.start(item_tag, &[]);
+        writer.element("title", &paper.title);
+        writer.start("authors", &[]);
+        for author in paper.authors.iter() {
+            writer.element("author", &author.name);
+        }
+        writer.end("authors");
+        writer.element("year", &paper.publication_date.year().to_string());
+        writer.element("abstract", &paper.abstract_text);
+        writer.end(item_tag);
+    }
+    writer.end(tag);
+    return writer.xml;
+}
+
+fn related2markdown(papers: &[Paper], tag: &str) -> String {
+    let mut title = tag.to_string();
+    if let Some(first) = title.get_mut(0..1) {
+        first.make_ascii_uppercase();
+    }
+    let mut markdown = format!("## {}\n\n", title);
+    for paper in papers {
+        markdown.push_str(
+            format!(
+                "### {} ({})\n\n",
+                paper.title,
+                paper.publication_date.year()
+            )
+            .as_str(),
+        );
+        if !paper.authors.is_empty() {
+            let authors = paper
+                .authors
+                .iter()
+                .map(|author| author.name.as_str())
+                .collect::<Vec<&str>>();
+            markdown.push_str(format!("Authors: {}\n\n", authors.join(", ")).as_str());
+        }
+        if !paper.abstract_text.is_empty() {
+            markdown.push_str(format!("{}\n\n", paper.abstract_text).as_str());
+        }
+    }
+    return markdown;
+}
+
+fn related2json(papers: &[Paper], tag: &str) -> String {
+    let items = papers
+        .iter()
+        .map(|paper| {
+            json!({
+                "title": paper.title,
+                "authors": paper
+                    .authors
+                    .iter()
+                    .map(|author| author.name.clone())
+                    .collect::<Vec<String>>(),
+                "year": paper.publication_date.year(),
+                "abstract": paper.abstract_text,
+            })
+        })
+        .collect::<Vec<serde_json::Value>>();
+    return json!({ tag: items }).to_string();
+}
+
+fn notes2xml(paper: &Paper, notes: &[String]) -> String {
+    let mut writer = XmlWriter::new();
+    writer.start("paper", &[]);
+    writer.start("metadata", &[]);
+    writer.element("title", &paper.title);
+    writer.end("metadata");
+    writer.element("abstract", &paper.abstract_text);
+    writer.start("notes", &[]);
+    for note in notes {
+        writer.element("note", note);
+    }
+    writer.end("notes");
+    writer.end("paper");
+    return writer.xml;
+}
+
+fn notes2markdown(paper: &Paper, notes: &[String]) -> String {
+    let mut markdown = format!("# {}\n\n", paper.title);
+    if !paper.abstract_text.is_empty() {
+        markdown.push_str(format!("## Abstract\n\n{}\n\n", paper.abstract_text).as_str());
+    }
+    for (i, note) in notes.iter().enumerate() {
+        markdown.push_str(format!("## Part {}\n\n{}\n\n", i + 1, note).as_str());
+    }
+    return markdown;
+}
+
+fn notes2json(paper: &Paper, notes: &[String]) -> String {
+    let value = json!({
+        "title": paper.title,
+        "abstract": paper.abstract_text,
+        "notes": notes,
+    });
+    return value.to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Author;
+    use crate::document::Document;
+
+    fn paper() -> Paper {
+        let mut paper = Paper::default();
+        paper.title = "Less <is> More & Better".to_string();
+        paper.authors = vec![Author {
+            name: "Ada \"AL\" Lovelace".to_string(),
+            ..Default::default()
+        }];
+        paper.document = Document {
+            sections: vec![
+                DocumentSection::new(
+                    0,
+                    "1 Introduction",
+                    vec!["If a < b then c > d.".to_string()],
+                ),
+                DocumentSection::new(1, "1.1 Setting", vec!["R&D matters.".to_string()]),
+            ],
+            figures: vec![Figure {
+                label: "Figure 1".to_string(),
+                caption: "Overview".to_string(),
+                section: 0,
+            }],
+            tables: vec![Table {
+                label: "Table 1".to_string(),
+                caption: "BLEU <scores>".to_string(),
+                section: 1,
+                rows: vec![
+                    vec!["Model".to_string(), "BLEU".to_string()],
+                    vec!["A|B".to_string(), "27.3".to_string()],
+                ],
+            }],
+        };
+        paper.references = vec![Paper::reference(
+            "ss",
+            "Attention & Transformers",
+            "We propose <the> Transformer.",
+            vec![],
+            Default::default(),
+        )];
+        return paper;
+    }
+
+    fn view(paper: &Paper) -> PaperView<'_> {
+        PaperView {
+            paper,
+            sections: paper.document.iter().collect(),
+            figures: paper.document.figures.iter().collect(),
+            tables: paper.document.tables.iter().collect(),
+        }
+    }
+
+    #[test]
+    fn test_xml_is_escaped() {
+        let paper = paper();
+        let xml = serialize_paper(&view(&paper), PaperFormat::Xml);
+        assert!(
+            xml.starts_with("<paper><metadata><title>Less &lt;is&gt; More &amp; Better</title>")
+        );
+        assert!(xml.contains("<author>Ada &quot;AL&quot; Lovelace</author>"));
+        assert!(xml
+            .contains("<section role=\"introduction\" level=\"1\"><title>1 Introduction</title>"));
+        assert!(xml.contains("<paragraph>If a &lt; b then c &gt; d.</paragraph>"));
+        assert!(xml.contains("<caption>BLEU &lt;scores&gt;</caption>"));
+        assert!(xml.ends_with("</tables></paper>"));
+
+        let xml = serialize_related(
+            &paper.references,
+            "references",
+            "reference",
+            PaperFormat::Xml,
+        );
+        assert!(
+            xml.starts_with("<references><reference><title>Attention &amp; Transformers</title>")
+        );
+        assert!(xml.contains("<abstract>We propose &lt;the&gt; Transformer.</abstract>"));
+        assert!(xml.ends_with("</reference></references>"));
+
+        assert_eq!(escape_xml("a\u{0}b\u{b}c\td\ne"), "a b c\td\ne");
+    }
+
+    #[test]
+    fn test_markdown() {
+        let paper = paper();
+        let markdown = serialize_paper(&view(&paper), PaperFormat::Markdown);
+        assert!(
+            markdown.starts_with("# Less <is> More & Better\n\nAuthors: Ada \"AL\" Lovelace\n\n")
+        );
+        assert!(markdown.contains("## 1 Introduction\n\nIf a < b then c > d.\n\n"));
+        assert!(markdown.contains("### 1.1 Setting\n\n"));
+        assert!(markdown.contains("- **Figure 1**: Overview\n"));
+        assert!(markdown.contains("| Model | BLEU |\n| --- | --- |\n| A\\|B | 27.3 |\n"));
+
+        let markdown = serialize_related(
+            &paper.references,
+            "references",
+            "reference",
+            PaperFormat::Markdown,
+        );
+        assert!(markdown.starts_with("## References\n\n### Attention & Transformers ("));
+    }
+
+    #[test]
+    fn test_json() {
+        let paper = paper();
+        let value: serde_json::Value =
+            serde_json::from_str(&serialize_paper(&view(&paper), PaperFormat::Json)).unwrap();
+        assert_eq!(value["title"], "Less <is> More & Better");
+        assert_eq!(value["sections"][1]["role"], "other");
+        assert_eq!(value["sections"][1]["level"], 2);
+        assert_eq!(value["tables"][0]["rows"][1][0], "A|B");
+
+        let value: serde_json::Value = serde_json::from_str(&serialize_related(
+            &paper.references,
+            "citations",
+            "citation",
+            PaperFormat::Json,
+        ))
+        .unwrap();
+        assert_eq!(value["citations"][0]["title"], "Attention & Transformers");
+    }
+
+    #[test]
+    fn test_paper_format() {
+        assert_eq!("XML".parse::<PaperFormat>().unwrap(), PaperFormat::Xml);
+        assert_eq!("md".parse::<PaperFormat>().unwrap(), PaperFormat::Markdown);
+        assert_eq!("json".parse::<PaperFormat>().unwrap(), PaperFormat::Json);
+        assert!("yaml".parse::<PaperFormat>().is_err());
+    }
+
+    #[test]
+    fn test_serialize_notes() {
+        let mut paper = paper();
+        paper.abstract_text = "Smaller & faster.".to_string();
+        let notes = vec!["Uses <b> tags & more.".to_string()];
+
+        let xml = serialize_notes(&paper, &notes, PaperFormat::Xml);
+        assert!(xml.contains("<title>Less &lt;is&gt; More &amp; Better</title>"));
+        assert!(xml.contains("<abstract>Smaller &amp; faster.</abstract>"));
+        assert!(xml.contains("<notes><note>Uses &lt;b&gt; tags &amp; more.</note></notes>"));
+
+        let markdown = serialize_notes(&paper, &notes, PaperFormat::Markdown);
+        assert!(
+            markdown.starts_with("# Less <is> More & Better\n\n## Abstract\n\nSmaller & faster.")
+        );
+        assert!(markdown.contains("## Part 1\n\nUses <b> tags & more."));
+        assert!(!markdown.contains("&amp;"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&serialize_notes(&paper, &notes, PaperFormat::Json)).unwrap();
+        assert_eq!(json["abstract"], "Smaller & faster.");
+        assert_eq!(json["notes"][0], "Uses <b> tags & more.");
+    }
+}
@@ -0,0 +1,199 @@
+//! This module persists the parsed sections of the papers on the local disk,
+//! so that a paper only has to be parsed once across runs.
+use crate::common::Paper;
+use crate::document::Document;
+use crate::latex::EprintDownloader;
+use crate::utils::arxiv_id_from_url;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dotenvy::dotenv;
+use rsrpp::parser::structs::Section;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPaper {
+    pub arxiv_id: String,
+    pub ss_id: String,
+    pub title: String,
+    pub parsed_at: DateTime<Utc>,
+    #[serde(default = "Document::default")]
+    pub document: Document,
+    /// Sections stored before the document model was introduced
+    #[serde(skip_serializing, default = "Vec::new")]
+    pub sections: Vec<Section>,
+}
+
+impl StoredPaper {
+    pub fn from_paper(paper: &Paper) -> StoredPaper {
+        StoredPaper {
+            arxiv_id: paper.arxiv_id.clone(),
+            ss_id: paper.ss_id.clone(),
+            title: paper.title.clone(),
+            parsed_at: Utc::now(),
+            document: paper.document.clone(),
+            sections: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SectionStore {
+    pub dir: PathBuf,
+    eprints: EprintDownloader,
+}
+
+impl SectionStore {
+    pub fn new() -> SectionStore {
+        dotenv().ok();
+        let cache_dir = std::env::var("CACHE_DIR").unwrap_or(String::from(".cache"));
+        SectionStore {
+            dir: Path::new(&cache_dir).join("sections"),
+            eprints: EprintDownloader::default(),
+        }
+    }
+
+    pub fn from_dir(dir: &Path) -> SectionStore {
+        SectionStore {
+            dir: dir.to_path_buf(),
+            eprints: EprintDownloader::default(),
+        }
+    }
+
+    /// Wait `wait_time` seconds between the LaTeX source downloads of the papers not stored yet.
+    pub fn wait_time(&mut self, wait_time: u64) -> &mut Self {
+        self.eprints = EprintDownloader::new(wait_time);
+        return self;
+    }
+
+    /// Build the file name of a paper in the store.
+    /// The arXiv ID is preferred, then the Semantic Scholar ID, then the title.
+    pub fn key(paper: &Paper) -> String {
+        let key = if !paper.arxiv_id.is_empty() {
+            arxiv_id_from_url(&paper.arxiv_id)
+        } else if !paper.ss_id.is_empty() {
+            paper.ss_id.clone()
+        } else {
+            paper.title.to_lowercase()
+        };
+        return key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+    }
+
+    pub fn path(&self, paper: &Paper) -> PathBuf {
+        return self.dir.join(format!("{}.json", Self::key(paper)));
+    }
+
+    pub fn contains(&self, paper: &Paper) -> bool {
+        return self.path(paper).exists();
+    }
+
+    pub fn save(&self, paper: &Paper) -> Result<()> {
+        if !self.dir.exists() {
+            std::fs::create_dir_all(&self.dir)?;
+        }
+        let stored = StoredPaper::from_paper(paper);
+        std::fs::write(self.path(paper), serde_json::to_string(&stored)?)?;
+        return Ok(());
+    }
+
+    /// Load the stored document into the paper.
+    /// Returns `false` if the paper has not been parsed yet.
+    pub fn load(&self, paper: &mut Paper) -> Result<bool> {
+        let path = self.path(paper);
+        if !path.exists() {
+            return Ok(false);
+        }
+        let stored = serde_json::from_str::<StoredPaper>(&std::fs::read_to_string(path)?)?;
+        paper.document = if stored.document.is_empty() {
+            Document::from_sections(&stored.sections)
+        } else {
+            stored.document
+        };
+        return Ok(true);
+    }
+
+    /// Get the original text of the paper from the store,
+    /// or parse the PDF and store the result if the paper is not stored yet.
+    /// An explicit `pdf` is always parsed, and a stored file that cannot be read is replaced.
+    pub async fn get_original_text(
+        &self,
+        paper: &mut Paper,
+        pdf: Option<String>,
+        verbose: bool,
+    ) -> Result<()> {
+        if pdf.is_none() {
+            match self.load(paper) {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(e) => eprintln!(
+                    "WARNING: Failed to load the stored sections, parsing the paper again: {}",
+                    e
+                ),
+            }
+        }
+        paper.get_original_text(pdf, &self.eprints, verbose).await?;
+        self.save(paper)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        let mut paper = Paper::default();
+        paper.title = "Attention Is All You Need".to_string();
+        assert_eq!(SectionStore::key(&paper), "attention_is_all_you_need");
+
+        paper.ss_id = "204e3073870fae3d05bcbc2f6a8e263d9b72e776".to_string();
+        assert_eq!(
+            SectionStore::key(&paper),
+            "204e3073870fae3d05bcbc2f6a8e263d9b72e776"
+        );
+
+        paper.arxiv_id = "http://arxiv.org/abs/1706.03762v7".to_string();
+        assert_eq!(SectionStore::key(&paper), "1706.03762v7");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join("arxiv-batch-test-section-store");
+        let store = SectionStore::from_dir(&dir);
+
+        let mut paper = Paper::default();
+        paper.arxiv_id = "http://arxiv.org/abs/1706.03762v7".to_string();
+        paper.document = Document::from_sections(&[
+            Section {
+                index: 0,
+                title: "Introduction".to_string(),
+                contents: vec!["Recurrent neural networks ...".to_string()],
+            },
+            Section {
+                index: 1,
+                title: "Conclusion".to_string(),
+                contents: vec!["In this work ...".to_string()],
+            },
+        ]);
+        store.save(&paper).unwrap();
+        assert!(store.contains(&paper));
+
+        let mut loaded = Paper::default();
+        loaded.arxiv_id = paper.arxiv_id.clone();
+        assert!(store.load(&mut loaded).unwrap());
+        assert_eq!(loaded.document, paper.document);
+        assert_eq!(loaded.document.find_by_title("Introduction").len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
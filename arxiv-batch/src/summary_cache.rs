@@ -0,0 +1,181 @@
+//! This module caches the summaries on the local disk, so that a paper whose summary was
+//! generated but not posted (e.g. Notion failed) is not summarized and paid for again.
+//! An entry is keyed by the model ID, the prompt template version and a hash of the paper content.
+use crate::common::{Paper, Summary};
+use crate::cost::TokenUsage;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use dotenvy::dotenv;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedSummary {
+    pub key: String,
+    pub model_id: String,
+    pub template_version: String,
+    pub content_hash: String,
+    pub title: String,
+    pub arxiv_id: String,
+    pub created_at: DateTime<Utc>,
+    pub summary: Summary,
+    /// Usage of the requests that generated the summary
+    pub usage: TokenUsage,
+}
+
+impl CachedSummary {
+    pub fn from_paper(key: &str, model_id: &str, paper: &Paper) -> CachedSummary {
+        CachedSummary {
+            key: key.to_string(),
+            model_id: model_id.to_string(),
+            template_version: paper.summary.template_version.clone(),
+            content_hash: content_hash(paper),
+            title: paper.title.clone(),
+            arxiv_id: paper.arxiv_id.clone(),
+            created_at: Utc::now(),
+            summary: paper.summary.clone(),
+            usage: paper.usage.clone(),
+        }
+    }
+}
+
+/// Hex SHA-256 of the parts joined with newlines.
+pub fn hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\n");
+    }
+    return format!("{:x}", hasher.finalize());
+}
+
+/// Hash of the content summarized: the title, the abstract, the document and the references.
+pub fn content_hash(paper: &Paper) -> String {
+    let document = serde_json::to_string(&paper.document).unwrap_or_default();
+    let references = paper
+        .references
+        .iter()
+        .map(|reference| reference.title.as_str())
+        .collect::<Vec<&str>>()
+        .join("\n");
+    return hash(&[&paper.title, &paper.abstract_text, &document, &references]);
+}
+
+#[derive(Debug, Clone)]
+pub struct SummaryCache {
+    pub dir: PathBuf,
+}
+
+impl SummaryCache {
+    pub fn new() -> SummaryCache {
+        dotenv().ok();
+        let cache_dir = std::env::var("CACHE_DIR").unwrap_or(String::from(".cache"));
+        SummaryCache {
+            dir: Path::new(&cache_dir).join("summaries"),
+        }
+    }
+
+    pub fn from_dir(dir: &Path) -> SummaryCache {
+        SummaryCache {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    pub fn path(&self, key: &str) -> PathBuf {
+        return self.dir.join(format!("{}.json", key));
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<CachedSummary>> {
+        let path = self.path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let entry = serde_json::from_str::<CachedSummary>(&std::fs::read_to_string(path)?)?;
+        return Ok(Some(entry));
+    }
+
+    pub fn put(&self, entry: &CachedSummary) -> Result<()> {
+        if !self.dir.exists() {
+            std::fs::create_dir_all(&self.dir)?;
+        }
+        std::fs::write(self.path(&entry.key), serde_json::to_string(entry)?)?;
+        return Ok(());
+    }
+
+    /// All the entries, oldest first. Unreadable files are skipped with a warning.
+    pub fn entries(&self) -> Result<Vec<CachedSummary>> {
+        let mut entries = Vec::new();
+        if !self.dir.exists() {
+            return Ok(entries);
+        }
+        for file in std::fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path.extension().map(|x| x != "json").unwrap_or(true) {
+                continue;
+            }
+            let entry = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| Ok(serde_json::from_str::<CachedSummary>(&text)?));
+            match entry {
+                Ok(entry) => entries.push(entry),
+                Err(e) => eprintln!("WARNING: Failed to read {}: {}", path.display(), e),
+            }
+        }
+        entries.sort_by_key(|entry| entry.created_at);
+        return Ok(entries);
+    }
+
+    /// Remove the entries matching `predicate` and return how many were removed.
+    pub fn prune(&self, predicate: impl Fn(&CachedSummary) -> bool) -> Result<usize> {
+        let mut count = 0;
+        for entry in self.entries()? {
+            if predicate(&entry) {
+                std::fs::remove_file(self.path(&entry.key))?;
+                count += 1;
+            }
+        }
+        return Ok(count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_put_get_and_prune() {
+        let dir = std::env::temp_dir().join("arxiv-batch-test-summary-cache");
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = SummaryCache::from_dir(&dir);
+
+        let mut paper = Paper::default();
+        paper.title = "Attention Is All You Need".to_string();
+        paper.summary.overview = "A transformer.".to_string();
+        paper.summary.template_version = "summary-ja-1".to_string();
+        let key = hash(&["gpt-4o-mini", "summary-ja-1", &content_hash(&paper)]);
+        assert_eq!(key.len(), 64);
+        assert!(cache.get(&key).unwrap().is_none());
+
+        cache
+            .put(&CachedSummary::from_paper(&key, "gpt-4o-mini", &paper))
+            .unwrap();
+        let mut old = CachedSummary::from_paper("old", "gpt-4o", &paper);
+        old.created_at = Utc::now() - chrono::Duration::days(30);
+        cache.put(&old).unwrap();
+
+        let entry = cache.get(&key).unwrap().unwrap();
+        assert_eq!(entry.summary.overview, "A transformer.");
+        assert_eq!(entry.template_version, "summary-ja-1");
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].key, "old");
+
+        let threshold = Utc::now() - chrono::Duration::days(7);
+        let removed = cache.prune(|entry| entry.created_at < threshold).unwrap();
+        assert_eq!(removed, 1);
+        assert_eq!(cache.entries().unwrap().len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,7 @@
 use super::ai::*;
 use super::collector::*;
 use super::common::*;
+use super::latex::EprintDownloader;
 use std::sync::Once;
 
 static INIT: Once = Once::new();
@@ -61,7 +62,10 @@ async fn test_paper2xml() {
     let _ = collector.update_from_arxiv(&mut paper, true).await;
     let _ = collector.update_from_ss(&mut paper, false).await;
 
-    match paper.get_original_text(None, true).await {
+    match paper
+        .get_original_text(None, &EprintDownloader::default(), true)
+        .await
+    {
         Ok(_) => {}
         Err(e) => {
             assert!(false, "Error: {:?}", e);
@@ -87,7 +91,10 @@ async fn test_get_keywords() {
     let _ = collector.update_from_arxiv(&mut paper, true).await;
     let _ = collector.update_from_ss(&mut paper, false).await;
 
-    match paper.get_original_text(None, true).await {
+    match paper
+        .get_original_text(None, &EprintDownloader::default(), true)
+        .await
+    {
         Ok(_) => {}
         Err(e) => {
             assert!(false, "Error: {:?}", e);
@@ -109,14 +116,17 @@ async fn test_summarize() {
     let _ = collector.update_from_arxiv(&mut paper, true).await;
     let _ = collector.update_from_ss(&mut paper, false).await;
 
-    match paper.get_original_text(None, true).await {
+    match paper
+        .get_original_text(None, &EprintDownloader::default(), true)
+        .await
+    {
         Ok(_) => {}
         Err(e) => {
             assert!(false, "Error: {:?}", e);
         }
     }
 
-    let ai = AI::new("gpt-4o-mini");
+    let ai = AI::new("gpt-4o-mini").unwrap();
     let result = ai.summarize(&mut paper).await;
     match result {
         Ok(_) => {
@@ -0,0 +1,117 @@
+//! This module counts the tokens of the prompts and knows the context window of each model,
+//! so that long papers can be split before they overflow the context window.
+use openai_tools::Message;
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton, CoreBPE};
+
+/// Context windows of the models, matched by prefix (the first match wins).
+const CONTEXT_WINDOWS: [(&str, usize); 12] = [
+    ("gpt-4o-mini", 128_000),
+    ("gpt-4o", 128_000),
+    ("chatgpt-4o", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3-mini", 200_000),
+    ("o3", 200_000),
+];
+
+const DEFAULT_CONTEXT_WINDOW: usize = 128_000;
+
+/// Tokens added by the chat format for every message.
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// Upper bound of the tokens reserved for the response.
+const MAX_RESPONSE_TOKENS: usize = 16_384;
+
+pub fn context_window(model_id: &str) -> usize {
+    return CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| model_id.starts_with(prefix))
+        .map(|(_, size)| *size)
+        .unwrap_or(DEFAULT_CONTEXT_WINDOW);
+}
+
+/// Tokens available for the prompt: the context window minus the tokens reserved for the response.
+pub fn prompt_budget(model_id: &str) -> usize {
+    return window_prompt_budget(context_window(model_id));
+}
+
+/// Tokens available for the prompt in a context window of `window` tokens.
+pub fn window_prompt_budget(window: usize) -> usize {
+    return window - (window / 4).min(MAX_RESPONSE_TOKENS);
+}
+
+/// Count the tokens of `text` with the tokenizer of the model.
+/// Unknown models are counted with o200k_base, the tokenizer of the recent OpenAI models.
+pub fn count_tokens(model_id: &str, text: &str) -> usize {
+    return with_bpe(model_id, |bpe| bpe.encode_ordinary(text).len());
+}
+
+/// Cut `text` down to at most `max_tokens` tokens.
+pub fn truncate_tokens(model_id: &str, text: &str, max_tokens: usize) -> String {
+    return with_bpe(model_id, |bpe| {
+        let tokens = bpe.encode_ordinary(text);
+        if tokens.len() <= max_tokens {
+            return text.to_string();
+        }
+        return bpe
+            .decode(tokens[..max_tokens].to_vec())
+            .unwrap_or_else(|_| text.chars().take(max_tokens).collect());
+    });
+}
+
+fn with_bpe<T>(model_id: &str, f: impl FnOnce(&CoreBPE) -> T) -> T {
+    let bpe = match get_tokenizer(model_id) {
+        Some(Tokenizer::Cl100kBase) => cl100k_base_singleton(),
+        _ => o200k_base_singleton(),
+    };
+    let bpe = bpe.lock();
+    return f(&bpe);
+}
+
+pub fn count_message_tokens(model_id: &str, messages: &[Message]) -> usize {
+    return messages
+        .iter()
+        .map(|message| count_tokens(model_id, &message.content) + TOKENS_PER_MESSAGE)
+        .sum();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_window() {
+        assert_eq!(context_window("gpt-4o-mini-2024-07-18"), 128_000);
+        assert_eq!(context_window("gpt-4-0613"), 8_192);
+        assert_eq!(context_window("gpt-4-32k"), 32_768);
+        assert_eq!(context_window("o1-preview"), 200_000);
+        assert_eq!(context_window("unknown-model"), DEFAULT_CONTEXT_WINDOW);
+        assert_eq!(prompt_budget("gpt-4-0613"), 6_144);
+        assert_eq!(prompt_budget("gpt-4o"), 128_000 - 16_384);
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        assert_eq!(count_tokens("gpt-4o", ""), 0);
+        assert_eq!(count_tokens("gpt-4o", "hello world"), 2);
+        assert_eq!(count_tokens("gpt-4-0613", "hello world"), 2);
+
+        let messages = vec![
+            Message::new("system", "hello world"),
+            Message::new("user", "hello world"),
+        ];
+        assert_eq!(count_message_tokens("gpt-4o", &messages), 12);
+    }
+
+    #[test]
+    fn test_truncate_tokens() {
+        assert_eq!(truncate_tokens("gpt-4o", "hello world", 1), "hello");
+        assert_eq!(truncate_tokens("gpt-4o", "hello world", 10), "hello world");
+    }
+}
@@ -0,0 +1,412 @@
+//! This module triages the papers of a day before they are summarized.
+//! The title and the abstract of each paper are scored against the interest profile of the
+//! team (`INTEREST_PROFILE`, a TOML file) with keyword rules and, optionally, a rating by a
+//! cheap LLM. Only the top N papers and the papers above the threshold are processed.
+use crate::ai::AI;
+use crate::common::Paper;
+use crate::cost::Budget;
+use crate::llm::CancelToken;
+use crate::prompt::render;
+use anyhow::Result;
+use dotenvy::dotenv;
+use openai_tools::json_schema::JsonSchema;
+use openai_tools::Message;
+use regex::Regex;
+use serde::Deserialize;
+use std::path::Path;
+
+const DEFAULT_PROMPT: &str = "You screen new papers for a research team. The interests of the team:\n\n{description}\n\nRate how relevant the following paper is to the team from 0 (irrelevant) to 10 (must read).\n\nTitle: {title}\n\nAbstract: {abstract}";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct KeywordRule {
+    /// Phrase matched at the start of a word ignoring the case ("language model" also matches
+    /// "language models"), or a regular expression
+    pub pattern: String,
+    /// Added to the score when the pattern matches (negative to demote a topic)
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+    #[serde(default)]
+    pub regex: bool,
+}
+
+fn default_weight() -> f64 {
+    return 1.0;
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct InterestProfile {
+    /// Interests of the team in natural language, given to the LLM
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub keywords: Vec<KeywordRule>,
+    /// Multiplier of the weight of a keyword found in the title
+    #[serde(default = "default_title_weight")]
+    pub title_weight: f64,
+    /// Process at most this many papers, the highest scores first
+    #[serde(default)]
+    pub top_n: Option<usize>,
+    /// Process only the papers scoring at least this much
+    #[serde(default)]
+    pub threshold: Option<f64>,
+    /// Model that rates the relevance of each paper; no LLM is used if it is not set
+    #[serde(default)]
+    pub llm_model_id: Option<String>,
+    /// Score of a paper rated 10 by the LLM
+    #[serde(default = "default_llm_weight")]
+    pub llm_weight: f64,
+    /// Prompt of the rating with `{description}`, `{title}` and `{abstract}`
+    #[serde(default = "default_prompt")]
+    pub prompt: String,
+}
+
+fn default_title_weight() -> f64 {
+    return 2.0;
+}
+
+fn default_llm_weight() -> f64 {
+    return 5.0;
+}
+
+fn default_prompt() -> String {
+    return String::from(DEFAULT_PROMPT);
+}
+
+impl InterestProfile {
+    pub fn parse(text: &str) -> Result<InterestProfile> {
+        return toml::from_str(text)
+            .map_err(|e| anyhow::anyhow!("Failed to parse interest profile: {}", e));
+    }
+
+    pub fn load(path: &Path) -> Result<InterestProfile> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+        return InterestProfile::parse(&text);
+    }
+
+    /// Load the profile from `INTEREST_PROFILE`, or None if it is not set (no triage).
+    pub fn from_env() -> Result<Option<InterestProfile>> {
+        dotenv().ok();
+        return match std::env::var("INTEREST_PROFILE") {
+            Ok(path) if !path.trim().is_empty() => {
+                Ok(Some(InterestProfile::load(Path::new(path.trim()))?))
+            }
+            _ => Ok(None),
+        };
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TriageScore {
+    pub score: f64,
+    pub keyword_score: f64,
+    /// Rating of the LLM from 0 to 10
+    pub llm_rating: Option<u32>,
+    /// Patterns found in the title or the abstract
+    pub matched: Vec<String>,
+    /// Reason of the rating given by the LLM
+    pub reason: String,
+}
+
+impl std::fmt::Display for TriageScore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.2} (keywords: {:.2}", self.score, self.keyword_score)?;
+        if !self.matched.is_empty() {
+            write!(f, " [{}]", self.matched.join(", "))?;
+        }
+        if let Some(rating) = self.llm_rating {
+            write!(f, ", LLM: {}/10", rating)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TriageResult {
+    /// Papers to process, the highest scores first
+    pub selected: Vec<(Paper, TriageScore)>,
+    pub skipped: Vec<(Paper, TriageScore)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Triage {
+    profile: InterestProfile,
+    rules: Vec<(Regex, f64)>,
+    llm: Option<AI>,
+    /// The papers are scored by the keywords only once the ratings reach the budget
+    budget: Budget,
+}
+
+impl Triage {
+    pub fn new(profile: InterestProfile) -> Result<Triage> {
+        let mut rules = Vec::new();
+        for rule in profile.keywords.iter() {
+            let pattern = if rule.regex {
+                format!("(?i){}", rule.pattern)
+            } else {
+                format!(r"(?i)\b{}", regex::escape(rule.pattern.trim()))
+            };
+            let pattern = Regex::new(&pattern)
+                .map_err(|e| anyhow::anyhow!("Invalid keyword {}: {}", rule.pattern, e))?;
+            rules.push((pattern, rule.weight));
+        }
+        let llm = profile
+            .llm_model_id
+            .as_ref()
+            .map(|model_id| AI::new(model_id))
+            .transpose()?;
+        return Ok(Triage {
+            profile,
+            rules,
+            llm,
+            budget: Budget::default(),
+        });
+    }
+
+    pub fn llm(&mut self, llm: AI) -> &mut Self {
+        self.llm = Some(llm);
+        return self;
+    }
+
+    pub fn budget(&mut self, budget: Budget) -> &mut Self {
+        self.budget = budget;
+        return self;
+    }
+
+    pub fn cancel_token(&mut self, cancel: CancelToken) -> &mut Self {
+        if let Some(llm) = self.llm.as_mut() {
+            llm.cancel_token(cancel);
+        }
+        return self;
+    }
+
+    /// Tokens and cost of the ratings so far.
+    pub fn usage(&self) -> Option<crate::cost::TokenUsage> {
+        return self.llm.as_ref().map(|llm| llm.run_usage());
+    }
+
+    /// Sum of the weights of the keywords found; a keyword in the title counts `title_weight` times.
+    pub fn keyword_score(&self, paper: &Paper) -> (f64, Vec<String>) {
+        let mut score = 0.0;
+        let mut matched = Vec::new();
+        for ((pattern, weight), rule) in self.rules.iter().zip(self.profile.keywords.iter()) {
+            if pattern.is_match(&paper.title) {
+                score += weight * self.profile.title_weight;
+            } else if pattern.is_match(&paper.abstract_text) {
+                score += weight;
+            } else {
+                continue;
+            }
+            matched.push(rule.pattern.clone());
+        }
+        return (score, matched);
+    }
+
+    /// Score a paper. If the rating by the LLM fails, only the keywords are used.
+    pub async fn score(&self, paper: &Paper) -> TriageScore {
+        let (keyword_score, matched) = self.keyword_score(paper);
+        let mut score = TriageScore {
+            score: keyword_score,
+            keyword_score,
+            matched,
+            ..Default::default()
+        };
+        let llm = self
+            .llm
+            .as_ref()
+            .filter(|llm| self.budget.exceeded(&llm.run_usage()).is_none());
+        if let Some(llm) = llm {
+            match self.rate(llm, paper).await {
+                Ok((rating, reason)) => {
+                    score.score += self.profile.llm_weight * rating as f64 / 10.0;
+                    score.llm_rating = Some(rating);
+                    score.reason = reason;
+                }
+                Err(e) => eprintln!("WARNING: Failed to rate {}: {}", paper.title, e),
+            }
+        }
+        return score;
+    }
+
+    async fn rate(&self, llm: &AI, paper: &Paper) -> Result<(u32, String)> {
+        let prompt = render(
+            &self.profile.prompt,
+            &[
+                ("description", self.profile.description.trim()),
+                ("title", &paper.title),
+                ("abstract", &paper.abstract_text),
+            ],
+        );
+        let mut json_schema = JsonSchema::new("relevance");
+        json_schema.add_property(
+            "relevance",
+            "integer",
+            Some(String::from("Relevance from 0 to 10")),
+        );
+        json_schema.add_property(
+            "reason",
+            "string",
+            Some(String::from("Reason of the rating in one sentence")),
+        );
+        let response = llm
+            .request_json(vec![Message::new("user", &prompt)], json_schema)
+            .await?;
+        let rating = response["relevance"]
+            .as_u64()
+            .ok_or_else(|| anyhow::anyhow!("No relevance in the response: {}", response))?;
+        let reason = response["reason"].as_str().unwrap_or_default().to_string();
+        return Ok((rating.min(10) as u32, reason));
+    }
+
+    /// Score the papers and split them into the papers to process and the skipped papers.
+    pub async fn triage(&self, papers: Vec<Paper>) -> TriageResult {
+        let mut scored = Vec::new();
+        for paper in papers {
+            let score = self.score(&paper).await;
+            scored.push((paper, score));
+        }
+        return self.select(scored);
+    }
+
+    /// Keep the papers above the threshold, then the top N of them.
+    /// Without a threshold or a limit, all the papers are kept in the order of their scores.
+    pub fn select(&self, mut scored: Vec<(Paper, TriageScore)>) -> TriageResult {
+        scored.sort_by(|a, b| b.1.score.partial_cmp(&a.1.score).unwrap());
+        let mut result = TriageResult::default();
+        for (paper, score) in scored {
+            let above = self
+                .profile
+                .threshold
+                .map(|threshold| score.score >= threshold)
+                .unwrap_or(true);
+            let within = self
+                .profile
+                .top_n
+                .map(|top_n| result.selected.len() < top_n)
+                .unwrap_or(true);
+            if above && within {
+                result.selected.push((paper, score));
+            } else {
+                result.skipped.push((paper, score));
+            }
+        }
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::tests::PaperBuilder;
+
+    const PROFILE: &str = r#"
+        description = "Efficient large language models and knowledge distillation."
+        top_n = 2
+        threshold = 1.0
+
+        [[keywords]]
+        pattern = "language model"
+        weight = 2.0
+
+        [[keywords]]
+        pattern = "distill(ation|ing)?"
+        regex = true
+
+        [[keywords]]
+        pattern = "speech"
+        weight = -3.0
+    "#;
+
+    #[tokio::test]
+    async fn test_keyword_triage() {
+        let triage = Triage::new(InterestProfile::parse(PROFILE).unwrap()).unwrap();
+        let (score, matched) = triage.keyword_score(
+            &PaperBuilder::new("Distilling a Language Model")
+                .abstract_text("We distill a large language model.")
+                .build(),
+        );
+        assert_eq!(score, 6.0);
+        assert_eq!(matched, vec!["language model", "distill(ation|ing)?"]);
+
+        let result = triage
+            .triage(vec![
+                PaperBuilder::new("Speech Language Models")
+                    .abstract_text("Spoken language models.")
+                    .build(),
+                PaperBuilder::new("Graph Neural Networks")
+                    .abstract_text("We study graphs.")
+                    .build(),
+                PaperBuilder::new("Distilling a Language Model")
+                    .abstract_text("We distill it.")
+                    .build(),
+                PaperBuilder::new("Small Models")
+                    .abstract_text("Knowledge distillation for small models.")
+                    .build(),
+                PaperBuilder::new("Tiny Language Models")
+                    .abstract_text("We train small models.")
+                    .build(),
+            ])
+            .await;
+        let titles = result
+            .selected
+            .iter()
+            .map(|(paper, _)| paper.title.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            titles,
+            vec!["Distilling a Language Model", "Tiny Language Models"]
+        );
+        assert_eq!(result.skipped.len(), 3);
+        assert_eq!(result.skipped[0].0.title, "Small Models");
+        assert_eq!(result.skipped[0].1.score, 1.0);
+        assert_eq!(result.skipped[2].1.score, -2.0);
+        assert_eq!(
+            result.selected[0].1.to_string(),
+            "6.00 (keywords: 6.00 [language model, distill(ation|ing)?])"
+        );
+
+        assert!(Triage::new(
+            InterestProfile::parse("[[keywords]]\npattern = \"(\"\nregex = true").unwrap()
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_llm_triage() {
+        let rating = serde_json::json!({"relevance": 8, "reason": "It distills a language model."});
+        let (llm, mut requests) = crate::llm::tests::stub_ai(&rating.to_string()).await;
+        let mut triage = Triage::new(InterestProfile::parse(PROFILE).unwrap()).unwrap();
+        triage.llm(llm);
+        let score = triage
+            .score(
+                &PaperBuilder::new("Graph Neural Networks")
+                    .abstract_text("We study graphs.")
+                    .build(),
+            )
+            .await;
+        assert_eq!(score.keyword_score, 0.0);
+        assert_eq!(score.llm_rating, Some(8));
+        assert_eq!(score.score, 4.0);
+        assert_eq!(score.reason, "It distills a language model.");
+        assert_eq!(triage.usage().unwrap().total_tokens(), 120);
+
+        let raw = requests.recv().await.unwrap();
+        assert!(raw.contains("Efficient large language models"));
+        assert!(raw.contains(r#""name":"relevance""#));
+
+        // the papers are scored by the keywords once the ratings use up the budget
+        triage.budget(Budget {
+            max_cost: None,
+            max_tokens: Some(120),
+        });
+        let score = triage
+            .score(
+                &PaperBuilder::new("Graph Neural Networks")
+                    .abstract_text("We study graphs.")
+                    .build(),
+            )
+            .await;
+        assert_eq!(score.llm_rating, None);
+        assert!(requests.try_recv().is_err());
+    }
+}
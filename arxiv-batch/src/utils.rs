@@ -64,6 +64,22 @@ pub fn datetime_from_str(date_str: &str) -> DateTime<Utc> {
     }
 }
 
+/// Extract the arXiv ID from an arXiv URL: "http://arxiv.org/abs/1706.03762v7" -> "1706.03762v7".
+pub fn arxiv_id_from_url(url: &str) -> String {
+    let url = url.trim_end_matches('/').trim_end_matches(".pdf");
+    for prefix in ["/abs/", "/pdf/", "/e-print/"] {
+        if let Some((_, id)) = url.split_once(prefix) {
+            return id.to_string();
+        }
+    }
+    return url.to_string();
+}
+
+/// URL of a Notion page: "1234abcd-..." -> "https://www.notion.so/1234abcd...".
+pub fn notion_url(page_id: &str) -> String {
+    return format!("https://www.notion.so/{}", page_id.replace('-', ""));
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -235,6 +251,23 @@ mod test {
         println!("|{}|{:.3}|", s2, score);
     }
 
+    #[test]
+    fn test_arxiv_id_from_url() {
+        assert_eq!(
+            arxiv_id_from_url("http://arxiv.org/abs/1706.03762v7"),
+            "1706.03762v7"
+        );
+        assert_eq!(
+            arxiv_id_from_url("https://arxiv.org/pdf/1706.03762v7.pdf"),
+            "1706.03762v7"
+        );
+        assert_eq!(
+            arxiv_id_from_url("http://arxiv.org/abs/cs/0112017v1"),
+            "cs/0112017v1"
+        );
+        assert_eq!(arxiv_id_from_url("1706.03762"), "1706.03762");
+    }
+
     #[test]
     fn test_datetime_from_str() {
         let date_str = "2024-12-29";